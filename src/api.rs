@@ -49,10 +49,16 @@ use nrfxlib_sys as sys;
 
 /// Trampoline into the BSD library function `bsd_os_application_irq_handler`.
 /// You must call this when an EGU1 interrupt occurs.
+///
+/// With the `async` feature, this also wakes any tasks waiting on an AT
+/// socket future.
 pub fn application_irq_handler() {
 	unsafe {
 		sys::nrf_modem_os_application_irq_handler();
 	}
+	crate::ffi::notify_event();
+	#[cfg(feature = "async")]
+	crate::raw::wake_all();
 }
 
 /// Trampoline into the BSD library function `bsd_os_trace_irq_handler`. You
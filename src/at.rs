@@ -26,13 +26,106 @@ use crate::{raw::*, AtError, Error};
 
 /// Represents a connection to the modem using AT Commands.
 #[derive(Debug)]
-pub struct AtSocket(Socket);
+pub struct AtSocket {
+	socket: Socket,
+	/// Holds data read by `read_urc` which we haven't returned yet.
+	urc_buffer: [u8; AT_BUFFER_LENGTH],
+	/// How many bytes of `urc_buffer` are valid.
+	urc_length: usize,
+	/// How many bytes of `urc_buffer` we have already returned.
+	urc_offset: usize,
+}
+
+/// An iterator over the lines of an AT command response. See
+/// `AtSocket::response_lines`.
+#[derive(Debug)]
+pub struct ResponseLines<'a> {
+	socket: &'a mut AtSocket,
+	buffer: [u8; AT_BUFFER_LENGTH],
+	length: usize,
+	offset: usize,
+	finished: bool,
+}
+
+/// A future which reads the response to an AT command. See
+/// `AtSocket::poll_response_async`.
+#[cfg(feature = "async")]
+#[derive(Debug)]
+pub struct PollResponse<'a, F> {
+	socket: &'a mut AtSocket,
+	callback_function: F,
+}
+
+/// A single line of an AT command response, with any whitespace trimmed.
+pub type ResponseLine = heapless::String<AT_BUFFER_LENGTH>;
+
+/// Builds an AT command from its name and a list of arguments, adding the
+/// `=`, commas and quotes for you.
+///
+/// ```ignore
+/// AtCommand::new("AT+CGDCONT")
+///     .arg(1)
+///     .quoted_arg("IP")
+///     .quoted_arg("internet")
+///     .send(|_| {})?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct AtCommand {
+	buffer: heapless::String<AT_BUFFER_LENGTH>,
+	num_args: usize,
+	overflowed: bool,
+}
+
+/// The error codes the modem can give in a `+CME ERROR` response. See the
+/// Nordic AT command reference for the full list.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CmeErrorCode {
+	/// Phone failure (0)
+	PhoneFailure,
+	/// Operation not allowed (3)
+	OperationNotAllowed,
+	/// Operation not supported (4)
+	OperationNotSupported,
+	/// SIM not inserted (10)
+	SimNotInserted,
+	/// SIM PIN required (11)
+	SimPinRequired,
+	/// SIM PUK required (12)
+	SimPukRequired,
+	/// SIM failure (13)
+	SimFailure,
+	/// SIM busy (14)
+	SimBusy,
+	/// SIM wrong (15)
+	SimWrong,
+	/// Incorrect password (e.g. the wrong PIN) (16)
+	IncorrectPassword,
+	/// SIM PIN2 required (17)
+	SimPin2Required,
+	/// SIM PUK2 required (18)
+	SimPuk2Required,
+	/// No network service (30)
+	NoNetworkService,
+	/// Incorrect parameters (50)
+	IncorrectParameters,
+	/// Not found (Nordic specific) (513)
+	NotFound,
+	/// No access (Nordic specific) (514)
+	NoAccess,
+	/// Memory full (Nordic specific) (515)
+	MemoryFull,
+	/// Not allowed in active state (Nordic specific) (516)
+	NotAllowedInActiveState,
+	/// A code we don't have a name for
+	Unknown(i32),
+}
 
 //******************************************************************************
 // Constants
 //******************************************************************************
 
-// None
+/// The size of the buffer we read AT responses into.
+pub(crate) const AT_BUFFER_LENGTH: usize = 256;
 
 //******************************************************************************
 // Global Variables
@@ -54,12 +147,56 @@ impl AtSocket {
 	/// Create a new AT socket.
 	pub fn new() -> Result<AtSocket, Error> {
 		let skt = Socket::new(SocketDomain::Lte, SocketType::Datagram, SocketProtocol::At)?;
-		Ok(AtSocket(skt))
+		Ok(AtSocket {
+			socket: skt,
+			urc_buffer: [0u8; AT_BUFFER_LENGTH],
+			urc_length: 0,
+			urc_offset: 0,
+		})
 	}
 
 	/// Send an AT command to the modem
 	pub fn send_command(&self, command: &str) -> Result<(), Error> {
-		self.0.write(command.as_bytes()).map(|_count| ())
+		self.socket.write(command.as_bytes()).map(|_count| ())
+	}
+
+	/// Read one unsolicited result code (a notification like `+CEREG: 1`)
+	/// from the modem, if there is one waiting. Doesn't block.
+	///
+	/// The line has any whitespace or newlines trimmed. If the modem sent
+	/// several lines at once, you get them one per call. If it sent more than
+	/// `AT_BUFFER_LENGTH` bytes at once, you get `Error::Truncated`.
+	pub fn read_urc(&mut self) -> Result<Option<ResponseLine>, Error> {
+		loop {
+			if self.urc_offset >= self.urc_length {
+				// Read some more data, dropping the null terminator
+				match self.socket.recv_datagram(&mut self.urc_buffer)? {
+					None => return Ok(None),
+					Some(n) => {
+						self.urc_length = n.saturating_sub(1);
+						self.urc_offset = 0;
+					}
+				}
+				continue;
+			}
+			let remaining = &self.urc_buffer[self.urc_offset..self.urc_length];
+			let line_length = remaining
+				.iter()
+				.position(|b| *b == b'\n')
+				.map(|n| n + 1)
+				.unwrap_or(remaining.len());
+			self.urc_offset += line_length;
+			let line = core::str::from_utf8(&remaining[0..line_length])
+				.map_err(|_| Error::BadDataFormat)?
+				.trim();
+			if line.is_empty() {
+				continue;
+			}
+			let mut result = ResponseLine::new();
+			// Can't fail - the line came out of a buffer this size
+			let _ = result.push_str(line);
+			return Ok(Some(result));
+		}
 	}
 
 	/// Read from the AT socket until we get something that indicates the command has completed.
@@ -68,72 +205,380 @@ impl AtSocket {
 	/// ERROR:xxx`. These are mapped to a Rust `Result` type.
 	///
 	/// Any other data received is deemed to be a command result and passed to the given fn `callback_function`.
-	pub fn poll_response<F>(&mut self, mut callback_function: F) -> Result<(), Error>
+	///
+	/// If the modem sends more than `AT_BUFFER_LENGTH` bytes at once, you get
+	/// `Error::Truncated`.
+	pub fn poll_response<F>(&mut self, callback_function: F) -> Result<(), Error>
 	where
 		F: FnMut(&str),
 	{
-		let result;
-		'outer: loop {
-			let mut buf = [0u8; 256];
-			let length = 'inner: loop {
-				match self.recv(&mut buf)? {
-					None => {
-						// EAGAIN
-					}
-					Some(n) => break 'inner n,
-				};
-			};
-			let s = unsafe { core::str::from_utf8_unchecked(&buf[0..length - 1]) };
-			for line in s.lines() {
-				let line = line.trim();
-				match line {
-					"OK" => {
-						result = Ok(());
-						break 'outer;
+		self.read_response::<AT_BUFFER_LENGTH, F>(None, callback_function)
+	}
+
+	/// Like `poll_response`, but gives up with `Error::Timeout` if the modem
+	/// sends nothing for `timeout_ms` milliseconds.
+	///
+	/// The timeout restarts each time some of the response arrives, so a slow
+	/// but steady response can take longer than `timeout_ms` in total.
+	pub fn poll_response_timeout<F>(
+		&mut self,
+		timeout_ms: u32,
+		callback_function: F,
+	) -> Result<(), Error>
+	where
+		F: FnMut(&str),
+	{
+		self.read_response::<AT_BUFFER_LENGTH, F>(Some(timeout_ms), callback_function)
+	}
+
+	/// Read the response to a command as an iterator of lines, rather than
+	/// with a callback.
+	///
+	/// The iterator ends when the command completes. If it completed with
+	/// `ERROR`, `+CME ERROR:xxx` or `+CMS ERROR:xxx`, the last item is the
+	/// error. So is `Error::Truncated`, if the modem sends more than
+	/// `AT_BUFFER_LENGTH` bytes at once.
+	///
+	/// ```ignore
+	/// let mut skt = AtSocket::new()?;
+	/// skt.send_command("AT+CGMR")?;
+	/// for line in skt.response_lines() {
+	///     let line = line?;
+	///     info!("Got {}", line);
+	/// }
+	/// ```
+	pub fn response_lines(&mut self) -> ResponseLines<'_> {
+		ResponseLines {
+			socket: self,
+			buffer: [0u8; AT_BUFFER_LENGTH],
+			length: 0,
+			offset: 0,
+			finished: false,
+		}
+	}
+
+	/// Send an AT command to the modem and wait for it to complete, without
+	/// blocking the executor. See `poll_response_async`.
+	///
+	/// ```ignore
+	/// let mut skt = AtSocket::new()?;
+	/// skt.send_command_async("AT+CFUN?", |line| info!("Got {}", line))
+	///     .await?;
+	/// ```
+	#[cfg(feature = "async")]
+	pub async fn send_command_async<F>(
+		&mut self,
+		command: &str,
+		callback_function: F,
+	) -> Result<(), Error>
+	where
+		F: FnMut(&str) + Unpin,
+	{
+		self.send_command(command)?;
+		self.poll_response_async(callback_function).await
+	}
+
+	/// Like `poll_response`, but returns a future rather than blocking.
+	///
+	/// The future calls `callback_function` with each line as it arrives, and
+	/// resolves when the command completes. While there is nothing to read,
+	/// the task sleeps until the next call to `application_irq_handler`, so
+	/// make sure that is hooked up to the EGU1 interrupt.
+	#[cfg(feature = "async")]
+	pub fn poll_response_async<F>(&mut self, callback_function: F) -> PollResponse<'_, F>
+	where
+		F: FnMut(&str) + Unpin,
+	{
+		PollResponse {
+			socket: self,
+			callback_function,
+		}
+	}
+}
+
+#[cfg(feature = "async")]
+impl<'a, F> core::future::Future for PollResponse<'a, F>
+where
+	F: FnMut(&str) + Unpin,
+{
+	type Output = Result<(), Error>;
+
+	fn poll(
+		self: core::pin::Pin<&mut Self>,
+		cx: &mut core::task::Context<'_>,
+	) -> core::task::Poll<Self::Output> {
+		let this = self.get_mut();
+		loop {
+			// Register before reading, so we can't miss data which arrives
+			// between the read and going to sleep.
+			crate::raw::register_waker(cx.waker());
+			let mut buf = [0u8; AT_BUFFER_LENGTH];
+			match this.socket.recv_datagram(&mut buf) {
+				Ok(None) => return core::task::Poll::Pending,
+				Ok(Some(length)) => {
+					let s = unsafe {
+						core::str::from_utf8_unchecked(&buf[0..length.saturating_sub(1)])
+					};
+					if let Some(result) = handle_lines(s, &mut this.callback_function) {
+						return core::task::Poll::Ready(result);
 					}
-					"ERROR" => {
-						result = Err(Error::AtError(AtError::Error));
-						break 'outer;
+				}
+				Err(e) => return core::task::Poll::Ready(Err(e)),
+			}
+		}
+	}
+}
+
+impl<'a> Iterator for ResponseLines<'a> {
+	type Item = Result<ResponseLine, Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		while !self.finished {
+			if self.offset >= self.length {
+				// Read some more data, dropping the null terminator
+				match self.socket.recv_datagram(&mut self.buffer) {
+					Ok(None) => {
+						// EAGAIN
 					}
-					err if err.starts_with("+CME ERROR:") => {
-						let num_str = &err[11..];
-						let value = num_str.trim().parse().unwrap_or(-1);
-						result = Err(Error::AtError(AtError::CmeError(value)));
-						break 'outer;
+					Ok(Some(n)) => {
+						self.length = n.saturating_sub(1);
+						self.offset = 0;
 					}
-					err if err.starts_with("+CMS ERROR:") => {
-						let num_str = &err[11..];
-						let value = num_str.trim().parse().unwrap_or(-1);
-						result = Err(Error::AtError(AtError::CmsError(value)));
-						break 'outer;
+					Err(e) => {
+						self.finished = true;
+						return Some(Err(e));
 					}
-					data => {
-						callback_function(data);
+				}
+				continue;
+			}
+			let remaining = &self.buffer[self.offset..self.length];
+			let line_length = remaining
+				.iter()
+				.position(|b| *b == b'\n')
+				.map(|n| n + 1)
+				.unwrap_or(remaining.len());
+			self.offset += line_length;
+			let line = unsafe { core::str::from_utf8_unchecked(&remaining[0..line_length]) };
+			let line = line.trim();
+			if line.is_empty() {
+				continue;
+			}
+			match parse_final_response(line) {
+				Some(Ok(())) => {
+					self.finished = true;
+				}
+				Some(Err(e)) => {
+					self.finished = true;
+					return Some(Err(e));
+				}
+				None => {
+					let mut result = ResponseLine::new();
+					// Cannot fail - the line came from a buffer of the same size
+					let _ = result.push_str(line);
+					return Some(Ok(result));
+				}
+			}
+		}
+		None
+	}
+}
+
+impl AtCommand {
+	/// Start a new AT command, e.g. `AT+CGDCONT`.
+	pub fn new(name: &str) -> AtCommand {
+		let mut command = AtCommand {
+			buffer: heapless::String::new(),
+			num_args: 0,
+			overflowed: false,
+		};
+		command.push_str(name);
+		command
+	}
+
+	/// Add an argument, which is formatted as-is (e.g. a number).
+	pub fn arg<T>(mut self, value: T) -> AtCommand
+	where
+		T: core::fmt::Display,
+	{
+		use core::fmt::Write;
+		self.push_separator();
+		if write!(self.buffer, "{}", value).is_err() {
+			self.overflowed = true;
+		}
+		self
+	}
+
+	/// Add an argument which is a string, and so must be quoted.
+	///
+	/// Any `"` or `\` characters in `value` are escaped as `\22` and `\5C`
+	/// respectively, as per 3GPP TS 27.007.
+	pub fn quoted_arg(mut self, value: &str) -> AtCommand {
+		self.push_separator();
+		self.push_str("\"");
+		for c in value.chars() {
+			match c {
+				'"' => self.push_str("\\22"),
+				'\\' => self.push_str("\\5C"),
+				c => {
+					if self.buffer.push(c).is_err() {
+						self.overflowed = true;
 					}
 				}
 			}
 		}
-		result
+		self.push_str("\"");
+		self
+	}
+
+	/// Add an empty argument, to skip over an optional parameter.
+	pub fn empty_arg(mut self) -> AtCommand {
+		self.push_separator();
+		self
+	}
+
+	/// Get the finished command. Returns `Error::WriteError` if the command
+	/// was too long to fit in our buffer.
+	pub fn as_str(&self) -> Result<&str, Error> {
+		if self.overflowed {
+			Err(Error::WriteError)
+		} else {
+			Ok(self.buffer.as_str())
+		}
+	}
+
+	/// Send this command to the modem, and call the given closure with any
+	/// indications received. See `send_at_command`.
+	pub fn send<F>(&self, function: F) -> Result<(), Error>
+	where
+		F: FnMut(&str),
+	{
+		send_at_command(self.as_str()?, function)
+	}
+
+	/// Like `send`, but reads the response into a buffer of `N` bytes. See
+	/// `send_long_at_command`.
+	pub(crate) fn send_long<const N: usize, F>(&self, function: F) -> Result<(), Error>
+	where
+		F: FnMut(&str),
+	{
+		send_long_at_command::<N, F>(self.as_str()?, function)
+	}
+
+	/// Add the `=` before the first argument, or the `,` before later ones.
+	fn push_separator(&mut self) {
+		if self.num_args == 0 {
+			self.push_str("=");
+		} else {
+			self.push_str(",");
+		}
+		self.num_args += 1;
+	}
+
+	/// Add some text to the command, noting if we ran out of space.
+	fn push_str(&mut self, s: &str) {
+		if self.buffer.push_str(s).is_err() {
+			self.overflowed = true;
+		}
 	}
 }
 
 impl Pollable for AtSocket {
 	/// Get the underlying socket ID for this socket.
 	fn get_fd(&self) -> i32 {
-		self.0.fd
+		self.socket.fd
 	}
 }
 
 impl core::ops::DerefMut for AtSocket {
 	fn deref_mut(&mut self) -> &mut Socket {
-		&mut self.0
+		&mut self.socket
 	}
 }
 
 impl core::ops::Deref for AtSocket {
 	type Target = Socket;
 	fn deref(&self) -> &Socket {
-		&self.0
+		&self.socket
+	}
+}
+
+impl CmeErrorCode {
+	/// Convert the number from a `+CME ERROR` response into a
+	/// `CmeErrorCode`. Codes we don't recognise become `Unknown`.
+	pub fn from_i32(value: i32) -> CmeErrorCode {
+		match value {
+			0 => CmeErrorCode::PhoneFailure,
+			3 => CmeErrorCode::OperationNotAllowed,
+			4 => CmeErrorCode::OperationNotSupported,
+			10 => CmeErrorCode::SimNotInserted,
+			11 => CmeErrorCode::SimPinRequired,
+			12 => CmeErrorCode::SimPukRequired,
+			13 => CmeErrorCode::SimFailure,
+			14 => CmeErrorCode::SimBusy,
+			15 => CmeErrorCode::SimWrong,
+			16 => CmeErrorCode::IncorrectPassword,
+			17 => CmeErrorCode::SimPin2Required,
+			18 => CmeErrorCode::SimPuk2Required,
+			30 => CmeErrorCode::NoNetworkService,
+			50 => CmeErrorCode::IncorrectParameters,
+			513 => CmeErrorCode::NotFound,
+			514 => CmeErrorCode::NoAccess,
+			515 => CmeErrorCode::MemoryFull,
+			516 => CmeErrorCode::NotAllowedInActiveState,
+			value => CmeErrorCode::Unknown(value),
+		}
+	}
+
+	/// Get the number the modem uses for this error.
+	pub fn as_i32(self) -> i32 {
+		match self {
+			CmeErrorCode::PhoneFailure => 0,
+			CmeErrorCode::OperationNotAllowed => 3,
+			CmeErrorCode::OperationNotSupported => 4,
+			CmeErrorCode::SimNotInserted => 10,
+			CmeErrorCode::SimPinRequired => 11,
+			CmeErrorCode::SimPukRequired => 12,
+			CmeErrorCode::SimFailure => 13,
+			CmeErrorCode::SimBusy => 14,
+			CmeErrorCode::SimWrong => 15,
+			CmeErrorCode::IncorrectPassword => 16,
+			CmeErrorCode::SimPin2Required => 17,
+			CmeErrorCode::SimPuk2Required => 18,
+			CmeErrorCode::NoNetworkService => 30,
+			CmeErrorCode::IncorrectParameters => 50,
+			CmeErrorCode::NotFound => 513,
+			CmeErrorCode::NoAccess => 514,
+			CmeErrorCode::MemoryFull => 515,
+			CmeErrorCode::NotAllowedInActiveState => 516,
+			CmeErrorCode::Unknown(value) => value,
+		}
+	}
+}
+
+impl core::fmt::Display for CmeErrorCode {
+	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+		let description = match self {
+			CmeErrorCode::PhoneFailure => "phone failure",
+			CmeErrorCode::OperationNotAllowed => "operation not allowed",
+			CmeErrorCode::OperationNotSupported => "operation not supported",
+			CmeErrorCode::SimNotInserted => "SIM not inserted",
+			CmeErrorCode::SimPinRequired => "SIM PIN required",
+			CmeErrorCode::SimPukRequired => "SIM PUK required",
+			CmeErrorCode::SimFailure => "SIM failure",
+			CmeErrorCode::SimBusy => "SIM busy",
+			CmeErrorCode::SimWrong => "SIM wrong",
+			CmeErrorCode::IncorrectPassword => "incorrect password",
+			CmeErrorCode::SimPin2Required => "SIM PIN2 required",
+			CmeErrorCode::SimPuk2Required => "SIM PUK2 required",
+			CmeErrorCode::NoNetworkService => "no network service",
+			CmeErrorCode::IncorrectParameters => "incorrect parameters",
+			CmeErrorCode::NotFound => "not found",
+			CmeErrorCode::NoAccess => "no access",
+			CmeErrorCode::MemoryFull => "memory full",
+			CmeErrorCode::NotAllowedInActiveState => "not allowed in active state",
+			CmeErrorCode::Unknown(_) => "unknown error",
+		};
+		write!(f, "{} ({})", description, self.as_i32())
 	}
 }
 
@@ -151,11 +596,230 @@ where
 	skt.poll_response(function)
 }
 
+/// Like `send_at_command`, but reads the response into a buffer of `N`
+/// bytes rather than `AT_BUFFER_LENGTH`. For the few commands whose response
+/// can be longer than that.
+pub(crate) fn send_long_at_command<const N: usize, F>(
+	command: &str,
+	function: F,
+) -> Result<(), Error>
+where
+	F: FnMut(&str),
+{
+	let mut skt = AtSocket::new()?;
+	skt.send_command(command)?;
+	skt.read_response::<N, F>(None, function)
+}
+
+/// Sends a list of AT commands to the modem, one after the other, on a single
+/// AT socket. Any indications received are ignored.
+///
+/// Stops at the first command which fails. If the modem rejects a command,
+/// you get `Error::AtCommandFailed` with the index of that command in
+/// `commands`.
+pub fn send_at_commands(commands: &[&str]) -> Result<(), Error> {
+	let mut skt = AtSocket::new()?;
+	for (idx, command) in commands.iter().enumerate() {
+		skt.send_command(command)?;
+		skt.poll_response(|_| {}).map_err(|e| match e {
+			Error::AtError(at_error) => Error::AtCommandFailed(idx, at_error),
+			e => e,
+		})?;
+	}
+	Ok(())
+}
+
 //******************************************************************************
 // Private Functions and Impl on Private Types
 //******************************************************************************
 
-// None
+impl AtSocket {
+	/// Does the work for `poll_response` and `poll_response_timeout`, reading
+	/// the response in pieces of up to `N` bytes. With no timeout, we wait
+	/// forever.
+	///
+	/// A piece which doesn't fit in `N` bytes gives `Error::Truncated`, as we
+	/// would otherwise lose the end of the response, and with it the line
+	/// saying the command has completed.
+	pub(crate) fn read_response<const N: usize, F>(
+		&mut self,
+		timeout_ms: Option<u32>,
+		mut callback_function: F,
+	) -> Result<(), Error>
+	where
+		F: FnMut(&str),
+	{
+		loop {
+			let mut buf = [0u8; N];
+			if let Some(timeout_ms) = timeout_ms {
+				self.wait_readable(timeout_ms)?;
+			}
+			let length = loop {
+				match self.recv_datagram(&mut buf)? {
+					None => {
+						// EAGAIN
+					}
+					Some(n) => break n,
+				};
+			};
+			let s = unsafe { core::str::from_utf8_unchecked(&buf[0..length - 1]) };
+			if let Some(result) = handle_lines(s, &mut callback_function) {
+				return result;
+			}
+		}
+	}
+
+	/// Wait up to `timeout_ms` milliseconds for the socket to become readable.
+	/// `poll` can only wait for `i32::MAX` milliseconds at a time, so longer
+	/// timeouts take several polls.
+	fn wait_readable(&self, timeout_ms: u32) -> Result<(), Error> {
+		let mut remaining = timeout_ms;
+		loop {
+			let chunk = remaining.min(i32::MAX as u32);
+			let result = self.poll(
+				crate::PollFlags::Read,
+				core::time::Duration::from_millis(u64::from(chunk)),
+			)?;
+			if result != crate::PollResult::default() {
+				return Ok(());
+			}
+			remaining -= chunk;
+			if remaining == 0 {
+				return Err(Error::Timeout);
+			}
+		}
+	}
+}
+
+/// Pass each line of `s` to `callback_function`, until we find one which
+/// indicates the command has completed. Returns the result of the command,
+/// or `None` if it hasn't completed yet.
+fn handle_lines<F>(s: &str, callback_function: &mut F) -> Option<Result<(), Error>>
+where
+	F: FnMut(&str),
+{
+	for line in s.lines() {
+		let line = line.trim();
+		match parse_final_response(line) {
+			Some(r) => return Some(r),
+			None => callback_function(line),
+		}
+	}
+	None
+}
+
+/// Check if this line of an AT response indicates the command has completed.
+///
+/// Commands are completed by `OK`, `ERROR`, `+CME ERROR:xxx` or `+CMS
+/// ERROR:xxx`. These are mapped to a Rust `Result` type. Any other line gives
+/// `None`.
+fn parse_final_response(line: &str) -> Option<Result<(), Error>> {
+	match line {
+		"OK" => Some(Ok(())),
+		"ERROR" => Some(Err(Error::AtError(AtError::Error))),
+		err if err.starts_with("+CME ERROR:") => {
+			let num_str = &err[11..];
+			let value = num_str.trim().parse().unwrap_or(-1);
+			Some(Err(Error::AtError(AtError::CmeError(
+				CmeErrorCode::from_i32(value),
+			))))
+		}
+		err if err.starts_with("+CMS ERROR:") => {
+			let num_str = &err[11..];
+			let value = num_str.trim().parse().unwrap_or(-1);
+			Some(Err(Error::AtError(AtError::CmsError(value))))
+		}
+		_ => None,
+	}
+}
+
+//******************************************************************************
+// Tests
+//******************************************************************************
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::backend::mock;
+	use nrfxlib_sys as sys;
+
+	#[test]
+	fn command_lines_reach_the_callback() {
+		mock::run(|modem| {
+			modem.reply("AT+CGMR", "mfw_nrf9160_1.2.3\r\nOK\r\n");
+			let mut lines = heapless::Vec::<ResponseLine, 4>::new();
+			send_at_command("AT+CGMR", |line| {
+				let mut s = ResponseLine::new();
+				s.push_str(line).unwrap();
+				lines.push(s).unwrap();
+			})
+			.unwrap();
+			assert_eq!(lines.len(), 1);
+			assert_eq!(lines[0].as_str(), "mfw_nrf9160_1.2.3");
+		});
+	}
+
+	#[test]
+	fn cme_error_is_decoded() {
+		mock::run(|modem| {
+			modem.reply("AT+CPIN?", "+CME ERROR: 10\r\n");
+			assert_eq!(
+				send_at_command("AT+CPIN?", |_| {}),
+				Err(Error::AtError(AtError::CmeError(CmeErrorCode::from_i32(
+					10
+				))))
+			);
+		});
+	}
+
+	#[test]
+	fn failed_command_is_numbered() {
+		mock::run(|modem| {
+			modem.reply("AT+CFUN=4", "OK\r\n");
+			assert_eq!(
+				send_at_commands(&["AT+CFUN=4", "AT+BOGUS"]),
+				Err(Error::AtCommandFailed(1, AtError::Error))
+			);
+			assert_eq!(modem.commands(), ["AT+CFUN=4", "AT+BOGUS"]);
+		});
+	}
+
+	#[test]
+	fn urcs_are_split_into_lines() {
+		mock::run(|modem| {
+			let mut skt = AtSocket::new().unwrap();
+			modem.push(sys::NRF_PROTO_AT, b"+CEREG: 2\r\n+CEREG: 1\r\n");
+			assert_eq!(skt.read_urc().unwrap().unwrap().as_str(), "+CEREG: 2");
+			assert_eq!(skt.read_urc().unwrap().unwrap().as_str(), "+CEREG: 1");
+			assert_eq!(skt.read_urc().unwrap(), None);
+		});
+	}
+
+	#[test]
+	fn overlong_urc_is_an_error() {
+		mock::run(|modem| {
+			let mut skt = AtSocket::new().unwrap();
+			modem.push(sys::NRF_PROTO_AT, &[b'x'; AT_BUFFER_LENGTH + 1]);
+			modem.push(sys::NRF_PROTO_AT, b"+CEREG: 1\r\n");
+			assert!(matches!(skt.read_urc(), Err(Error::Truncated(_, _))));
+			assert_eq!(skt.read_urc().unwrap().unwrap().as_str(), "+CEREG: 1");
+		});
+	}
+
+	#[test]
+	fn overlong_response_ends_the_lines() {
+		mock::run(|modem| {
+			let mut response = [b'x'; AT_BUFFER_LENGTH + 8];
+			response[AT_BUFFER_LENGTH + 4..].copy_from_slice(b"OK\r\n");
+			modem.reply("AT+CGMR", core::str::from_utf8(&response).unwrap());
+			let mut skt = AtSocket::new().unwrap();
+			skt.send_command("AT+CGMR").unwrap();
+			let mut lines = skt.response_lines();
+			assert!(matches!(lines.next(), Some(Err(Error::Truncated(_, _)))));
+			assert!(lines.next().is_none());
+		});
+	}
+}
 
 //******************************************************************************
 // End of File
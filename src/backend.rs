@@ -0,0 +1,845 @@
+//! # Socket back-end for nrfxlib
+//!
+//! The basic socket calls (create, close, connect, bind, listen, accept, send,
+//! receive, options, poll and DNS look-ups) go through the functions in this
+//! module. Normally they call straight into the Nordic library. So does the
+//! `wfe` which `nrf_modem_os_timedwait` sleeps with. With the `mock-backend`
+//! feature enabled, you can install your own `Backend` with `set_backend`,
+//! and it will receive those calls instead. This lets you test code built on
+//! this crate without a modem.
+//!
+//! Copyright (c) 42 Technology Ltd 2019
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+// On a host there is no Nordic library, so without a back-end the arguments
+// to these functions go nowhere.
+#![cfg_attr(not(target_arch = "arm"), allow(unused_variables))]
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+use core::mem::MaybeUninit;
+use nrfxlib_sys as sys;
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// Something which can stand in for the socket calls in the Nordic library.
+///
+/// Each function takes the same arguments and returns the same values as the
+/// `nrf_*` function it is named after. To report an error, return -1 and
+/// call `set_errno` first.
+#[cfg(any(test, feature = "mock-backend"))]
+pub trait Backend: Sync {
+	/// Replaces `nrf_socket`
+	fn socket(&self, domain: i32, skt_type: i32, protocol: i32) -> i32;
+	/// Replaces `nrf_close`
+	fn close(&self, fd: i32) -> i32;
+	/// Replaces `nrf_connect`
+	fn connect(&self, fd: i32, addr: &sys::nrf_sockaddr_in) -> i32;
+	/// Replaces `nrf_connect` with an IPv6 address. By default this fails
+	/// with `NRF_EAFNOSUPPORT`.
+	fn connect6(&self, _fd: i32, _addr: &sys::nrf_sockaddr_in6) -> i32 {
+		set_errno(sys::NRF_EAFNOSUPPORT as i32);
+		-1
+	}
+	/// Replaces `nrf_bind`
+	fn bind(&self, fd: i32, addr: &sys::nrf_sockaddr_in) -> i32;
+	/// Replaces `nrf_listen`
+	fn listen(&self, fd: i32, backlog: i32) -> i32;
+	/// Replaces `nrf_accept`. Fills in `addr` with the peer's address.
+	fn accept(&self, fd: i32, addr: &mut sys::nrf_sockaddr_in) -> i32;
+	/// Replaces `nrf_send`
+	fn send(&self, fd: i32, buf: &[u8], flags: i32) -> sys::ssize_t;
+	/// Replaces `nrf_sendto`
+	fn send_to(&self, fd: i32, buf: &[u8], flags: i32, addr: &sys::nrf_sockaddr_in)
+		-> sys::ssize_t;
+	/// Replaces `nrf_write`
+	fn write(&self, fd: i32, buf: &[u8]) -> sys::ssize_t;
+	/// Replaces `nrf_recv`. Returns the number of bytes written into the
+	/// start of `buf`.
+	fn recv(&self, fd: i32, buf: &mut [MaybeUninit<u8>], flags: i32) -> sys::ssize_t;
+	/// Replaces `nrf_recvfrom`. Returns the number of bytes written into the
+	/// start of `buf`, and fills in `addr` with the sender's address.
+	fn recv_from(
+		&self,
+		fd: i32,
+		buf: &mut [MaybeUninit<u8>],
+		flags: i32,
+		addr: &mut sys::nrf_sockaddr_in,
+	) -> sys::ssize_t;
+	/// Replaces `nrf_setsockopt`. `value` points at `length` bytes.
+	fn set_option(
+		&self,
+		fd: i32,
+		level: i32,
+		name: i32,
+		value: *const sys::ctypes::c_void,
+		length: u32,
+	) -> i32;
+	/// Replaces `nrf_getsockopt`. `value` points at `length` bytes, and
+	/// `length` should be updated with the number of bytes written.
+	fn get_option(
+		&self,
+		fd: i32,
+		level: i32,
+		name: i32,
+		value: *mut sys::ctypes::c_void,
+		length: &mut u32,
+	) -> i32;
+	/// Replaces `nrf_poll`
+	fn poll(&self, fds: &mut [sys::nrf_pollfd], timeout_ms: i32) -> i32;
+	/// Replaces `nrf_sendto` on a GNSS socket, where the Nordic library takes
+	/// the type of the assistance data in place of the destination address.
+	/// By default this fails with `NRF_EOPNOTSUPP`.
+	fn send_agps(
+		&self,
+		_fd: i32,
+		_buf: &[u8],
+		_data_type: sys::nrf_gnss_agps_data_type_t,
+	) -> sys::ssize_t {
+		set_errno(sys::NRF_EOPNOTSUPP as i32);
+		-1
+	}
+	/// Replaces `nrf_getaddrinfo`. `hostname` has no null terminator. On
+	/// success, point `result` at a list of records and return 0. By default
+	/// this fails with `NRF_EOPNOTSUPP`.
+	fn getaddrinfo(
+		&self,
+		_hostname: &str,
+		_hints: &sys::nrf_addrinfo,
+		_result: &mut *mut sys::nrf_addrinfo,
+	) -> i32 {
+		set_errno(sys::NRF_EOPNOTSUPP as i32);
+		-1
+	}
+	/// Replaces `nrf_freeaddrinfo`. Called with each list your `getaddrinfo`
+	/// returned, once we have finished with it. By default this does nothing.
+	fn freeaddrinfo(&self, _result: *mut sys::nrf_addrinfo) {}
+	/// Replaces the `wfe` which `nrf_modem_os_timedwait` sleeps with. A test
+	/// can call `notify_event` from here to play the part of the modem.
+	fn wait_for_event(&self) {
+		cortex_m::asm::wfe();
+	}
+}
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+/// The back-end installed with `set_backend`, if any.
+#[cfg(any(test, feature = "mock-backend"))]
+static BACKEND: cortex_m::interrupt::Mutex<core::cell::Cell<Option<&'static dyn Backend>>> =
+	cortex_m::interrupt::Mutex::new(core::cell::Cell::new(None));
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+/// Call into the Nordic library.
+#[cfg(target_arch = "arm")]
+macro_rules! nordic {
+	($call:expr) => {
+		unsafe { $call }
+	};
+}
+
+/// The Nordic library only exists for the nRF9160. On a host, which we only
+/// build for to run tests, a back-end must be installed.
+#[cfg(not(target_arch = "arm"))]
+macro_rules! nordic {
+	($call:expr) => {
+		panic!("no socket back-end installed")
+	};
+}
+
+/// Hand the call to the installed back-end, if there is one.
+macro_rules! try_backend {
+	($method:ident($($arg:expr),*)) => {
+		#[cfg(any(test, feature = "mock-backend"))]
+		{
+			if let Some(backend) = installed() {
+				return backend.$method($($arg),*);
+			}
+		}
+	};
+}
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+/// Send all future socket calls to `backend`, instead of the Nordic library.
+/// Pass `None` to go back to the Nordic library.
+#[cfg(any(test, feature = "mock-backend"))]
+pub fn set_backend(backend: Option<&'static dyn Backend>) {
+	critical_section(|cs| BACKEND.borrow(cs).set(backend));
+}
+
+/// Set the value returned by `get_last_error`, as the Nordic library does
+/// when a call fails.
+#[cfg(any(test, feature = "mock-backend"))]
+pub fn set_errno(errno: i32) {
+	crate::ffi::nrf_modem_os_errno_set(errno);
+}
+
+/// Tell a waiting `nrf_modem_os_timedwait` that the modem has something for
+/// the library, as `application_irq_handler` does.
+#[cfg(feature = "mock-backend")]
+pub fn notify_event() {
+	crate::ffi::notify_event();
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+#[cfg(any(test, feature = "mock-backend"))]
+fn installed() -> Option<&'static dyn Backend> {
+	critical_section(|cs| BACKEND.borrow(cs).get())
+}
+
+/// Run `f` with interrupts disabled. On a host there are no interrupts to
+/// disable - we only build for one to run tests against a mock back-end - so
+/// there we just run `f`.
+#[cfg(any(test, feature = "mock-backend"))]
+fn critical_section<F, R>(f: F) -> R
+where
+	F: FnOnce(&cortex_m::interrupt::CriticalSection) -> R,
+{
+	#[cfg(target_arch = "arm")]
+	{
+		cortex_m::interrupt::free(f)
+	}
+	#[cfg(not(target_arch = "arm"))]
+	{
+		// NOTE(unsafe) - there is nothing to be interrupted by
+		f(unsafe { &cortex_m::interrupt::CriticalSection::new() })
+	}
+}
+
+pub(crate) fn socket(domain: i32, skt_type: i32, protocol: i32) -> i32 {
+	try_backend!(socket(domain, skt_type, protocol));
+	nordic!(sys::nrf_socket(domain, skt_type, protocol))
+}
+
+pub(crate) fn close(fd: i32) -> i32 {
+	try_backend!(close(fd));
+	nordic!(sys::nrf_close(fd))
+}
+
+pub(crate) fn connect(fd: i32, addr: &sys::nrf_sockaddr_in) -> i32 {
+	try_backend!(connect(fd, addr));
+	nordic!(sys::nrf_connect(
+		fd,
+		addr as *const sys::nrf_sockaddr_in as *const _,
+		u32::from(addr.sin_len),
+	))
+}
+
+pub(crate) fn connect6(fd: i32, addr: &sys::nrf_sockaddr_in6) -> i32 {
+	try_backend!(connect6(fd, addr));
+	nordic!(sys::nrf_connect(
+		fd,
+		addr as *const sys::nrf_sockaddr_in6 as *const _,
+		u32::from(addr.sin6_len),
+	))
+}
+
+pub(crate) fn bind(fd: i32, addr: &sys::nrf_sockaddr_in) -> i32 {
+	try_backend!(bind(fd, addr));
+	nordic!(sys::nrf_bind(
+		fd,
+		addr as *const sys::nrf_sockaddr_in as *const _,
+		u32::from(addr.sin_len),
+	))
+}
+
+pub(crate) fn listen(fd: i32, backlog: i32) -> i32 {
+	try_backend!(listen(fd, backlog));
+	nordic!(sys::nrf_listen(fd, backlog))
+}
+
+pub(crate) fn accept(fd: i32, addr: &mut sys::nrf_sockaddr_in) -> i32 {
+	try_backend!(accept(fd, addr));
+	nordic!(sys::nrf_accept(
+		fd,
+		addr as *mut sys::nrf_sockaddr_in as *mut _,
+		// The library tells us how long the address it wrote is, but we
+		// know already
+		&mut u32::from(addr.sin_len),
+	))
+}
+
+pub(crate) fn send(fd: i32, buf: &[u8], flags: i32) -> sys::ssize_t {
+	try_backend!(send(fd, buf, flags));
+	nordic!(sys::nrf_send(
+		fd,
+		buf.as_ptr() as *const _,
+		buf.len() as u32,
+		flags
+	))
+}
+
+pub(crate) fn send_to(
+	fd: i32,
+	buf: &[u8],
+	flags: i32,
+	addr: &sys::nrf_sockaddr_in,
+) -> sys::ssize_t {
+	try_backend!(send_to(fd, buf, flags, addr));
+	nordic!(sys::nrf_sendto(
+		fd,
+		buf.as_ptr() as *const _,
+		buf.len() as u32,
+		flags,
+		addr as *const sys::nrf_sockaddr_in as *const _,
+		u32::from(addr.sin_len),
+	))
+}
+
+pub(crate) fn write(fd: i32, buf: &[u8]) -> sys::ssize_t {
+	try_backend!(write(fd, buf));
+	nordic!(sys::nrf_write(
+		fd,
+		buf.as_ptr() as *const _,
+		buf.len() as u32
+	))
+}
+
+pub(crate) fn recv(fd: i32, buf: &mut [MaybeUninit<u8>], flags: i32) -> sys::ssize_t {
+	try_backend!(recv(fd, buf, flags));
+	nordic!(sys::nrf_recv(
+		fd,
+		buf.as_mut_ptr() as *mut _,
+		buf.len() as u32,
+		flags
+	))
+}
+
+pub(crate) fn recv_from(
+	fd: i32,
+	buf: &mut [MaybeUninit<u8>],
+	flags: i32,
+	addr: &mut sys::nrf_sockaddr_in,
+) -> sys::ssize_t {
+	try_backend!(recv_from(fd, buf, flags, addr));
+	nordic!(sys::nrf_recvfrom(
+		fd,
+		buf.as_mut_ptr() as *mut _,
+		buf.len() as u32,
+		flags,
+		addr as *mut sys::nrf_sockaddr_in as *mut _,
+		// The library tells us how long the address it wrote is, but we
+		// know already
+		&mut u32::from(addr.sin_len),
+	))
+}
+
+pub(crate) fn set_option(
+	fd: i32,
+	level: i32,
+	name: i32,
+	value: *const sys::ctypes::c_void,
+	length: u32,
+) -> i32 {
+	try_backend!(set_option(fd, level, name, value, length));
+	nordic!(sys::nrf_setsockopt(fd, level, name, value, length))
+}
+
+pub(crate) fn get_option(
+	fd: i32,
+	level: i32,
+	name: i32,
+	value: *mut sys::ctypes::c_void,
+	length: &mut u32,
+) -> i32 {
+	try_backend!(get_option(fd, level, name, value, length));
+	nordic!(sys::nrf_getsockopt(
+		fd,
+		level,
+		name,
+		value,
+		length as *mut u32
+	))
+}
+
+/// `hostname` must be null terminated.
+pub(crate) fn getaddrinfo(
+	hostname: &str,
+	hints: &sys::nrf_addrinfo,
+	result: &mut *mut sys::nrf_addrinfo,
+) -> i32 {
+	try_backend!(getaddrinfo(hostname.trim_end_matches('\0'), hints, result));
+	nordic!(sys::nrf_getaddrinfo(
+		hostname.as_ptr(),
+		core::ptr::null(),
+		hints,
+		result
+	))
+}
+
+pub(crate) fn freeaddrinfo(result: *mut sys::nrf_addrinfo) {
+	try_backend!(freeaddrinfo(result));
+	nordic!(sys::nrf_freeaddrinfo(result))
+}
+
+pub(crate) fn send_agps(
+	fd: i32,
+	buf: &[u8],
+	data_type: sys::nrf_gnss_agps_data_type_t,
+) -> sys::ssize_t {
+	try_backend!(send_agps(fd, buf, data_type));
+	nordic!(sys::nrf_sendto(
+		fd,
+		buf.as_ptr() as *const _,
+		buf.len() as u32,
+		0,
+		&data_type as *const sys::nrf_gnss_agps_data_type_t as *const _,
+		core::mem::size_of::<sys::nrf_gnss_agps_data_type_t>() as u32,
+	))
+}
+
+pub(crate) fn wait_for_event() {
+	try_backend!(wait_for_event());
+	cortex_m::asm::wfe();
+}
+
+pub(crate) fn poll(fds: &mut [sys::nrf_pollfd], timeout_ms: i32) -> i32 {
+	try_backend!(poll(fds, timeout_ms));
+	nordic!(sys::nrf_poll(
+		fds.as_mut_ptr(),
+		fds.len() as u32,
+		timeout_ms
+	))
+}
+
+//******************************************************************************
+// Tests
+//******************************************************************************
+
+/// A pretend modem, for testing the code above the socket calls on a host.
+///
+/// AT commands written to an AT socket get the reply given to `reply` for
+/// that command. Anything else to be read (notifications, GNSS frames) is
+/// queued with `push`.
+#[cfg(test)]
+pub(crate) mod mock {
+	extern crate std;
+
+	use super::*;
+	use std::collections::VecDeque;
+	use std::string::String;
+	use std::sync::Mutex;
+	use std::vec::Vec;
+
+	/// How many reads in a row can find nothing waiting before we decide the
+	/// test has hung.
+	const MAX_EMPTY_READS: u32 = 10_000;
+
+	/// There is only one back-end, so tests which use it take turns.
+	static LOCK: Mutex<()> = Mutex::new(());
+
+	pub(crate) struct MockModem {
+		state: Mutex<State>,
+	}
+
+	#[derive(Default)]
+	struct State {
+		next_fd: i32,
+		/// The protocol each open socket was created with
+		sockets: Vec<(i32, i32)>,
+		/// The reply to give each AT command
+		replies: Vec<(String, String)>,
+		/// Data waiting to be read, by protocol
+		inbox: Vec<(i32, VecDeque<Vec<u8>>)>,
+		/// Every AT command written
+		commands: Vec<String>,
+		/// The AT command being written. Like the modem, we answer it once
+		/// the caller stops writing and looks for the response, so a command
+		/// can be written in pieces (e.g. with `write!`).
+		partial_command: String,
+		empty_reads: u32,
+		/// How many times `wait_for_event` has been called
+		waits: u32,
+		/// Play the part of the modem on this call to `wait_for_event`
+		wake_after: Option<u32>,
+		/// What every hostname resolves to
+		addresses: Vec<[u8; 4]>,
+		/// How many more connection attempts to refuse
+		refusals: u32,
+		/// The socket used for each connection attempt
+		connections: Vec<i32>,
+		/// The socket, level and name of every option set
+		options: Vec<(i32, i32, i32)>,
+		/// What reading each option (by level and name) gives
+		option_values: Vec<((i32, i32), Vec<u8>)>,
+		/// Make writes fail with this errno
+		write_errno: Option<i32>,
+		/// Every socket closed, in order
+		closed: Vec<i32>,
+	}
+
+	/// Install a fresh `MockModem` for the duration of `f`.
+	pub(crate) fn run<F>(f: F)
+	where
+		F: FnOnce(&'static MockModem),
+	{
+		let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+		let modem: &'static MockModem = std::boxed::Box::leak(std::boxed::Box::new(MockModem {
+			state: Mutex::new(State::default()),
+		}));
+		set_backend(Some(modem));
+		f(modem);
+		set_backend(None);
+	}
+
+	impl MockModem {
+		/// Reply to `command` with `response` (usually ending in `OK`). A
+		/// `\0` in `response` splits it into separate reads, so you can send
+		/// a notification after the `OK`.
+		pub(crate) fn reply(&self, command: &str, response: &str) {
+			self.state()
+				.replies
+				.push((String::from(command), String::from(response)));
+		}
+
+		/// Queue `data` to be read from the next socket of type `protocol`.
+		/// AT data gets a null terminator, as the modem sends.
+		pub(crate) fn push(&self, protocol: u32, data: &[u8]) {
+			let mut data = data.to_vec();
+			if protocol == sys::NRF_PROTO_AT {
+				data.push(0);
+			}
+			self.state().inbox(protocol as i32).push_back(data);
+		}
+
+		/// Every AT command written so far.
+		pub(crate) fn commands(&self) -> Vec<String> {
+			let mut state = self.state();
+			state.finish_command();
+			state.commands.clone()
+		}
+
+		/// Signal a modem event (with `notify_event`) on the `waits`th call
+		/// to `wait_for_event`.
+		pub(crate) fn wake_after(&self, waits: u32) {
+			self.state().wake_after = Some(waits);
+		}
+
+		/// How many times `wait_for_event` has been called.
+		pub(crate) fn waits(&self) -> u32 {
+			self.state().waits
+		}
+
+		/// Make every hostname resolve to `addresses`.
+		pub(crate) fn resolve(&self, addresses: &[[u8; 4]]) {
+			self.state().addresses = addresses.to_vec();
+		}
+
+		/// Make the next `count` connection attempts time out.
+		pub(crate) fn refuse(&self, count: u32) {
+			self.state().refusals = count;
+		}
+
+		/// The socket used for each connection attempt so far.
+		pub(crate) fn connections(&self) -> Vec<i32> {
+			self.state().connections.clone()
+		}
+
+		/// Make reading the option `level`, `name` give `value`.
+		pub(crate) fn option_value(&self, level: u32, name: u32, value: &[u8]) {
+			self.state()
+				.option_values
+				.push(((level as i32, name as i32), value.to_vec()));
+		}
+
+		/// Make every write from now on fail with `errno`.
+		pub(crate) fn fail_writes(&self, errno: u32) {
+			self.state().write_errno = Some(errno as i32);
+		}
+
+		/// Every socket closed so far, in order.
+		pub(crate) fn closed(&self) -> Vec<i32> {
+			self.state().closed.clone()
+		}
+
+		/// The level and name of every option set on socket `fd`.
+		pub(crate) fn options(&self, fd: i32) -> Vec<(i32, i32)> {
+			self.state()
+				.options
+				.iter()
+				.filter(|(f, _, _)| *f == fd)
+				.map(|(_, level, name)| (*level, *name))
+				.collect()
+		}
+
+		fn state(&self) -> std::sync::MutexGuard<'_, State> {
+			self.state.lock().unwrap()
+		}
+	}
+
+	impl State {
+		fn protocol(&self, fd: i32) -> i32 {
+			self.sockets
+				.iter()
+				.find(|(s, _)| *s == fd)
+				.map(|(_, p)| *p)
+				.expect("unknown socket")
+		}
+
+		/// Answer the AT command being written, if there is one.
+		fn finish_command(&mut self) {
+			if self.partial_command.is_empty() {
+				return;
+			}
+			let command = core::mem::take(&mut self.partial_command);
+			let reply = self
+				.replies
+				.iter()
+				.find(|(c, _)| *c == command)
+				.map(|(_, r)| r.clone())
+				.unwrap_or_else(|| String::from("ERROR\r\n"));
+			self.commands.push(command);
+			for part in reply.split('\0') {
+				let mut data = part.as_bytes().to_vec();
+				data.push(0);
+				self.inbox(sys::NRF_PROTO_AT as i32).push_back(data);
+			}
+		}
+
+		fn inbox(&mut self, protocol: i32) -> &mut VecDeque<Vec<u8>> {
+			let idx = match self.inbox.iter().position(|(p, _)| *p == protocol) {
+				Some(idx) => idx,
+				None => {
+					self.inbox.push((protocol, VecDeque::new()));
+					self.inbox.len() - 1
+				}
+			};
+			&mut self.inbox[idx].1
+		}
+	}
+
+	impl Backend for MockModem {
+		fn socket(&self, _domain: i32, _skt_type: i32, protocol: i32) -> i32 {
+			let mut state = self.state();
+			state.next_fd += 1;
+			let fd = state.next_fd;
+			state.sockets.push((fd, protocol));
+			fd
+		}
+
+		fn close(&self, fd: i32) -> i32 {
+			let mut state = self.state();
+			state.sockets.retain(|(s, _)| *s != fd);
+			state.closed.push(fd);
+			0
+		}
+
+		fn connect(&self, fd: i32, _addr: &sys::nrf_sockaddr_in) -> i32 {
+			let mut state = self.state();
+			state.connections.push(fd);
+			if state.refusals > 0 {
+				state.refusals -= 1;
+				set_errno(sys::NRF_ETIMEDOUT as i32);
+				-1
+			} else {
+				0
+			}
+		}
+
+		fn bind(&self, _fd: i32, _addr: &sys::nrf_sockaddr_in) -> i32 {
+			0
+		}
+
+		fn listen(&self, _fd: i32, _backlog: i32) -> i32 {
+			0
+		}
+
+		fn accept(&self, _fd: i32, _addr: &mut sys::nrf_sockaddr_in) -> i32 {
+			set_errno(sys::NRF_EOPNOTSUPP as i32);
+			-1
+		}
+
+		fn send(&self, fd: i32, buf: &[u8], _flags: i32) -> sys::ssize_t {
+			self.write(fd, buf)
+		}
+
+		fn send_to(
+			&self,
+			fd: i32,
+			buf: &[u8],
+			_flags: i32,
+			_addr: &sys::nrf_sockaddr_in,
+		) -> sys::ssize_t {
+			self.write(fd, buf)
+		}
+
+		fn write(&self, fd: i32, buf: &[u8]) -> sys::ssize_t {
+			let mut state = self.state();
+			if let Some(errno) = state.write_errno {
+				set_errno(errno);
+				return -1;
+			}
+			if state.protocol(fd) == sys::NRF_PROTO_AT as i32 {
+				state
+					.partial_command
+					.push_str(core::str::from_utf8(buf).unwrap());
+			}
+			buf.len() as sys::ssize_t
+		}
+
+		fn recv(&self, fd: i32, buf: &mut [MaybeUninit<u8>], flags: i32) -> sys::ssize_t {
+			let mut state = self.state();
+			state.finish_command();
+			let protocol = state.protocol(fd);
+			match state.inbox(protocol).pop_front() {
+				Some(data) => {
+					state.empty_reads = 0;
+					for (slot, byte) in buf.iter_mut().zip(data.iter()) {
+						*slot = MaybeUninit::new(*byte);
+					}
+					// Like the real thing, `MSG_TRUNC` gives the whole length
+					if (flags & sys::NRF_MSG_TRUNC as i32) != 0 {
+						data.len() as sys::ssize_t
+					} else {
+						data.len().min(buf.len()) as sys::ssize_t
+					}
+				}
+				None => {
+					state.empty_reads += 1;
+					assert!(state.empty_reads < MAX_EMPTY_READS, "nothing to read");
+					set_errno(sys::NRF_EAGAIN as i32);
+					-1
+				}
+			}
+		}
+
+		fn recv_from(
+			&self,
+			fd: i32,
+			buf: &mut [MaybeUninit<u8>],
+			flags: i32,
+			_addr: &mut sys::nrf_sockaddr_in,
+		) -> sys::ssize_t {
+			self.recv(fd, buf, flags)
+		}
+
+		fn set_option(
+			&self,
+			fd: i32,
+			level: i32,
+			name: i32,
+			_value: *const sys::ctypes::c_void,
+			_length: u32,
+		) -> i32 {
+			self.state().options.push((fd, level, name));
+			0
+		}
+
+		fn get_option(
+			&self,
+			_fd: i32,
+			level: i32,
+			name: i32,
+			value: *mut sys::ctypes::c_void,
+			length: &mut u32,
+		) -> i32 {
+			let state = self.state();
+			if let Some((_, data)) = state
+				.option_values
+				.iter()
+				.find(|(key, _)| *key == (level, name))
+			{
+				let count = data.len().min(*length as usize);
+				// NOTE(unsafe) - the caller gave us `length` bytes at `value`.
+				unsafe {
+					core::ptr::copy_nonoverlapping(data.as_ptr(), value as *mut u8, count);
+				}
+				*length = count as u32;
+			}
+			0
+		}
+
+		fn poll(&self, fds: &mut [sys::nrf_pollfd], _timeout_ms: i32) -> i32 {
+			let mut state = self.state();
+			state.finish_command();
+			let mut count = 0;
+			for pollfd in fds.iter_mut() {
+				let protocol = state.protocol(pollfd.fd);
+				pollfd.revents = 0;
+				if !state.inbox(protocol).is_empty() {
+					pollfd.revents = sys::NRF_POLLIN as i16 & pollfd.events;
+				}
+				if pollfd.revents != 0 {
+					count += 1;
+				}
+			}
+			count
+		}
+
+		fn getaddrinfo(
+			&self,
+			_hostname: &str,
+			_hints: &sys::nrf_addrinfo,
+			result: &mut *mut sys::nrf_addrinfo,
+		) -> i32 {
+			let mut next = core::ptr::null_mut();
+			for octets in self.state().addresses.iter().rev() {
+				let addr = std::boxed::Box::new(crate::NrfSockAddrIn::new(*octets, 0).0);
+				next = std::boxed::Box::into_raw(std::boxed::Box::new(sys::nrf_addrinfo {
+					ai_flags: 0,
+					ai_family: sys::NRF_AF_INET as i32,
+					ai_socktype: 0,
+					ai_protocol: 0,
+					ai_addrlen: core::mem::size_of::<sys::nrf_sockaddr_in>() as u32,
+					ai_addr: std::boxed::Box::into_raw(addr) as *mut sys::nrf_sockaddr,
+					ai_canonname: core::ptr::null_mut(),
+					ai_next: next,
+				}));
+			}
+			*result = next;
+			0
+		}
+
+		fn freeaddrinfo(&self, mut result: *mut sys::nrf_addrinfo) {
+			while !result.is_null() {
+				// NOTE(unsafe) - these all came from `Box::into_raw` in
+				// `getaddrinfo`.
+				let record = unsafe { std::boxed::Box::from_raw(result) };
+				drop(unsafe {
+					std::boxed::Box::from_raw(record.ai_addr as *mut sys::nrf_sockaddr_in)
+				});
+				result = record.ai_next;
+			}
+		}
+
+		fn wait_for_event(&self) {
+			let mut state = self.state();
+			state.waits += 1;
+			if state.wake_after == Some(state.waits) {
+				crate::ffi::notify_event();
+			}
+		}
+	}
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
@@ -0,0 +1,380 @@
+//! # Modem DFU for nrfxlib
+//!
+//! Device Firmware Update (DFU) related code, for updating the firmware on
+//! the modem itself. Firmware images (typically delta updates supplied by
+//! Nordic) are sent to the modem down a socket using the Nordic-specific
+//! `NRF_PROTO_DFU`.
+//!
+//! A typical update looks like:
+//!
+//! ```ignore
+//! nrfxlib::dfu::init()?;
+//! let mut offset = nrfxlib::dfu::get_offset()?;
+//! while let Some(chunk) = download_chunk(offset) {
+//!     nrfxlib::dfu::write(offset, chunk)?;
+//!     offset += chunk.len();
+//! }
+//! nrfxlib::dfu::apply()?;
+//! // Now reboot the modem
+//! ```
+//!
+//! Copyright (c) 42 Technology Ltd 2021
+//!
+//! Dual-licensed under MIT and Apache 2.0. See the [README](../README.md) for
+//! more details.
+
+//******************************************************************************
+// Sub-Modules
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Imports
+//******************************************************************************
+
+use super::{get_last_error, Error};
+use crate::raw::*;
+use log::debug;
+use nrfxlib_sys as sys;
+
+//******************************************************************************
+// Types
+//******************************************************************************
+
+/// Represents a connection to the modem's firmware update sub-system.
+#[derive(Debug)]
+pub struct DfuSocket(Socket);
+
+/// The result of a firmware update which the modem applied when it started.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DfuResult {
+	/// No update was applied.
+	NoUpdate,
+	/// The update was applied successfully.
+	Success,
+	/// The update failed an integrity check (the UUID or signature was
+	/// wrong) and was not applied.
+	IntegrityCheckFailed,
+	/// The update could not be applied because of a hardware error.
+	HardwareError,
+	/// The update could not be applied because of an internal error in the
+	/// modem.
+	InternalError,
+}
+
+/// Version information from the modem's firmware update sub-system.
+#[derive(Debug, Clone)]
+pub struct DfuVersions {
+	/// The UUID of the firmware currently running on the modem.
+	pub firmware_uuid: heapless::String<FIRMWARE_UUID_LEN>,
+	/// The space (in bytes) the modem has available for a new firmware image.
+	pub available_space: usize,
+}
+
+//******************************************************************************
+// Constants
+//******************************************************************************
+
+/// The length of a modem firmware UUID, as a string.
+const FIRMWARE_UUID_LEN: usize = 36;
+
+/// The offset the modem reports when its DFU area holds an old image and
+/// must be erased before a new one can be written.
+const DFU_AREA_NOT_BLANK_OFFSET: sys::nrf_dfu_fw_offset_t = 2_621_440;
+
+//******************************************************************************
+// Global Variables
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Macros
+//******************************************************************************
+
+// None
+
+//******************************************************************************
+// Public Functions and Impl on Public Types
+//******************************************************************************
+
+impl DfuSocket {
+	/// Create a new DFU socket.
+	pub fn new() -> Result<DfuSocket, Error> {
+		let skt = Socket::new(SocketDomain::Local, SocketType::Stream, SocketProtocol::Dfu)?;
+		Ok(DfuSocket(skt))
+	}
+
+	/// Get the offset within the firmware image that the modem expects the
+	/// next chunk to be written at. This is zero if no image has been
+	/// started, or the size of the image if it has been completely received.
+	///
+	/// If the DFU area needs erasing before an image can be written, you get
+	/// `Error::DfuAreaNotBlank`.
+	pub fn get_offset(&self) -> Result<usize, Error> {
+		self.0.start_operation()?;
+		let mut length: u32 = core::mem::size_of::<sys::nrf_dfu_fw_offset_t>() as u32;
+		let mut value: sys::nrf_dfu_fw_offset_t = 0;
+		let result = crate::backend::get_option(
+			self.0.fd,
+			sys::NRF_SOL_DFU as i32,
+			sys::NRF_SO_DFU_OFFSET as i32,
+			&mut value as *mut sys::nrf_dfu_fw_offset_t as *mut sys::ctypes::c_void,
+			&mut length,
+		);
+		if result < 0 {
+			Err(Error::Nordic("dfu_offset", result, get_last_error()))
+		} else if value == DFU_AREA_NOT_BLANK_OFFSET {
+			Err(Error::DfuAreaNotBlank)
+		} else {
+			Ok(value as usize)
+		}
+	}
+
+	/// Set the offset within the firmware image at which the next chunk will
+	/// be written.
+	pub fn set_offset(&self, offset: usize) -> Result<(), Error> {
+		self.0
+			.set_option(SocketOption::DfuOffset(offset as sys::nrf_dfu_fw_offset_t))
+	}
+
+	/// Write a chunk of the firmware image, starting at the given offset.
+	///
+	/// If `offset` is not where the modem expects the next chunk to go (see
+	/// `get_offset`), the modem is told to move to `offset` first. This lets
+	/// you resume an interrupted download.
+	///
+	/// If the modem rejects the chunk, you get `Error::DfuFailed` with the
+	/// reason it gave.
+	pub fn write_chunk(&self, offset: usize, chunk: &[u8]) -> Result<(), Error> {
+		if self.get_offset()? != offset {
+			debug!("Moving DFU offset to {}", offset);
+			self.set_offset(offset)?;
+		}
+		let mut remaining = chunk;
+		while !remaining.is_empty() {
+			let written = self
+				.0
+				.write(remaining)
+				.map_err(|e| self.get_dfu_error().unwrap_or(e))?;
+			if written == 0 {
+				return Err(Error::WriteError);
+			}
+			remaining = &remaining[written..];
+		}
+		Ok(())
+	}
+
+	/// Get the UUID of the firmware currently running on the modem.
+	pub fn get_firmware_uuid(&self) -> Result<heapless::String<FIRMWARE_UUID_LEN>, Error> {
+		self.0.start_operation()?;
+		let mut length: u32 = core::mem::size_of::<sys::nrf_dfu_fw_version_t>() as u32;
+		let mut value: sys::nrf_dfu_fw_version_t = [0u8; FIRMWARE_UUID_LEN];
+		let result = crate::backend::get_option(
+			self.0.fd,
+			sys::NRF_SOL_DFU as i32,
+			sys::NRF_SO_DFU_FW_VERSION as i32,
+			value.as_mut_ptr() as *mut sys::ctypes::c_void,
+			&mut length,
+		);
+		if result < 0 {
+			return Err(Error::Nordic("dfu_fw_version", result, get_last_error()));
+		}
+		let uuid = core::str::from_utf8(&value).map_err(|_| Error::BadDataFormat)?;
+		let mut output = heapless::String::new();
+		output
+			.push_str(uuid.trim_end_matches('\0'))
+			.map_err(|_| Error::BadDataFormat)?;
+		Ok(output)
+	}
+
+	/// Get the space (in bytes) the modem has available for a new firmware
+	/// image.
+	pub fn get_available_space(&self) -> Result<usize, Error> {
+		self.0.start_operation()?;
+		let mut length: u32 = core::mem::size_of::<sys::nrf_dfu_resources_t>() as u32;
+		let mut value: sys::nrf_dfu_resources_t = 0;
+		let result = crate::backend::get_option(
+			self.0.fd,
+			sys::NRF_SOL_DFU as i32,
+			sys::NRF_SO_DFU_RESOURCES as i32,
+			&mut value as *mut sys::nrf_dfu_resources_t as *mut sys::ctypes::c_void,
+			&mut length,
+		);
+		if result < 0 {
+			Err(Error::Nordic("dfu_resources", result, get_last_error()))
+		} else {
+			Ok(value as usize)
+		}
+	}
+
+	/// Delete the backup firmware image, to make space for a new image.
+	pub fn erase(&self) -> Result<(), Error> {
+		self.0.set_option(SocketOption::DfuBackupDelete)
+	}
+
+	/// Schedule the received firmware image to be applied. The update takes
+	/// place when the modem is next rebooted.
+	pub fn apply(&self) -> Result<(), Error> {
+		self.0.set_option(SocketOption::DfuApply)
+	}
+}
+
+impl Pollable for DfuSocket {
+	/// Get the underlying socket ID for this socket.
+	fn get_fd(&self) -> i32 {
+		self.0.fd
+	}
+}
+
+impl core::ops::DerefMut for DfuSocket {
+	fn deref_mut(&mut self) -> &mut Socket {
+		&mut self.0
+	}
+}
+
+impl core::ops::Deref for DfuSocket {
+	type Target = Socket;
+	fn deref(&self) -> &Socket {
+		&self.0
+	}
+}
+
+/// Check the modem is ready to receive a firmware image.
+///
+/// Creates and destroys a new DFU socket, and reports where any previous
+/// (partial) download got up to.
+pub fn init() -> Result<(), Error> {
+	let skt = DfuSocket::new()?;
+	let offset = skt.get_offset()?;
+	debug!("Modem DFU ready, offset {}", offset);
+	Ok(())
+}
+
+/// Write a chunk of the firmware image, starting at the given offset.
+///
+/// Chunks can be delivered as they are downloaded. To resume an interrupted
+/// download, call `get_offset` and carry on from there. Creates and destroys
+/// a new DFU socket. See `DfuSocket::write_chunk`.
+pub fn write(offset: usize, chunk: &[u8]) -> Result<(), Error> {
+	let skt = DfuSocket::new()?;
+	skt.write_chunk(offset, chunk)
+}
+
+/// Get the offset within the firmware image that the modem expects the next
+/// chunk to be written at. Creates and destroys a new DFU socket.
+pub fn get_offset() -> Result<usize, Error> {
+	let skt = DfuSocket::new()?;
+	skt.get_offset()
+}
+
+/// Delete the backup firmware image, to make space for a new image. Creates
+/// and destroys a new DFU socket.
+pub fn erase() -> Result<(), Error> {
+	let skt = DfuSocket::new()?;
+	skt.erase()
+}
+
+/// Schedule the received firmware image to be applied when the modem is next
+/// rebooted. Creates and destroys a new DFU socket.
+pub fn apply() -> Result<(), Error> {
+	let skt = DfuSocket::new()?;
+	skt.apply()
+}
+
+/// Find out whether a firmware update was applied when the modem last
+/// started, and if so, whether it worked.
+///
+/// The modem applies a scheduled update (see `apply`) when it starts, and
+/// reports the result from `init`. Call this function after `init` to check
+/// that an update took effect.
+pub fn scheduled_update_result() -> Result<DfuResult, Error> {
+	let result = crate::INIT_RESULT.load(core::sync::atomic::Ordering::SeqCst);
+	match result as u32 {
+		0 => Ok(DfuResult::NoUpdate),
+		sys::MODEM_DFU_RESULT_OK => Ok(DfuResult::Success),
+		sys::MODEM_DFU_RESULT_UUID_ERROR | sys::MODEM_DFU_RESULT_AUTH_ERROR => {
+			Ok(DfuResult::IntegrityCheckFailed)
+		}
+		sys::MODEM_DFU_RESULT_HARDWARE_ERROR => Ok(DfuResult::HardwareError),
+		sys::MODEM_DFU_RESULT_INTERNAL_ERROR => Ok(DfuResult::InternalError),
+		_ => Err(Error::UnrecognisedValue),
+	}
+}
+
+/// Get the UUID of the firmware currently running on the modem, and the
+/// space it has available for a new firmware image. Creates and destroys a
+/// new DFU socket.
+pub fn get_versions() -> Result<DfuVersions, Error> {
+	let skt = DfuSocket::new()?;
+	Ok(DfuVersions {
+		firmware_uuid: skt.get_firmware_uuid()?,
+		available_space: skt.get_available_space()?,
+	})
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+impl DfuSocket {
+	/// Ask the modem why the last DFU operation failed
+	/// (`NRF_SO_DFU_ERROR`). Gives `None` if it didn't, or we can't tell.
+	fn get_dfu_error(&self) -> Option<Error> {
+		self.0.start_operation().ok()?;
+		let mut length: u32 = core::mem::size_of::<sys::nrf_dfu_err_t>() as u32;
+		let mut value: sys::nrf_dfu_err_t = 0;
+		let result = crate::backend::get_option(
+			self.0.fd,
+			sys::NRF_SOL_DFU as i32,
+			sys::NRF_SO_DFU_ERROR as i32,
+			&mut value as *mut sys::nrf_dfu_err_t as *mut sys::ctypes::c_void,
+			&mut length,
+		);
+		if result < 0 || value == 0 {
+			None
+		} else {
+			Some(Error::DfuFailed(value))
+		}
+	}
+}
+
+//******************************************************************************
+// Tests
+//******************************************************************************
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::backend::mock;
+
+	#[test]
+	fn dirty_dfu_area_is_reported() {
+		mock::run(|modem| {
+			modem.option_value(
+				sys::NRF_SOL_DFU,
+				sys::NRF_SO_DFU_OFFSET,
+				&DFU_AREA_NOT_BLANK_OFFSET.to_le_bytes(),
+			);
+			assert_eq!(get_offset(), Err(Error::DfuAreaNotBlank));
+		});
+	}
+
+	#[test]
+	fn rejected_chunk_gives_the_dfu_error() {
+		mock::run(|modem| {
+			modem.option_value(
+				sys::NRF_SOL_DFU,
+				sys::NRF_SO_DFU_ERROR,
+				&(-5i32).to_le_bytes(),
+			);
+			modem.fail_writes(sys::NRF_EINVAL);
+			assert_eq!(write(0, &[0u8; 16]), Err(Error::DfuFailed(-5)));
+		});
+	}
+}
+
+//******************************************************************************
+// End of File
+//******************************************************************************
@@ -17,21 +17,30 @@
 // Imports
 //******************************************************************************
 
-pub use crate::tls::provision_certificates;
+pub use crate::tls::{
+	delete_credential, list_credentials, provision_certificates, provision_psk, CredentialType,
+	SecurityTag,
+};
 
-use super::{get_last_error, Error};
+use super::{Error, NrfSockAddrIn, NrfSockAddrIn6};
 use crate::raw::*;
+use crate::tls::SecureSettings;
 use log::debug;
-use nrfxlib_sys as sys;
 
 //******************************************************************************
 // Types
 //******************************************************************************
 
 /// Represents a connection to a remote TCP/IP device using DTLS over UDP.
+///
+/// If you connect by IP address (e.g. with `connect_ip`), the server's
+/// certificate is not checked against a hostname, as we don't know it. Use
+/// `connect` if you need that.
 #[derive(Debug)]
 pub struct DtlsSocket {
 	socket: Socket,
+	/// The security options we have set on `socket`
+	settings: SecureSettings,
 }
 
 /// Specify which version of the DTLS standard to use
@@ -76,121 +85,101 @@ pub enum PeerVerification {
 //******************************************************************************
 
 impl DtlsSocket {
-	/// Create a new TLS socket. Only supports TLS v1.2/1.3 and IPv4 at the moment.
+	/// Create a new IPv4 DTLS socket. Only supports DTLS v1.2 at the moment.
+	///
+	/// The modem takes at most seven `security_tags`; you get
+	/// `Error::InvalidArgument` if you give more.
 	pub fn new(
 		peer_verify: PeerVerification,
-		security_tags: &[u32],
+		security_tags: &[SecurityTag],
 		version: Version,
+	) -> Result<DtlsSocket, Error> {
+		DtlsSocket::new_with_family(peer_verify, security_tags, version, SocketFamily::Ipv4)
+	}
+
+	/// Create a new DTLS socket, using IPv4 or IPv6. See `new`.
+	pub fn new_with_family(
+		peer_verify: PeerVerification,
+		security_tags: &[SecurityTag],
+		version: Version,
+		family: SocketFamily,
 	) -> Result<DtlsSocket, Error> {
 		let nrf_dtls_version = match version {
 			Version::Dtls1v2 => SocketProtocol::Dtls1v2,
 		};
 
-		let socket = Socket::new(SocketDomain::Inet, SocketType::Datagram, nrf_dtls_version)?;
+		let socket = Socket::new(family.into(), SocketType::Datagram, nrf_dtls_version)?;
 
 		// Now configure this socket
+		let settings = SecureSettings::new(peer_verify.as_integer(), security_tags)?;
+		settings.apply(&socket)?;
 
-		// Set whether we verify the peer
-		socket.set_option(SocketOption::TlsPeerVerify(peer_verify.as_integer()))?;
+		Ok(DtlsSocket { socket, settings })
+	}
 
-		// Always enable session caching to speed up connecting. 0 = enabled, 1
-		// = disabled (the default).
-		socket.set_option(SocketOption::TlsSessionCache(0))?;
+	/// Look up the hostname and for each result returned, try to connect to
+	/// it. An IPv6 socket looks up AAAA records, and an IPv4 socket looks up
+	/// A records.
+	///
+	/// Stops at the first address which accepts the connection. If none of
+	/// them do, you get `Error::ConnectFailed`. The underlying socket is
+	/// replaced after each failed attempt, with the same security options.
+	pub fn connect(&mut self, hostname: &str, port: u16) -> Result<(), Error> {
+		debug!("Connecting via DTLS to {}:{}", hostname, port);
 
-		// We don't set the cipher list, and assume the defaults are sensible.
+		// First we set the hostname
+		self.socket
+			.set_option(SocketOption::TlsHostName(hostname))?;
 
-		if !security_tags.is_empty() {
-			// Configure the socket to use the pre-stored certificates. See
-			// `provision_certificates`.
-			socket.set_option(SocketOption::TlsTagList(security_tags))?;
-		}
+		let settings = &self.settings;
+		self.socket.connect_by_name(hostname, port, |socket| {
+			settings.apply(socket)?;
+			socket.set_option(SocketOption::TlsHostName(hostname))
+		})
+	}
 
-		Ok(DtlsSocket { socket })
+	/// Choose whether the modem caches the DTLS session, so it can resume it
+	/// next time rather than doing a full handshake. `new` turns this on.
+	///
+	/// Resuming a session makes connecting much faster and uses less data,
+	/// but lets the server link your connections together, and some servers
+	/// handle resumption badly. Call this before `connect`.
+	pub fn set_session_cache(&mut self, enabled: bool) -> Result<(), Error> {
+		self.settings.set_session_cache(&self.socket, enabled)
 	}
 
-	/// Look up the hostname and for each result returned, try to connect to
-	/// it.
-	pub fn connect(&self, hostname: &str, port: u16) -> Result<(), Error> {
-		use core::fmt::Write;
+	/// Restrict the cipher suites the modem will offer to the ones in
+	/// `suites`, given as IANA values (see the `TLS_*` constants in the
+	/// `tls` module). By default the modem offers every suite it supports.
+	///
+	/// Call this before `connect`. You get `Error::InvalidArgument` if
+	/// `suites` lists more than 32 suites.
+	pub fn set_cipher_suites(&mut self, suites: &[u32]) -> Result<(), Error> {
+		self.settings.set_cipher_suites(&self.socket, suites)
+	}
 
-		debug!("Connecting via DTLS to {}:{}", hostname, port);
+	/// Connect to the given IPv4 address and port, without doing a DNS
+	/// look-up.
+	pub fn connect_ip(&self, addr: core::net::Ipv4Addr, port: u16) -> Result<(), Error> {
+		self.connect_addr(&core::net::SocketAddrV4::new(addr, port).into())
+	}
 
-		// First we set the hostname
+	/// Like `connect_ip`, but taking the address and port together.
+	pub fn connect_addr(&self, addr: &NrfSockAddrIn) -> Result<(), Error> {
 		self.socket
-			.set_option(SocketOption::TlsHostName(hostname))?;
+			.connect_labelled("dtls_connect", SockAddr::V4(addr.0))
+	}
 
-		let mut result;
-		// Now, make a null-terminated hostname
-		let mut hostname_smallstring: heapless::String<64> = heapless::String::new();
-		write!(hostname_smallstring, "{}\0", hostname).map_err(|_| Error::HostnameTooLong)?;
-		// Now call getaddrinfo with some hints
-		let hints = sys::nrf_addrinfo {
-			ai_flags: 0,
-			ai_family: sys::NRF_AF_INET as i32,
-			ai_socktype: sys::NRF_SOCK_DGRAM as i32,
-			ai_protocol: 0,
-			ai_addrlen: 0,
-			ai_addr: core::ptr::null_mut(),
-			ai_canonname: core::ptr::null_mut(),
-			ai_next: core::ptr::null_mut(),
-		};
-		let mut output_ptr: *mut sys::nrf_addrinfo = core::ptr::null_mut();
-		result = unsafe {
-			sys::nrf_getaddrinfo(
-				// hostname
-				hostname_smallstring.as_ptr(),
-				// service
-				core::ptr::null(),
-				// hints
-				&hints,
-				// output pointer
-				&mut output_ptr,
-			)
-		};
+	/// Connect to the given IPv6 address and port, without doing a DNS
+	/// look-up. The socket must have been created with `SocketFamily::Ipv6`.
+	pub fn connect_ipv6(&self, addr: core::net::Ipv6Addr, port: u16) -> Result<(), Error> {
+		self.connect_addr6(&core::net::SocketAddrV6::new(addr, port, 0, 0).into())
+	}
 
-		if (result != 0) && output_ptr.is_null() {
-			return Err(Error::Nordic("dtls_dns", result, get_last_error()));
-		} else {
-			let mut record: &sys::nrf_addrinfo = unsafe { &*output_ptr };
-			loop {
-				let dns_addr: &sys::nrf_sockaddr_in =
-					unsafe { &*(record.ai_addr as *const sys::nrf_sockaddr_in) };
-				// Create a new sockaddr_in with the right port
-				let connect_addr = sys::nrf_sockaddr_in {
-					sin_len: core::mem::size_of::<sys::nrf_sockaddr_in>() as u8,
-					sin_family: sys::NRF_AF_INET as i32,
-					sin_port: htons(port),
-					sin_addr: dns_addr.sin_addr,
-				};
-
-				debug!("Trying IP address {}", &crate::NrfSockAddrIn(connect_addr));
-
-				// try and connect to this result
-				result = unsafe {
-					sys::nrf_connect(
-						self.socket.fd,
-						&connect_addr as *const sys::nrf_sockaddr_in as *const _,
-						connect_addr.sin_len as u32,
-					)
-				};
-				if result == 0 {
-					break;
-				}
-				if !record.ai_next.is_null() {
-					record = unsafe { &*record.ai_next };
-				} else {
-					break;
-				}
-			}
-			unsafe {
-				sys::nrf_freeaddrinfo(output_ptr);
-			}
-		}
-		if result != 0 {
-			Err(Error::Nordic("dtls_connect", result, get_last_error()))
-		} else {
-			Ok(())
-		}
+	/// Like `connect_ipv6`, but taking the address and port together.
+	pub fn connect_addr6(&self, addr: &NrfSockAddrIn6) -> Result<(), Error> {
+		self.socket
+			.connect_labelled("dtls_connect", SockAddr::V6(addr.0))
 	}
 }
 
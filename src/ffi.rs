@@ -13,6 +13,14 @@ use log::debug;
 /// Number of IPC configurations in `NrfxIpcConfig`
 const IPC_CONF_NUM: usize = 8;
 
+/// NRF9160 runs at 64 MHz, so this many CPU cycles (and processor-clocked
+/// SysTick counts) make a millisecond.
+const CYCLES_PER_MS: u32 = 64_000;
+
+/// SysTick control bits: counter enabled, interrupt on wrap, and counting
+/// the processor clock.
+const SYST_CSR_ENABLE_TICKINT_CLKSOURCE: u32 = 0b111;
+
 /// Used by `libmodem` to configure the IPC peripheral. See `nrfx_ipc_config_t`
 /// in `nrfx/drivers/include/nrfx_ipc.h`.
 #[derive(Debug, Clone)]
@@ -60,6 +68,110 @@ pub enum NrfxErr {
 	ErrorAlreadyInitialized = (0x0BAD0000 + 12),
 }
 
+/// The `errno` values the Nordic library can report - exactly those defined
+/// in `nrf_errno.h`. See `last_error` and `NrfErrno::from_i32`.
+#[repr(i32)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NrfErrno {
+	/// `EPERM` - Operation not permitted
+	Perm = nrfxlib_sys::NRF_EPERM as i32,
+	/// `ENOENT` - No such file or directory
+	NoEnt = nrfxlib_sys::NRF_ENOENT as i32,
+	/// `EIO` - I/O error
+	Io = nrfxlib_sys::NRF_EIO as i32,
+	/// `EBADF` - Bad file descriptor
+	BadF = nrfxlib_sys::NRF_EBADF as i32,
+	/// `EAGAIN` - Try again - the operation would block
+	Again = nrfxlib_sys::NRF_EAGAIN as i32,
+	/// `ENOMEM` - Out of memory
+	NoMem = nrfxlib_sys::NRF_ENOMEM as i32,
+	/// `EACCES` - Permission denied
+	Acces = nrfxlib_sys::NRF_EACCES as i32,
+	/// `EFAULT` - Bad address
+	Fault = nrfxlib_sys::NRF_EFAULT as i32,
+	/// `EINVAL` - Invalid argument
+	Inval = nrfxlib_sys::NRF_EINVAL as i32,
+	/// `EMFILE` - Too many open files
+	MFile = nrfxlib_sys::NRF_EMFILE as i32,
+	/// `ENOSPC` - No space left on device
+	NoSpc = nrfxlib_sys::NRF_ENOSPC as i32,
+	/// `EOPNOTSUPP` - Operation not supported
+	OpNotSupp = nrfxlib_sys::NRF_EOPNOTSUPP as i32,
+	/// `ENOBUFS` - No buffer space available
+	NoBufs = nrfxlib_sys::NRF_ENOBUFS as i32,
+	/// `EAFNOSUPPORT` - Address family not supported
+	AfNoSupport = nrfxlib_sys::NRF_EAFNOSUPPORT as i32,
+	/// `EPROTOTYPE` - Protocol wrong type for socket
+	ProtoType = nrfxlib_sys::NRF_EPROTOTYPE as i32,
+	/// `ENOPROTOOPT` - Protocol not available
+	NoProtoOpt = nrfxlib_sys::NRF_ENOPROTOOPT as i32,
+	/// `EADDRINUSE` - Address already in use
+	AddrInUse = nrfxlib_sys::NRF_EADDRINUSE as i32,
+	/// `ECONNABORTED` - Connection aborted
+	ConnAborted = nrfxlib_sys::NRF_ECONNABORTED as i32,
+	/// `ENETUNREACH` - Network is unreachable
+	NetUnreach = nrfxlib_sys::NRF_ENETUNREACH as i32,
+	/// `ENETDOWN` - Network is down
+	NetDown = nrfxlib_sys::NRF_ENETDOWN as i32,
+	/// `ETIMEDOUT` - Connection timed out
+	TimedOut = nrfxlib_sys::NRF_ETIMEDOUT as i32,
+	/// `EHOSTDOWN` - Host is down
+	HostDown = nrfxlib_sys::NRF_EHOSTDOWN as i32,
+	/// `EINPROGRESS` - Operation now in progress
+	InProgress = nrfxlib_sys::NRF_EINPROGRESS as i32,
+	/// `EALREADY` - Operation already in progress
+	Already = nrfxlib_sys::NRF_EALREADY as i32,
+	/// `EMSGSIZE` - Message too long
+	MsgSize = nrfxlib_sys::NRF_EMSGSIZE as i32,
+	/// `EPROTONOSUPPORT` - Protocol not supported
+	ProtoNoSupport = nrfxlib_sys::NRF_EPROTONOSUPPORT as i32,
+	/// `ENETRESET` - Network dropped connection on reset
+	NetReset = nrfxlib_sys::NRF_ENETRESET as i32,
+	/// `EISCONN` - Socket is already connected
+	IsConn = nrfxlib_sys::NRF_EISCONN as i32,
+	/// `ENOTCONN` - Socket is not connected
+	NotConn = nrfxlib_sys::NRF_ENOTCONN as i32,
+	/// `ECONNRESET` - Connection reset by peer
+	ConnReset = nrfxlib_sys::NRF_ECONNRESET as i32,
+	/// `ECANCELED` - Operation canceled
+	Canceled = nrfxlib_sys::NRF_ECANCELED as i32,
+}
+
+/// Every `NrfErrno`, so we can look them up by value.
+const ALL_ERRNOS: [NrfErrno; 31] = [
+	NrfErrno::Perm,
+	NrfErrno::NoEnt,
+	NrfErrno::Io,
+	NrfErrno::BadF,
+	NrfErrno::Again,
+	NrfErrno::NoMem,
+	NrfErrno::Acces,
+	NrfErrno::Fault,
+	NrfErrno::Inval,
+	NrfErrno::MFile,
+	NrfErrno::NoSpc,
+	NrfErrno::OpNotSupp,
+	NrfErrno::NoBufs,
+	NrfErrno::AfNoSupport,
+	NrfErrno::ProtoType,
+	NrfErrno::NoProtoOpt,
+	NrfErrno::AddrInUse,
+	NrfErrno::ConnAborted,
+	NrfErrno::NetUnreach,
+	NrfErrno::NetDown,
+	NrfErrno::TimedOut,
+	NrfErrno::HostDown,
+	NrfErrno::InProgress,
+	NrfErrno::Already,
+	NrfErrno::MsgSize,
+	NrfErrno::ProtoNoSupport,
+	NrfErrno::NetReset,
+	NrfErrno::IsConn,
+	NrfErrno::NotConn,
+	NrfErrno::ConnReset,
+	NrfErrno::Canceled,
+];
+
 /// Stores the last error from the library. See `nrf_modem_os_errno_set` and
 /// `get_last_error`.
 static LAST_ERROR: core::sync::atomic::AtomicI32 = core::sync::atomic::AtomicI32::new(0);
@@ -70,6 +182,62 @@ static IPC_CONTEXT: core::sync::atomic::AtomicUsize = core::sync::atomic::Atomic
 /// Remembers the IPC handler function we were given
 static IPC_HANDLER: core::sync::atomic::AtomicUsize = core::sync::atomic::AtomicUsize::new(0);
 
+/// Counts the allocations we couldn't satisfy. See `allocation_failures`.
+static ALLOCATION_FAILURES: core::sync::atomic::AtomicU32 = core::sync::atomic::AtomicU32::new(0);
+
+/// The function to call when an allocation fails, or zero. See
+/// `set_oom_hook`.
+static OOM_HOOK: core::sync::atomic::AtomicUsize = core::sync::atomic::AtomicUsize::new(0);
+
+/// Set when the library has handled an event from the modem, so a
+/// `nrf_modem_os_timedwait` in progress can stop waiting. See
+/// `notify_event`.
+static MODEM_EVENT: core::sync::atomic::AtomicBool = core::sync::atomic::AtomicBool::new(false);
+
+/// The function which `nrf_modem_os_timedwait` calls to do the waiting, or
+/// zero to use our own. See `set_timed_wait_impl`.
+static TIMED_WAIT_IMPL: core::sync::atomic::AtomicUsize = core::sync::atomic::AtomicUsize::new(0);
+
+/// How many milliseconds `nrf_modem_os_timedwait` has spent waiting, in
+/// total, wrapping on overflow. See `waited_ms`.
+static WAITED_MS: core::sync::atomic::AtomicU32 = core::sync::atomic::AtomicU32::new(0);
+
+/// Size of the buffer holding modem trace bytes until `read_trace` collects
+/// them.
+const TRACE_BUFFER_SIZE: usize = 2048;
+
+/// Holds modem trace bytes given to us by `nrf_modem_os_trace_put`. See
+/// `read_trace`.
+static TRACE_BUFFER: cortex_m::interrupt::Mutex<
+	core::cell::RefCell<heapless::Deque<u8, TRACE_BUFFER_SIZE>>,
+> = cortex_m::interrupt::Mutex::new(core::cell::RefCell::new(heapless::Deque::new()));
+
+/// The function to give modem trace bytes to, or zero to buffer them for
+/// `read_trace`. See `set_trace_sink`.
+static TRACE_SINK: core::sync::atomic::AtomicUsize = core::sync::atomic::AtomicUsize::new(0);
+
+/// Counts the trace bytes we had to throw away because `TRACE_BUFFER` was
+/// full.
+static TRACE_BYTES_DROPPED: core::sync::atomic::AtomicU32 = core::sync::atomic::AtomicU32::new(0);
+
+/// Get the number of times the library has asked for memory (from its heap,
+/// or from the TX area of shared memory) and we couldn't give it any.
+///
+/// If this goes up, you probably need a bigger heap - see `init_with_heap`.
+pub fn allocation_failures() -> u32 {
+	ALLOCATION_FAILURES.load(core::sync::atomic::Ordering::SeqCst)
+}
+
+/// Set a function to be called whenever the library asks for memory and we
+/// couldn't give it any. It is given the number of bytes requested. Pass
+/// `None` to remove it.
+///
+/// The function may be called from an interrupt, so it should be quick.
+pub fn set_oom_hook(hook: Option<fn(usize)>) {
+	let value = hook.map(|f| f as usize).unwrap_or(0);
+	OOM_HOOK.store(value, core::sync::atomic::Ordering::SeqCst);
+}
+
 /// Function required by BSD library. We need to set the EGU1 interrupt.
 #[no_mangle]
 pub extern "C" fn nrf_modem_os_application_irq_set() {
@@ -111,28 +279,178 @@ pub fn get_last_error() -> i32 {
 	LAST_ERROR.load(core::sync::atomic::Ordering::SeqCst)
 }
 
-/// Function required by BSD library
+/// Return the last error stored by the nrfxlib C library, if there is one and
+/// we recognise it.
+pub fn last_error() -> Option<NrfErrno> {
+	NrfErrno::from_i32(get_last_error())
+}
+
+/// Forget the last error stored by the nrfxlib C library, so that
+/// `get_last_error` returns 0 until the library reports another one.
+///
+/// The socket functions in this crate do this before they call into the
+/// library, so the error they report always belongs to that call.
+pub fn clear_last_error() {
+	LAST_ERROR.store(0, core::sync::atomic::Ordering::SeqCst);
+}
+
+impl NrfErrno {
+	/// Convert a raw `errno` value into an `NrfErrno`. Returns `None` for zero,
+	/// or for any value we don't recognise.
+	pub fn from_i32(value: i32) -> Option<NrfErrno> {
+		ALL_ERRNOS.iter().cloned().find(|e| *e as i32 == value)
+	}
+}
+
+/// Replace the way the library waits for the modem (`nrf_modem_os_timedwait`)
+/// with your own function, e.g. one which pends on an RTOS semaphore. Pass
+/// `None` to go back to the built-in wait.
+///
+/// The function is given the timeout in milliseconds, where a negative
+/// value means there is no timeout. It should return `0` as soon as
+/// `application_irq_handler` has run (so signal your semaphore from the EGU1
+/// interrupt, after calling it), after setting the timeout to the number of
+/// milliseconds that were left. The library relies on this to keep track of
+/// its own deadlines, and so do `modem::wait_for_lte_timeout` and
+/// `modem::next_notification`. If the timeout expires first, it should return
+/// `NRF_ETIMEDOUT`.
+///
+/// The built-in wait sleeps with `wfe` if SysTick is running with its
+/// interrupt enabled, as that wakes the core at least once per tick.
+/// Otherwise it has no way to keep time while asleep, so it busy-waits in
+/// one millisecond steps. Either way it returns early when the modem has
+/// something for us.
+pub fn set_timed_wait_impl(wait: Option<fn(&mut i32) -> i32>) {
+	let value = wait.map(|f| f as usize).unwrap_or(0);
+	TIMED_WAIT_IMPL.store(value, core::sync::atomic::Ordering::SeqCst);
+}
+
+/// Tell any `nrf_modem_os_timedwait` in progress that the library has
+/// handled an event from the modem, so it should stop waiting. Called from
+/// `application_irq_handler`.
+pub(crate) fn notify_event() {
+	MODEM_EVENT.store(true, core::sync::atomic::Ordering::SeqCst);
+	// A host has no `sev`, and nothing waiting in `wfe` either
+	#[cfg(target_arch = "arm")]
+	cortex_m::asm::sev();
+}
+
+/// Function required by BSD library. Waits until the library has handled an
+/// event from the modem (returning 0, with `*p_timeout_ms` updated to the
+/// time remaining) or the timeout expires (returning `NRF_ETIMEDOUT`).
+///
+/// A negative timeout means wait for the event however long it takes, like
+/// `K_FOREVER` in Zephyr.
 #[no_mangle]
-pub extern "C" fn nrf_modem_os_timedwait(_context: u32, p_timeout_ms: *const i32) -> i32 {
+pub extern "C" fn nrf_modem_os_timedwait(_context: u32, p_timeout_ms: *mut i32) -> i32 {
 	let timeout_ms = unsafe { *p_timeout_ms };
-	if timeout_ms < 0 {
-		// With Zephyr, negative timeouts pend on a semaphore with K_FOREVER.
-		// We can't do that here.
-		0i32
+	let wait = TIMED_WAIT_IMPL.load(core::sync::atomic::Ordering::SeqCst);
+	let result = if wait != 0 {
+		// NOTE(unsafe) - we only ever store a `fn(&mut i32) -> i32` in
+		// TIMED_WAIT_IMPL.
+		let wait: fn(&mut i32) -> i32 = unsafe { core::mem::transmute(wait) };
+		wait(unsafe { &mut *p_timeout_ms })
+	} else if timeout_ms < 0 {
+		wait_forever();
+		return 0;
 	} else {
-		// NRF9160 runs at 64 MHz, so this is close enough
-		cortex_m::asm::delay((timeout_ms as u32) * 64_000);
-		nrfxlib_sys::NRF_ETIMEDOUT as i32
+		match default_timed_wait(timeout_ms as u32) {
+			Some(remaining_ms) => {
+				unsafe {
+					*p_timeout_ms = remaining_ms as i32;
+				}
+				0
+			}
+			None => nrfxlib_sys::NRF_ETIMEDOUT as i32,
+		}
+	};
+	if timeout_ms >= 0 {
+		// On a timeout, all of it was used up
+		let remaining_ms = if result == 0 {
+			unsafe { *p_timeout_ms }.clamp(0, timeout_ms)
+		} else {
+			0
+		};
+		WAITED_MS.fetch_add(
+			(timeout_ms - remaining_ms) as u32,
+			core::sync::atomic::Ordering::SeqCst,
+		);
 	}
+	result
 }
 
-/// Function required by BSD library
+/// Get the total number of milliseconds `nrf_modem_os_timedwait` has spent
+/// waiting with a timeout. This wraps, so compare two readings with
+/// `wrapping_sub`.
+///
+/// It is the only clock we have. Waits with no timeout aren't counted.
+pub(crate) fn waited_ms() -> u32 {
+	WAITED_MS.load(core::sync::atomic::Ordering::SeqCst)
+}
+
+/// Function required by BSD library. Passes the trace data to the sink set
+/// with `set_trace_sink`, or if there isn't one, stores it so it can be
+/// collected later with `read_trace`.
+///
+/// If the buffer is full, the bytes which do not fit are dropped.
 #[no_mangle]
-pub extern "C" fn nrf_modem_os_trace_put(_data: *const u8, _len: u32) -> i32 {
-	// Do nothing
+pub extern "C" fn nrf_modem_os_trace_put(data: *const u8, len: u32) -> i32 {
+	if data.is_null() {
+		return 0;
+	}
+	let data = unsafe { core::slice::from_raw_parts(data, len as usize) };
+	let sink = TRACE_SINK.load(core::sync::atomic::Ordering::SeqCst);
+	if sink != 0 {
+		// NOTE(unsafe) - we only ever store a `fn(&[u8])` in TRACE_SINK.
+		let sink: fn(&[u8]) = unsafe { core::mem::transmute(sink) };
+		sink(data);
+		return 0;
+	}
+	cortex_m::interrupt::free(|cs| {
+		let mut trace_buffer = TRACE_BUFFER.borrow(cs).borrow_mut();
+		for (idx, byte) in data.iter().enumerate() {
+			if trace_buffer.push_back(*byte).is_err() {
+				let dropped = (data.len() - idx) as u32;
+				TRACE_BYTES_DROPPED.fetch_add(dropped, core::sync::atomic::Ordering::SeqCst);
+				break;
+			}
+		}
+	});
 	0
 }
 
+/// Move buffered modem trace bytes into the given buffer. Returns the number
+/// of bytes copied.
+pub(crate) fn read_trace(buf: &mut [u8]) -> usize {
+	cortex_m::interrupt::free(|cs| {
+		let mut trace_buffer = TRACE_BUFFER.borrow(cs).borrow_mut();
+		let mut count = 0;
+		for slot in buf.iter_mut() {
+			match trace_buffer.pop_front() {
+				Some(byte) => {
+					*slot = byte;
+					count += 1;
+				}
+				None => break,
+			}
+		}
+		count
+	})
+}
+
+/// Send modem trace bytes to `sink` as they arrive, or with `None`, go back
+/// to buffering them for `read_trace`.
+pub(crate) fn set_trace_sink(sink: Option<fn(&[u8])>) {
+	let value = sink.map(|f| f as usize).unwrap_or(0);
+	TRACE_SINK.store(value, core::sync::atomic::Ordering::SeqCst);
+}
+
+/// Return the number of trace bytes dropped because the trace buffer was
+/// full, and reset the count.
+pub(crate) fn take_trace_bytes_dropped() -> u32 {
+	TRACE_BYTES_DROPPED.swap(0, core::sync::atomic::Ordering::SeqCst)
+}
+
 /// Function required by BSD library
 #[no_mangle]
 pub extern "C" fn nrf_modem_irrecoverable_error_handler(err: u32) -> ! {
@@ -235,9 +553,21 @@ pub extern "C" fn nrfx_ipc_init(
 }
 
 /// Function for uninitializing the IPC module.
+///
+/// Undoes `nrfx_ipc_init` and `nrfx_ipc_config_load`, so the library can be
+/// started again after `shutdown`.
 #[no_mangle]
 pub extern "C" fn nrfx_ipc_uninit() {
-	unimplemented!();
+	debug!("nrfx_ipc_uninit()");
+	cortex_m::peripheral::NVIC::mask(nrf9160_pac::Interrupt::IPC);
+	unsafe {
+		// Disable every receive event interrupt
+		(*nrf9160_pac::IPC_NS::ptr())
+			.intenclr
+			.write(|w| w.bits(0xFFFF_FFFF));
+	}
+	IPC_HANDLER.store(0, core::sync::atomic::Ordering::SeqCst);
+	IPC_CONTEXT.store(0, core::sync::atomic::Ordering::SeqCst);
 }
 
 /// Allocate some memory from the given heap.
@@ -263,11 +593,20 @@ unsafe fn generic_alloc(num_bytes_requested: usize, heap: &crate::WrappedHeap) -
 					result = real_ptr.add(sizeof_usize);
 				}
 				Err(_e) => {
-					// Ignore
+					// Reported below
 				}
 			}
 		}
 	});
+	if result.is_null() {
+		ALLOCATION_FAILURES.fetch_add(1, core::sync::atomic::Ordering::SeqCst);
+		let hook = OOM_HOOK.load(core::sync::atomic::Ordering::SeqCst);
+		if hook != 0 {
+			// NOTE(unsafe) - we only ever store a `fn(usize)` in OOM_HOOK.
+			let hook: fn(usize) = core::mem::transmute(hook);
+			hook(num_bytes_requested);
+		}
+	}
 	result
 }
 
@@ -294,6 +633,54 @@ unsafe fn generic_free(ptr: *mut u8, heap: &crate::WrappedHeap) {
 	});
 }
 
+/// Wait for `notify_event`, however long it takes. We don't need to keep
+/// time, so we can always sleep - it is called from an interrupt, which
+/// wakes us.
+fn wait_forever() {
+	while !MODEM_EVENT.swap(false, core::sync::atomic::Ordering::SeqCst) {
+		crate::backend::wait_for_event();
+	}
+}
+
+/// Wait up to `timeout_ms` milliseconds for `notify_event`. Returns the
+/// number of milliseconds left if it was called, or `None` if we timed out.
+fn default_timed_wait(timeout_ms: u32) -> Option<u32> {
+	// NOTE(unsafe) - we only read SysTick, never change it.
+	let syst = unsafe { &*cortex_m::peripheral::SYST::PTR };
+	let ticking =
+		(syst.csr.read() & SYST_CSR_ENABLE_TICKINT_CLKSOURCE) == SYST_CSR_ENABLE_TICKINT_CLKSOURCE;
+	let total_cycles = u64::from(timeout_ms) * u64::from(CYCLES_PER_MS);
+	let mut elapsed_cycles = 0u64;
+	let mut last_count = syst.cvr.read();
+	loop {
+		if MODEM_EVENT.swap(false, core::sync::atomic::Ordering::SeqCst) {
+			let remaining = total_cycles.saturating_sub(elapsed_cycles);
+			return Some((remaining / u64::from(CYCLES_PER_MS)) as u32);
+		}
+		if elapsed_cycles >= total_cycles {
+			return None;
+		}
+		if ticking {
+			// Any interrupt wakes us, and SysTick interrupts at least once
+			// per wrap, so we can't miss a wrap of the counter.
+			crate::backend::wait_for_event();
+			let count = syst.cvr.read();
+			let reload = syst.rvr.read() & 0x00FF_FFFF;
+			// SysTick counts down to zero, then reloads
+			let delta = if count <= last_count {
+				last_count - count
+			} else {
+				last_count + (reload + 1 - count)
+			};
+			elapsed_cycles += u64::from(delta);
+			last_count = count;
+		} else {
+			cortex_m::asm::delay(CYCLES_PER_MS);
+			elapsed_cycles += u64::from(CYCLES_PER_MS);
+		}
+	}
+}
+
 /// Call this when we have an IPC IRQ. Not `extern C` as its not called by the
 /// library, only our interrupt handler code.
 pub unsafe fn ipc_irq_handler() {
@@ -310,7 +697,66 @@ pub unsafe fn ipc_irq_handler() {
 
 	// Execute interrupt handler to provide information about events to app
 	let handler_addr = IPC_HANDLER.load(core::sync::atomic::Ordering::SeqCst);
+	if handler_addr == 0 {
+		// `nrfx_ipc_uninit` has been called
+		return;
+	}
 	let handler = core::mem::transmute::<usize, NrfxIpcHandler>(handler_addr);
 	let context = IPC_CONTEXT.load(core::sync::atomic::Ordering::SeqCst);
 	(handler)(events_map, context as *mut u8);
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::backend::mock;
+
+	#[test]
+	fn negative_timeout_waits_for_event() {
+		mock::run(|modem| {
+			modem.wake_after(3);
+			let mut timeout_ms = -1;
+			assert_eq!(nrf_modem_os_timedwait(0, &mut timeout_ms), 0);
+			assert_eq!(modem.waits(), 3);
+		});
+	}
+
+	#[test]
+	fn custom_wait_reports_time_remaining() {
+		fn wait(timeout_ms: &mut i32) -> i32 {
+			assert_eq!(*timeout_ms, 100);
+			*timeout_ms = 40;
+			0
+		}
+		mock::run(|_| {
+			set_timed_wait_impl(Some(wait));
+			let mut timeout_ms = 100;
+			let result = nrf_modem_os_timedwait(0, &mut timeout_ms);
+			set_timed_wait_impl(None);
+			assert_eq!(result, 0);
+			assert_eq!(timeout_ms, 40);
+		});
+	}
+
+	#[test]
+	fn timed_waits_are_counted() {
+		fn wait(timeout_ms: &mut i32) -> i32 {
+			if *timeout_ms == 100 {
+				*timeout_ms = 40;
+				0
+			} else {
+				nrfxlib_sys::NRF_ETIMEDOUT as i32
+			}
+		}
+		mock::run(|_| {
+			set_timed_wait_impl(Some(wait));
+			let before = waited_ms();
+			let mut timeout_ms = 100;
+			nrf_modem_os_timedwait(0, &mut timeout_ms);
+			let mut timeout_ms = 25;
+			nrf_modem_os_timedwait(0, &mut timeout_ms);
+			set_timed_wait_impl(None);
+			assert_eq!(waited_ms().wrapping_sub(before), 60 + 25);
+		});
+	}
+}
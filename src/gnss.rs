@@ -3,7 +3,9 @@
 //! Global Navigation Satellite System (GNSS) related socket code.
 //!
 //! Currently only GPS is supported by the nRF9160 modem firmware, but they
-//! could add GLONASS, Galileo and Beidou in the future.
+//! could add GLONASS, Galileo and Beidou in the future. The 1.5.1 release of
+//! the Nordic library only offers `NRF_SO_GNSS_SYSTEM`, which accepts GPS
+//! alone, so there is no way to enable QZSS through this crate yet.
 //!
 //! Copyright (c) 42 Technology Ltd 2019
 //!
@@ -22,6 +24,7 @@
 
 use super::{get_last_error, Error};
 use crate::raw::*;
+use core::cell::Cell;
 use nrfxlib_sys as sys;
 
 //******************************************************************************
@@ -30,7 +33,17 @@ use nrfxlib_sys as sys;
 
 /// Represents a connection to the GPS sub-system.
 #[derive(Debug)]
-pub struct GnssSocket(Socket);
+pub struct GnssSocket {
+	socket: Socket,
+	/// How many position frames without a valid fix we have seen since the
+	/// GNSS system was started.
+	frames_without_fix: Cell<u32>,
+	/// How many seconds it took to get the first valid fix since the GNSS
+	/// system was started, if we have had one.
+	time_to_first_fix: Cell<Option<u32>>,
+	/// Whether we have started the GNSS system and not yet stopped it.
+	running: Cell<bool>,
+}
 
 /// Represents a position or NMEA string from the GNSS subsystem
 #[derive(Clone)]
@@ -50,11 +63,11 @@ pub enum GnssData {
 }
 
 /// Specifies which NMEA fields you want from the GNSS sub-system.
-#[derive(Default, Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub struct NmeaMask(u16);
 
 /// The specific fields you can enable or disable in an `NmeaMask`.
-#[derive(Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 #[repr(u16)]
 pub enum NmeaField {
 	/// Enables Global Positioning System Fix Data.
@@ -69,12 +82,138 @@ pub enum NmeaField {
 	RecommendedMinimumSpecificFixData = sys::NRF_GNSS_NMEA_RMC_MASK as u16,
 }
 
+/// The assistance data the GNSS sub-system is asking for, decoded from a
+/// `GnssData::Agps` frame. See `GnssData::agps_request`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AgpsRequest {
+	/// Bit N is set if ephemeris data is needed for GPS satellite N + 1
+	pub sv_mask_ephemeris: u32,
+	/// Bit N is set if almanac data is needed for GPS satellite N + 1
+	pub sv_mask_almanac: u32,
+	/// Is ephemeris data needed for any satellite?
+	pub ephemeris: bool,
+	/// Is almanac data needed for any satellite?
+	pub almanac: bool,
+	/// Are GPS-UTC parameters needed?
+	pub utc: bool,
+	/// Are Klobuchar ionospheric correction parameters needed?
+	pub klobuchar: bool,
+	/// Are NeQuick ionospheric correction parameters needed?
+	pub nequick: bool,
+	/// Are GPS system time and satellite time-of-week needed?
+	pub system_time_and_sv_tow: bool,
+	/// Is an approximate position needed?
+	pub position: bool,
+	/// Is satellite integrity data needed?
+	pub integrity: bool,
+}
+
+/// The kinds of assistance data you can give the GNSS sub-system with
+/// `GnssSocket::write_agps`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u16)]
+pub enum AgpsDataType {
+	/// GPS-UTC parameters
+	UtcParameters = sys::NRF_GNSS_AGPS_UTC_PARAMETERS as u16,
+	/// Ephemeris data for one satellite
+	Ephemerides = sys::NRF_GNSS_AGPS_EPHEMERIDES as u16,
+	/// Almanac data for one satellite
+	Almanac = sys::NRF_GNSS_AGPS_ALMANAC as u16,
+	/// Klobuchar ionospheric correction parameters
+	KlobucharIonosphericCorrection = sys::NRF_GNSS_AGPS_KLOBUCHAR_IONOSPHERIC_CORRECTION as u16,
+	/// NeQuick ionospheric correction parameters
+	NequickIonosphericCorrection = sys::NRF_GNSS_AGPS_NEQUICK_IONOSPHERIC_CORRECTION as u16,
+	/// GPS system time and satellite time-of-week
+	GpsSystemClockAndTows = sys::NRF_GNSS_AGPS_GPS_SYSTEM_CLOCK_AND_TOWS as u16,
+	/// An approximate position
+	Location = sys::NRF_GNSS_AGPS_LOCATION as u16,
+	/// Satellite integrity data
+	Integrity = sys::NRF_GNSS_AGPS_INTEGRITY as u16,
+}
+
+/// A position on the Earth, in decimal degrees. `Display` gives you
+/// `"<latitude>, <longitude>"` to six decimal places.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Coordinate {
+	/// Latitude in degrees, where positive is North
+	pub latitude: f64,
+	/// Longitude in degrees, where positive is East
+	pub longitude: f64,
+}
+
+/// A `Coordinate` in degrees, minutes and seconds. See `Coordinate::to_dms`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CoordinateDms {
+	/// The latitude, with a hemisphere of `'N'` or `'S'`
+	pub latitude: Dms,
+	/// The longitude, with a hemisphere of `'E'` or `'W'`
+	pub longitude: Dms,
+}
+
+/// An angle in degrees, minutes and seconds. `Display` gives you something
+/// like `51°30'03.600"N`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Dms {
+	/// Whole degrees
+	pub degrees: u16,
+	/// Whole minutes (0 to 59)
+	pub minutes: u8,
+	/// Seconds (0 to less than 60), to the nearest millisecond of arc
+	pub seconds: f32,
+	/// One of `'N'`, `'S'`, `'E'` or `'W'`
+	pub hemisphere: char,
+}
+
+/// A UTC date and time from a GNSS fix. See `GnssData::datetime`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DateTime {
+	/// The year (e.g. 2021)
+	pub year: u16,
+	/// The month (1 to 12)
+	pub month: u8,
+	/// The day of the month (1 to 31)
+	pub day: u8,
+	/// The hour (0 to 23)
+	pub hour: u8,
+	/// The minute (0 to 59)
+	pub minute: u8,
+	/// The second (0 to 59)
+	pub second: u8,
+	/// The millisecond (0 to 999)
+	pub millisecond: u16,
+}
+
+/// Tells the GNSS sub-system how you are going to use it. See
+/// `GnssSocket::set_use_case`.
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct GnssUseCase {
+	/// Set this if you will start the GNSS system several times, so it can
+	/// use hot starts. Otherwise every start is treated as a cold start.
+	pub multiple_hot_starts: bool,
+	/// Set this to accept less accurate fixes, in return for fixes which
+	/// arrive sooner and use less power.
+	pub low_accuracy: bool,
+}
+
+/// The power saving modes of the GNSS sub-system. See
+/// `GnssSocket::set_power_save_mode`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[repr(u8)]
+pub enum GnssPowerSaveMode {
+	/// No power saving. This is the default.
+	Disabled = sys::NRF_GNSS_PSM_DISABLED as u8,
+	/// Duty-cycled tracking, keeping the fix accuracy as high as possible.
+	DutyCyclingPerformance = sys::NRF_GNSS_PSM_DUTY_CYCLING_PERFORMANCE as u8,
+	/// Duty-cycled tracking, using as little power as possible.
+	DutyCyclingPower = sys::NRF_GNSS_PSM_DUTY_CYCLING_POWER as u8,
+}
+
 /// Specifies which non-volatile fields you want to delete before starting the GNSS.
-#[derive(Default, Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub struct DeleteMask(u32);
 
 /// The specific fields you can enable or disable in a `DeleteMask`.
-#[derive(Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 #[repr(u32)]
 pub enum DeleteField {
 	/// Bit 0 denotes ephemerides data.
@@ -95,11 +234,112 @@ pub enum DeleteField {
 	LocalClockFrequencyOffset = 1 << 7,
 }
 
+/// How often the GNSS sub-system should produce a fix.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum FixMode {
+	/// Get one fix, then stop.
+	Single,
+	/// Get a fix every second.
+	Continuous,
+	/// Get a fix every `interval_s` seconds. The modem accepts 10 to 1800
+	/// seconds.
+	Periodic {
+		/// The number of seconds between each fix
+		interval_s: u16,
+	},
+}
+
+/// A serialisable copy of a `GnssData`. We can't derive serde traits on the
+/// Nordic types, so we copy the fields out into these instead.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+enum GnssDataRepr {
+	Nmea(heapless::String<83>),
+	Position(PvtRepr),
+	Agps(AgpsRepr),
+}
+
+/// A serialisable copy of `sys::nrf_gnss_pvt_data_frame_t`.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct PvtRepr {
+	latitude: f64,
+	longitude: f64,
+	altitude: f32,
+	accuracy: f32,
+	speed: f32,
+	heading: f32,
+	datetime: DateTimeRepr,
+	pdop: f32,
+	hdop: f32,
+	vdop: f32,
+	tdop: f32,
+	flags: u8,
+	sv: [SvRepr; sys::NRF_GNSS_MAX_SATELLITES as usize],
+}
+
+/// A serialisable copy of `sys::nrf_gnss_datetime_t`.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct DateTimeRepr {
+	year: u16,
+	month: u8,
+	day: u8,
+	hour: u8,
+	minute: u8,
+	seconds: u8,
+	ms: u16,
+}
+
+/// A serialisable copy of `sys::nrf_gnss_sv_t`.
+#[cfg(feature = "serde")]
+#[derive(Copy, Clone, serde::Serialize, serde::Deserialize)]
+struct SvRepr {
+	sv: u16,
+	cn0: u16,
+	elevation: i16,
+	azimuth: i16,
+	flags: u8,
+	signal: u8,
+}
+
+/// A serialisable copy of `sys::nrf_gnss_agps_data_frame_t`.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct AgpsRepr {
+	sv_mask_ephe: u32,
+	sv_mask_alm: u32,
+	data_flags: u32,
+}
+
 //******************************************************************************
 // Constants
 //******************************************************************************
 
-// None
+/// While searching for a fix, the GNSS sub-system sends a position frame once
+/// per second.
+const POSITION_FRAME_PERIOD_MS: u32 = 1000;
+
+/// Every `NmeaField`, in bit order.
+const ALL_NMEA_FIELDS: [NmeaField; 5] = [
+	NmeaField::GpsFixData,
+	NmeaField::LatLongTime,
+	NmeaField::DopAndActiveSatellites,
+	NmeaField::SatellitesInView,
+	NmeaField::RecommendedMinimumSpecificFixData,
+];
+
+/// Every `DeleteField`, in bit order.
+const ALL_DELETE_FIELDS: [DeleteField; 8] = [
+	DeleteField::Ephemerides,
+	DeleteField::Almanac,
+	DeleteField::IonosphericCorrection,
+	DeleteField::LastGoodFix,
+	DeleteField::TimeOfWeek,
+	DeleteField::WeekNumber,
+	DeleteField::LeapSecond,
+	DeleteField::LocalClockFrequencyOffset,
+];
 
 //******************************************************************************
 // Global Variables
@@ -125,29 +365,59 @@ impl GnssSocket {
 			SocketType::Datagram,
 			SocketProtocol::Gnss,
 		)?;
-		Ok(GnssSocket(skt))
+		Ok(GnssSocket {
+			socket: skt,
+			frames_without_fix: Cell::new(0),
+			time_to_first_fix: Cell::new(None),
+			running: Cell::new(false),
+		})
 	}
 
 	/// Deletes the specified information from non-volatile memory, then starts
 	/// the GNSS sub-system.
 	pub fn start(&self, delete_mask: DeleteMask) -> Result<(), Error> {
-		self.0
+		self.socket
 			.set_option(SocketOption::GnssStart(delete_mask.as_u32()))?;
+		self.frames_without_fix.set(0);
+		self.time_to_first_fix.set(None);
+		self.running.set(true);
 		Ok(())
 	}
 
 	/// Stop the GNSS system.
 	pub fn stop(&self) -> Result<(), Error> {
-		self.0.set_option(SocketOption::GnssStop)?;
+		self.socket.set_option(SocketOption::GnssStop)?;
+		self.running.set(false);
 		Ok(())
 	}
 
+	/// Returns true if the GNSS system has been started (with `start`) and not
+	/// yet stopped (with `stop`).
+	pub fn is_running(&self) -> bool {
+		self.running.get()
+	}
+
+	/// Set how often the GNSS sub-system produces a fix. The default is
+	/// `FixMode::Continuous`.
+	pub fn set_fix_mode(&self, mode: FixMode) -> Result<(), Error> {
+		self.socket
+			.set_option(SocketOption::GnssFixInterval(mode.as_interval()))?;
+		Ok(())
+	}
+
+	/// Get how often the GNSS sub-system produces a fix.
+	pub fn get_fix_mode(&self) -> Result<FixMode, Error> {
+		self.get_fix_interval().map(FixMode::from_interval)
+	}
+
 	/// Set the Fix Interval.
 	///
 	/// Defines the interval between each fix in seconds. The default is 1. A
 	/// value of 0 means single-fix mode.
+	#[deprecated(note = "a value of 0 means single-fix, not 1 Hz; use `set_fix_mode`")]
 	pub fn set_fix_interval(&self, interval: u16) -> Result<(), Error> {
-		self.0.set_option(SocketOption::GnssFixInterval(interval))?;
+		self.socket
+			.set_option(SocketOption::GnssFixInterval(interval))?;
 		Ok(())
 	}
 
@@ -156,7 +426,8 @@ impl GnssSocket {
 	/// Defines how long (in seconds) the receiver should try to get a fix. The
 	/// default is 60 seconds and a value of 0 means wait forever.
 	pub fn set_fix_retry(&self, interval: u16) -> Result<(), Error> {
-		self.0.set_option(SocketOption::GnssFixRetry(interval))?;
+		self.socket
+			.set_option(SocketOption::GnssFixRetry(interval))?;
 		Ok(())
 	}
 
@@ -164,17 +435,16 @@ impl GnssSocket {
 	///
 	/// See `set_fix_interval` for more information.
 	pub fn get_fix_interval(&self) -> Result<u16, Error> {
+		self.socket.start_operation()?;
 		let mut length: u32 = core::mem::size_of::<u16>() as u32;
 		let mut value = 0u16;
-		let result = unsafe {
-			sys::nrf_getsockopt(
-				self.fd,
-				sys::NRF_SOL_GNSS as i32,
-				sys::NRF_SO_GNSS_FIX_INTERVAL as i32,
-				&mut value as *mut u16 as *mut sys::ctypes::c_void,
-				&mut length as *mut u32,
-			)
-		};
+		let result = crate::backend::get_option(
+			self.fd,
+			sys::NRF_SOL_GNSS as i32,
+			sys::NRF_SO_GNSS_FIX_INTERVAL as i32,
+			&mut value as *mut u16 as *mut sys::ctypes::c_void,
+			&mut length,
+		);
 		if result < 0 {
 			Err(Error::Nordic("fix_interval", result, get_last_error()))
 		} else {
@@ -186,17 +456,16 @@ impl GnssSocket {
 	///
 	/// See `set_fix_retry` for more information.
 	pub fn get_fix_retry(&self) -> Result<u16, Error> {
+		self.socket.start_operation()?;
 		let mut length: u32 = core::mem::size_of::<u16>() as u32;
 		let mut value = 0u16;
-		let result = unsafe {
-			sys::nrf_getsockopt(
-				self.fd,
-				sys::NRF_SOL_GNSS as i32,
-				sys::NRF_SO_GNSS_FIX_RETRY as i32,
-				&mut value as *mut u16 as *mut sys::ctypes::c_void,
-				&mut length as *mut u32,
-			)
-		};
+		let result = crate::backend::get_option(
+			self.fd,
+			sys::NRF_SOL_GNSS as i32,
+			sys::NRF_SO_GNSS_FIX_RETRY as i32,
+			&mut value as *mut u16 as *mut sys::ctypes::c_void,
+			&mut length,
+		);
 		if result < 0 {
 			Err(Error::Nordic("fix_retry", result, get_last_error()))
 		} else {
@@ -211,7 +480,7 @@ impl GnssSocket {
 	/// If you pass a default `NmeaMask`, you get no NMEA frames (only
 	/// `GnssData::Position` or `GnssData::Agps` frames).
 	pub fn set_nmea_mask(&self, mask: NmeaMask) -> Result<(), Error> {
-		self.0
+		self.socket
 			.set_option(SocketOption::GnssNmeaMask(mask.as_u16()))?;
 		Ok(())
 	}
@@ -220,17 +489,16 @@ impl GnssSocket {
 	///
 	/// See `set_nmea_mask`.
 	pub fn get_nmea_mask(&self) -> Result<NmeaMask, Error> {
+		self.socket.start_operation()?;
 		let mut length: u32 = core::mem::size_of::<u16>() as u32;
 		let mut value = 0u16;
-		let result = unsafe {
-			sys::nrf_getsockopt(
-				self.fd,
-				sys::NRF_SOL_GNSS as i32,
-				sys::NRF_SO_GNSS_NMEA_MASK as i32,
-				&mut value as *mut u16 as *mut sys::ctypes::c_void,
-				&mut length as *mut u32,
-			)
-		};
+		let result = crate::backend::get_option(
+			self.fd,
+			sys::NRF_SOL_GNSS as i32,
+			sys::NRF_SO_GNSS_NMEA_MASK as i32,
+			&mut value as *mut u16 as *mut sys::ctypes::c_void,
+			&mut length,
+		);
 		if result < 0 {
 			Err(Error::Nordic("nmea_mask", result, get_last_error()))
 		} else {
@@ -238,25 +506,102 @@ impl GnssSocket {
 		}
 	}
 
+	/// Set the elevation mask.
+	///
+	/// Satellites less than `degrees` above the horizon will not be used for
+	/// fixes. Signals from low satellites travel further through the
+	/// atmosphere, so ignoring them can improve accuracy.
+	pub fn set_elevation_mask(&self, degrees: u8) -> Result<(), Error> {
+		self.socket
+			.set_option(SocketOption::GnssElevationMask(degrees))?;
+		Ok(())
+	}
+
+	/// Set the use case.
+	///
+	/// The GNSS system must be stopped when you call this.
+	pub fn set_use_case(&self, use_case: GnssUseCase) -> Result<(), Error> {
+		self.socket
+			.set_option(SocketOption::GnssUseCase(use_case.as_u8()))?;
+		Ok(())
+	}
+
+	/// Set the power saving mode.
+	///
+	/// Duty cycling reduces current draw while tracking, at some cost to fix
+	/// quality. It only applies in continuous tracking mode (see
+	/// `set_fix_mode`). The GNSS system must be stopped when you call this.
+	pub fn set_power_save_mode(&self, mode: GnssPowerSaveMode) -> Result<(), Error> {
+		self.socket
+			.set_option(SocketOption::GnssPowerSaveMode(mode as u8))?;
+		Ok(())
+	}
+
+	/// Give the GNSS sub-system some assistance data.
+	///
+	/// `data` must be the Nordic structure for the given `data_type` (e.g. a
+	/// `nrf_gnss_agps_data_ephemeris_t` for `AgpsDataType::Ephemerides`),
+	/// as bytes. You would normally get this from an A-GPS service, in
+	/// response to the `GnssData::Agps` frames the sub-system sends when it
+	/// wants help. Assistance data can greatly reduce the time to first fix.
+	pub fn write_agps(&self, data_type: AgpsDataType, data: &[u8]) -> Result<(), Error> {
+		self.socket.start_operation()?;
+		let data_type = data_type as sys::nrf_gnss_agps_data_type_t;
+		// The Nordic library takes the data type in place of the destination
+		// address.
+		let result = crate::backend::send_agps(self.socket.fd, data, data_type);
+		if result < 0 {
+			Err(Error::Nordic("write_agps", result as i32, get_last_error()))
+		} else {
+			Ok(())
+		}
+	}
+
 	/// Get a fix from the GNSS system.
 	///
 	/// Performs a read on the GNSS socket. The Nordic library determines which
 	/// frame type you get on each read. You will get `None` if there is no fix
 	/// to be read.
 	pub fn get_fix(&self) -> Result<Option<GnssData>, Error> {
+		self.socket.start_operation()?;
 		let mut frame = core::mem::MaybeUninit::<sys::nrf_gnss_data_frame_t>::uninit();
-		let buffer_size = core::mem::size_of::<sys::nrf_gnss_data_frame_t>();
-		let result = unsafe {
-			sys::nrf_recv(
-				self.0.fd,
-				frame.as_mut_ptr() as *mut sys::ctypes::c_void,
-				buffer_size as u32,
-				sys::NRF_MSG_DONTWAIT as i32,
-			)
-		};
+		let result = crate::backend::recv(
+			self.socket.fd,
+			frame_bytes(&mut frame),
+			sys::NRF_MSG_DONTWAIT as i32,
+		);
 		self.process_fix(result, frame)
 	}
 
+	/// Get a fix from the GNSS system, once `poll` has told you there is one
+	/// waiting.
+	///
+	/// Pass in the result from this socket's `PollEntry`. If it isn't
+	/// readable, you get `None` without reading from the socket. Otherwise
+	/// this does a single non-blocking read, like `get_fix`. This lets you
+	/// wait on GNSS and your other sockets with one call to `poll`:
+	///
+	/// ```ignore
+	/// use nrfxlib::{at::AtSocket, gnss::GnssSocket, PollEntry, PollFlags};
+	/// let at_socket = AtSocket::new()?;
+	/// let gnss = GnssSocket::new()?;
+	/// let mut poll_list = [
+	///     PollEntry::new(&at_socket, PollFlags::Read),
+	///     PollEntry::new(&gnss, PollFlags::Read),
+	/// ];
+	/// nrfxlib::poll(&mut poll_list, 1000)?;
+	/// if let Some(fix) = gnss.get_fix_after_poll(poll_list[1].result())? {
+	///     // Handle the fix
+	/// }
+	/// ```
+	pub fn get_fix_after_poll(&self, poll_result: PollResult) -> Result<Option<GnssData>, Error> {
+		if poll_result.is_readable() {
+			self.get_fix()
+		} else {
+			Ok(None)
+		}
+	}
+
 	/// Wait for a fix from the GNSS system.
 	///
 	/// Performs a read on the GNSS socket and returns either a
@@ -264,19 +609,120 @@ impl GnssSocket {
 	/// `GnssData::Position`. The Nordic library determines which you get on
 	/// each read. You will get `None` if there is no fix to be read.
 	pub fn get_fix_blocking(&self) -> Result<Option<GnssData>, Error> {
+		self.socket.start_operation()?;
 		let mut frame = core::mem::MaybeUninit::<sys::nrf_gnss_data_frame_t>::uninit();
-		let buffer_size = core::mem::size_of::<sys::nrf_gnss_data_frame_t>();
-		let result = unsafe {
-			sys::nrf_recv(
-				self.0.fd,
-				frame.as_mut_ptr() as *mut sys::ctypes::c_void,
-				buffer_size as u32,
-				0,
-			)
-		};
+		let result = crate::backend::recv(self.socket.fd, frame_bytes(&mut frame), 0);
 		self.process_fix(result, frame)
 	}
 
+	/// Read every frame currently waiting on the GNSS socket, and write each
+	/// NMEA sentence to `sink`, followed by `\r\n`. Position and AGPS frames
+	/// are discarded.
+	///
+	/// Returns once there are no more frames to read. This is useful for
+	/// forwarding NMEA to a host over a UART.
+	pub fn pipe_nmea<W>(&self, sink: &mut W) -> Result<(), Error>
+	where
+		W: core::fmt::Write,
+	{
+		while let Some(fix) = self.get_fix()? {
+			if let Some(nmea_str) = fix.as_nmea() {
+				write!(sink, "{}\r\n", nmea_str)?;
+			}
+		}
+		Ok(())
+	}
+
+	/// Get the time it took to get the first valid fix after the GNSS system
+	/// was last started.
+	///
+	/// The Nordic library does not report this, so we work it out by counting
+	/// the position frames received without a valid fix. These arrive once
+	/// per second, so the result has a resolution of one second, and only
+	/// counts frames that have actually been read from this socket. You will
+	/// get `None` if there has been no valid fix since `start` was called.
+	pub fn time_to_first_fix(&self) -> Option<core::time::Duration> {
+		self.time_to_first_fix
+			.get()
+			.map(|secs| core::time::Duration::from_secs(u64::from(secs)))
+	}
+
+	/// Get a single valid fix, then stop the GNSS system.
+	///
+	/// Puts the GNSS system in single-fix mode, deletes the specified
+	/// information from non-volatile memory, and starts it. This function
+	/// then blocks until a valid `GnssData::Position` frame arrives, which is
+	/// returned. Any NMEA or AGPS frames received in the meantime are
+	/// discarded.
+	///
+	/// If no valid fix arrives within `timeout`, `Error::Timeout` is
+	/// returned. We have no clock of our own, so time spent searching is
+	/// measured by counting the (invalid) position frames the GNSS system
+	/// sends once per second, and by the time spent waiting in `poll`. The
+	/// GNSS system is also told to give up after `timeout` (rounded up to the
+	/// nearest second).
+	///
+	/// The GNSS system is stopped before this function returns, whether or
+	/// not a fix was obtained.
+	pub fn single_fix(
+		&self,
+		delete_mask: DeleteMask,
+		timeout: core::time::Duration,
+	) -> Result<GnssData, Error> {
+		let timeout_ms = timeout.as_millis().min(u128::from(u32::MAX)) as u32;
+		let retry_secs = timeout_ms.div_ceil(1000).clamp(1, u32::from(u16::MAX)) as u16;
+		self.set_fix_mode(FixMode::Single)?;
+		self.set_fix_retry(retry_secs)?;
+		self.start(delete_mask)?;
+		let result = self.wait_for_valid_fix(timeout_ms);
+		match result {
+			Ok(_) => self.stop()?,
+			Err(_) => {
+				let _ = self.stop();
+			}
+		}
+		result
+	}
+
+	/// Wait for a valid position frame to arrive. See `single_fix`.
+	fn wait_for_valid_fix(&self, timeout_ms: u32) -> Result<GnssData, Error> {
+		let mut remaining_ms = timeout_ms;
+		while remaining_ms > 0 {
+			let wait_ms = remaining_ms.min(i32::MAX as u32) as i32;
+			let mut poll_list = [PollEntry::new(self, PollFlags::Read)];
+			if poll(&mut poll_list, wait_ms)? == 0 {
+				// Nothing arrived in the time we waited
+				remaining_ms -= wait_ms as u32;
+				continue;
+			}
+			match self.get_fix()? {
+				Some(fix) if fix.is_valid() => return Ok(fix),
+				Some(GnssData::Position(_)) => {
+					// Still searching
+					remaining_ms = remaining_ms.saturating_sub(POSITION_FRAME_PERIOD_MS);
+				}
+				_ => {
+					// NMEA or AGPS data, or nothing - keep waiting
+				}
+			}
+		}
+		Err(Error::Timeout)
+	}
+
+	/// Update our time-to-first-fix measurement with a newly received
+	/// position frame. See `time_to_first_fix`.
+	fn record_fix_time(&self, fix: &GnssData) {
+		if self.time_to_first_fix.get().is_none() {
+			if fix.is_valid() {
+				self.time_to_first_fix
+					.set(Some(self.frames_without_fix.get()));
+			} else {
+				self.frames_without_fix
+					.set(self.frames_without_fix.get().saturating_add(1));
+			}
+		}
+	}
+
 	/// Parse the data returned from a GNSS socket read.
 	///
 	/// We get either an NMEA frame, a Position frame, or an AGPS frame. We
@@ -309,7 +755,9 @@ impl GnssSocket {
 					// We have frame.pvt
 					// NOTE(unsafe) - we have to trust that the Nordic library has given us enough bytes for the frame.
 					let pvt = unsafe { frame.__bindgen_anon_1.pvt };
-					Ok(Some(GnssData::Position(pvt)))
+					let fix = GnssData::Position(pvt);
+					self.record_fix_time(&fix);
+					Ok(Some(fix))
 				} else if frame.data_id as u32 == sys::NRF_GNSS_NMEA_DATA_ID {
 					// We have frame.nmea
 					let nmea = unsafe { &frame.__bindgen_anon_1.nmea[..] };
@@ -348,30 +796,45 @@ impl GnssSocket {
 impl Pollable for GnssSocket {
 	/// Get the underlying socket ID for this socket.
 	fn get_fd(&self) -> i32 {
-		self.0.fd
+		self.socket.fd
 	}
 }
 
 impl Drop for GnssSocket {
 	fn drop(&mut self) {
-		let _ = self.stop();
+		if self.is_running() {
+			let _ = self.stop();
+		}
 	}
 }
 
 impl core::ops::Deref for GnssSocket {
 	type Target = Socket;
 	fn deref(&self) -> &Socket {
-		&self.0
+		&self.socket
 	}
 }
 
 impl core::ops::DerefMut for GnssSocket {
 	fn deref_mut(&mut self) -> &mut Socket {
-		&mut self.0
+		&mut self.socket
 	}
 }
 
 impl GnssData {
+	/// Get the NMEA sentence, if this is an NMEA frame.
+	///
+	/// Returns `None` for any other kind of frame, or if `length` has been
+	/// changed so it no longer covers valid UTF-8.
+	pub fn as_nmea(&self) -> Option<&str> {
+		match self {
+			GnssData::Nmea { buffer, length } => buffer
+				.get(0..*length)
+				.and_then(|s| core::str::from_utf8(s).ok()),
+			_ => None,
+		}
+	}
+
 	/// Returns true if this fix is valid (i.e. is a position frame, AND has the valid flag set).
 	pub fn is_valid(&self) -> bool {
 		match self {
@@ -380,6 +843,194 @@ impl GnssData {
 			GnssData::Agps { .. } => false,
 		}
 	}
+
+	/// Decode the assistance data request in an AGPS frame.
+	///
+	/// Returns `None` if this is not an AGPS frame.
+	pub fn agps_request(&self) -> Option<AgpsRequest> {
+		match self {
+			GnssData::Agps(a) => {
+				// The request constants are bit numbers, not masks
+				let flag = |bit: u32| (a.data_flags & (1 << bit)) != 0;
+				Some(AgpsRequest {
+					sv_mask_ephemeris: a.sv_mask_ephe,
+					sv_mask_almanac: a.sv_mask_alm,
+					ephemeris: a.sv_mask_ephe != 0,
+					almanac: a.sv_mask_alm != 0,
+					utc: flag(sys::NRF_GNSS_AGPS_GPS_UTC_REQUEST),
+					klobuchar: flag(sys::NRF_GNSS_AGPS_KLOBUCHAR_REQUEST),
+					nequick: flag(sys::NRF_GNSS_AGPS_NEQUICK_REQUEST),
+					system_time_and_sv_tow: flag(sys::NRF_GNSS_AGPS_SYS_TIME_AND_SV_TOW_REQUEST),
+					position: flag(sys::NRF_GNSS_AGPS_POSITION_REQUEST),
+					integrity: flag(sys::NRF_GNSS_AGPS_INTEGRITY_REQUEST),
+				})
+			}
+			_ => None,
+		}
+	}
+
+	/// Get the position of this fix.
+	///
+	/// Returns `None` if this is not a valid position frame.
+	pub fn coordinate(&self) -> Option<Coordinate> {
+		match self {
+			GnssData::Position(p) if self.is_valid() => Some(Coordinate {
+				latitude: p.latitude,
+				longitude: p.longitude,
+			}),
+			_ => None,
+		}
+	}
+
+	/// Get the latitude of this fix, in degrees. Positive is North.
+	///
+	/// Returns `None` if this is not a valid position frame.
+	pub fn latitude(&self) -> Option<f64> {
+		self.valid_pvt().map(|p| p.latitude)
+	}
+
+	/// Get the longitude of this fix, in degrees. Positive is East.
+	///
+	/// Returns `None` if this is not a valid position frame.
+	pub fn longitude(&self) -> Option<f64> {
+		self.valid_pvt().map(|p| p.longitude)
+	}
+
+	/// Get the altitude of this fix, in metres above the WGS-84 ellipsoid.
+	///
+	/// Returns `None` if this is not a valid position frame.
+	pub fn altitude(&self) -> Option<f32> {
+		self.valid_pvt().map(|p| p.altitude)
+	}
+
+	/// Get the horizontal speed of this fix, in metres per second.
+	///
+	/// Returns `None` if this is not a valid position frame.
+	pub fn speed(&self) -> Option<f32> {
+		self.valid_pvt().map(|p| p.speed)
+	}
+
+	/// Get the heading of this fix, in degrees clockwise from North.
+	///
+	/// Returns `None` if this is not a valid position frame.
+	pub fn heading(&self) -> Option<f32> {
+		self.valid_pvt().map(|p| p.heading)
+	}
+
+	/// Get the UTC date and time of this fix.
+	///
+	/// Returns `None` if this is not a valid position frame.
+	pub fn datetime(&self) -> Option<DateTime> {
+		self.valid_pvt().map(|p| DateTime {
+			year: p.datetime.year,
+			month: p.datetime.month,
+			day: p.datetime.day,
+			hour: p.datetime.hour,
+			minute: p.datetime.minute,
+			second: p.datetime.seconds,
+			millisecond: p.datetime.ms,
+		})
+	}
+
+	/// Get the number of satellites the GNSS sub-system is tracking.
+	///
+	/// Returns `None` if this is not a position frame. The frame doesn't have
+	/// to hold a valid fix.
+	pub fn num_satellites_tracked(&self) -> Option<usize> {
+		match self {
+			GnssData::Position(p) => Some(p.sv.iter().filter(|sv| sv.sv != 0).count()),
+			_ => None,
+		}
+	}
+
+	/// Get the number of satellites used to calculate this fix.
+	///
+	/// Returns `None` if this is not a position frame. The frame doesn't have
+	/// to hold a valid fix.
+	pub fn num_satellites_used(&self) -> Option<usize> {
+		match self {
+			GnssData::Position(p) => Some(
+				p.sv.iter()
+					.filter(|sv| {
+						sv.sv != 0 && (sv.flags & sys::NRF_GNSS_SV_FLAG_USED_IN_FIX as u8) != 0
+					})
+					.count(),
+			),
+			_ => None,
+		}
+	}
+
+	/// Get the time of this fix, as the number of seconds since 1970-01-01
+	/// 00:00:00 UTC. The GNSS sub-system reports UTC, so no leap second
+	/// correction is required.
+	///
+	/// Returns `None` if this is not a valid position frame.
+	pub fn unix_timestamp(&self) -> Option<i64> {
+		match self {
+			GnssData::Position(p) if self.is_valid() => {
+				let dt = &p.datetime;
+				let days =
+					days_since_epoch(i64::from(dt.year), i64::from(dt.month), i64::from(dt.day));
+				Some(
+					days * 86_400
+						+ i64::from(dt.hour) * 3600
+						+ i64::from(dt.minute) * 60
+						+ i64::from(dt.seconds),
+				)
+			}
+			_ => None,
+		}
+	}
+}
+
+impl core::fmt::Display for DateTime {
+	/// Formats as ISO 8601, e.g. `2021-03-04T05:06:07.089Z`.
+	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+		write!(
+			f,
+			"{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+			self.year, self.month, self.day, self.hour, self.minute, self.second, self.millisecond
+		)
+	}
+}
+
+impl Coordinate {
+	/// Convert to degrees, minutes and seconds, with hemisphere letters
+	/// instead of signs. The equator counts as North and the prime meridian
+	/// as East.
+	pub fn to_dms(&self) -> CoordinateDms {
+		CoordinateDms {
+			latitude: Dms::new(self.latitude, 'N', 'S'),
+			longitude: Dms::new(self.longitude, 'E', 'W'),
+		}
+	}
+}
+
+impl core::fmt::Display for Coordinate {
+	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+		write!(
+			f,
+			"{:.6}, {:.6}",
+			without_negative_zero(self.latitude),
+			without_negative_zero(self.longitude)
+		)
+	}
+}
+
+impl core::fmt::Display for CoordinateDms {
+	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+		write!(f, "{} {}", self.latitude, self.longitude)
+	}
+}
+
+impl core::fmt::Display for Dms {
+	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+		write!(
+			f,
+			"{}\u{b0}{:02}'{:06.3}\"{}",
+			self.degrees, self.minutes, self.seconds, self.hemisphere
+		)
+	}
 }
 
 impl core::fmt::Debug for GnssData {
@@ -404,6 +1055,31 @@ impl NmeaMask {
 		NmeaMask(0)
 	}
 
+	/// Create a new NmeaMask, which selects every NMEA field.
+	pub fn all() -> Self {
+		ALL_NMEA_FIELDS
+			.iter()
+			.fold(NmeaMask::new(), |mask, field| mask.set(*field))
+	}
+
+	/// Returns true if no NMEA fields are selected.
+	pub fn is_empty(self) -> bool {
+		self.0 == 0
+	}
+
+	/// Returns true if the given NMEA field is selected.
+	pub fn contains(self, field: NmeaField) -> bool {
+		(self.0 & field.value()) != 0
+	}
+
+	/// Iterate through the NMEA fields selected in this mask.
+	pub fn iter(self) -> impl Iterator<Item = NmeaField> {
+		ALL_NMEA_FIELDS
+			.iter()
+			.cloned()
+			.filter(move |field| self.contains(*field))
+	}
+
 	/// Enable a particular NMEA field type in this mask.
 	pub fn set(self, field: NmeaField) -> Self {
 		NmeaMask(self.0 | field.value())
@@ -427,12 +1103,52 @@ impl NmeaField {
 	}
 }
 
+impl GnssUseCase {
+	/// Convert to an integer, for the socket to consume.
+	fn as_u8(self) -> u8 {
+		let mut value = 0;
+		if self.multiple_hot_starts {
+			value |= sys::NRF_GNSS_USE_CASE_MULTIPLE_HOT_START as u8;
+		}
+		if self.low_accuracy {
+			value |= sys::NRF_GNSS_USE_CASE_LOW_ACCURACY as u8;
+		}
+		value
+	}
+}
+
 impl DeleteMask {
 	/// Create a new DeleteMask, which selects nothing to be deleted.
 	pub fn new() -> Self {
 		DeleteMask(0)
 	}
 
+	/// Create a new DeleteMask, which selects everything to be deleted (i.e.
+	/// a cold start).
+	pub fn all() -> Self {
+		ALL_DELETE_FIELDS
+			.iter()
+			.fold(DeleteMask::new(), |mask, field| mask.set(*field))
+	}
+
+	/// Returns true if nothing is marked for deletion.
+	pub fn is_empty(self) -> bool {
+		self.0 == 0
+	}
+
+	/// Returns true if the given field is marked for deletion.
+	pub fn contains(self, field: DeleteField) -> bool {
+		(self.0 & field.value()) != 0
+	}
+
+	/// Iterate through the fields marked for deletion in this mask.
+	pub fn iter(self) -> impl Iterator<Item = DeleteField> {
+		ALL_DELETE_FIELDS
+			.iter()
+			.cloned()
+			.filter(move |field| self.contains(*field))
+	}
+
 	/// Mark a particular field as requiring deletion.
 	pub fn set(self, field: DeleteField) -> Self {
 		DeleteMask(self.0 | field.value())
@@ -456,11 +1172,448 @@ impl DeleteField {
 	}
 }
 
+impl FixMode {
+	/// Convert to the value the modem uses for the fix interval option.
+	fn as_interval(self) -> u16 {
+		match self {
+			FixMode::Single => 0,
+			FixMode::Continuous => 1,
+			FixMode::Periodic { interval_s } => interval_s,
+		}
+	}
+
+	/// Convert from the value the modem uses for the fix interval option.
+	fn from_interval(interval: u16) -> FixMode {
+		match interval {
+			0 => FixMode::Single,
+			1 => FixMode::Continuous,
+			interval_s => FixMode::Periodic { interval_s },
+		}
+	}
+}
+
+/// Check the checksum on an NMEA sentence, like
+/// `$GPGGA,...*47`.
+///
+/// The checksum is the XOR of every byte between the `$` and the `*`, written
+/// as two hex digits after the `*`. Returns false if the sentence doesn't
+/// start with `$`, or doesn't have a `*` followed by two hex digits. Anything
+/// after the checksum (e.g. `\r\n`) is ignored.
+pub fn nmea_checksum_valid(sentence: &[u8]) -> bool {
+	let body = match sentence.strip_prefix(b"$") {
+		Some(body) => body,
+		None => return false,
+	};
+	let star = match body.iter().position(|b| *b == b'*') {
+		Some(star) => star,
+		None => return false,
+	};
+	let expected = match body
+		.get(star + 1..star + 3)
+		.filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+		.and_then(|hex| core::str::from_utf8(hex).ok())
+		.and_then(|hex| u8::from_str_radix(hex, 16).ok())
+	{
+		Some(expected) => expected,
+		None => return false,
+	};
+	let actual = body[0..star].iter().fold(0u8, |acc, b| acc ^ b);
+	actual == expected
+}
+
 //******************************************************************************
 // Private Functions and Impl on Private Types
 //******************************************************************************
 
-// None
+impl GnssData {
+	/// Get the position frame, if this is one and it holds a valid fix.
+	fn valid_pvt(&self) -> Option<&sys::nrf_gnss_pvt_data_frame_t> {
+		match self {
+			GnssData::Position(p) if self.is_valid() => Some(p),
+			_ => None,
+		}
+	}
+}
+
+/// View a GNSS frame as the bytes the socket reads into.
+fn frame_bytes(
+	frame: &mut core::mem::MaybeUninit<sys::nrf_gnss_data_frame_t>,
+) -> &mut [core::mem::MaybeUninit<u8>] {
+	// NOTE(unsafe) - any bytes are a valid `MaybeUninit<u8>`, and the slice
+	// covers exactly the frame.
+	unsafe {
+		core::slice::from_raw_parts_mut(
+			frame.as_mut_ptr() as *mut core::mem::MaybeUninit<u8>,
+			core::mem::size_of::<sys::nrf_gnss_data_frame_t>(),
+		)
+	}
+}
+
+impl Dms {
+	/// Split an angle in decimal degrees into degrees, minutes and seconds.
+	fn new(value: f64, positive: char, negative: char) -> Dms {
+		const MILLIS_PER_DEGREE: f64 = 3_600_000.0;
+		let magnitude = if value < 0.0 { -value } else { value };
+		// Work in whole milliseconds of arc, so that rounding can't give us
+		// 60 seconds or 60 minutes.
+		let total_millis = (magnitude * MILLIS_PER_DEGREE + 0.5) as u64;
+		Dms {
+			degrees: (total_millis / 3_600_000) as u16,
+			minutes: ((total_millis / 60_000) % 60) as u8,
+			seconds: (total_millis % 60_000) as f32 / 1000.0,
+			// Don't put a value which rounds to zero in the negative
+			// hemisphere
+			hemisphere: if value < 0.0 && total_millis != 0 {
+				negative
+			} else {
+				positive
+			},
+		}
+	}
+}
+
+/// Stops values which round to zero at six decimal places from being shown
+/// as `-0.000000`.
+fn without_negative_zero(value: f64) -> f64 {
+	if value < 0.0 && value > -0.000_000_5 {
+		0.0
+	} else {
+		value
+	}
+}
+
+/// Count the days from 1970-01-01 to the given date in the proleptic
+/// Gregorian calendar, allowing for leap years.
+///
+/// See Howard Hinnant's `days_from_civil` algorithm.
+fn days_since_epoch(year: i64, month: i64, day: i64) -> i64 {
+	// Count years from March, so the leap day is at the end of the year
+	let year = if month <= 2 { year - 1 } else { year };
+	let era = year.div_euclid(400);
+	let year_of_era = year - era * 400;
+	let month_from_march = (month + 9) % 12;
+	let day_of_year = (153 * month_from_march + 2) / 5 + day - 1;
+	let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+	era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for GnssData {
+	fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+	where
+		S: serde::Serializer,
+	{
+		GnssDataRepr::from(self).serialize(serializer)
+	}
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for GnssData {
+	fn deserialize<D>(deserializer: D) -> Result<GnssData, D::Error>
+	where
+		D: serde::Deserializer<'de>,
+	{
+		let repr = GnssDataRepr::deserialize(deserializer)?;
+		Ok(GnssData::from(repr))
+	}
+}
+
+#[cfg(feature = "serde")]
+impl From<&GnssData> for GnssDataRepr {
+	fn from(data: &GnssData) -> GnssDataRepr {
+		match data {
+			GnssData::Nmea { buffer, length } => {
+				let mut nmea = heapless::String::new();
+				// NOTE(unsafe) - we checked this when we created the GnssData
+				let nmea_str = unsafe { core::str::from_utf8_unchecked(&buffer[0..*length]) };
+				// Cannot fail - the string has the same capacity as the buffer
+				let _ = nmea.push_str(nmea_str);
+				GnssDataRepr::Nmea(nmea)
+			}
+			GnssData::Position(p) => GnssDataRepr::Position(PvtRepr {
+				latitude: p.latitude,
+				longitude: p.longitude,
+				altitude: p.altitude,
+				accuracy: p.accuracy,
+				speed: p.speed,
+				heading: p.heading,
+				datetime: DateTimeRepr {
+					year: p.datetime.year,
+					month: p.datetime.month,
+					day: p.datetime.day,
+					hour: p.datetime.hour,
+					minute: p.datetime.minute,
+					seconds: p.datetime.seconds,
+					ms: p.datetime.ms,
+				},
+				pdop: p.pdop,
+				hdop: p.hdop,
+				vdop: p.vdop,
+				tdop: p.tdop,
+				flags: p.flags,
+				sv: p.sv.map(|sv| SvRepr {
+					sv: sv.sv,
+					cn0: sv.cn0,
+					elevation: sv.elevation,
+					azimuth: sv.azimuth,
+					flags: sv.flags,
+					signal: sv.signal,
+				}),
+			}),
+			GnssData::Agps(a) => GnssDataRepr::Agps(AgpsRepr {
+				sv_mask_ephe: a.sv_mask_ephe,
+				sv_mask_alm: a.sv_mask_alm,
+				data_flags: a.data_flags,
+			}),
+		}
+	}
+}
+
+#[cfg(feature = "serde")]
+impl From<GnssDataRepr> for GnssData {
+	fn from(repr: GnssDataRepr) -> GnssData {
+		match repr {
+			GnssDataRepr::Nmea(nmea) => {
+				let mut buffer = [0u8; 83];
+				buffer[0..nmea.len()].copy_from_slice(nmea.as_bytes());
+				GnssData::Nmea {
+					buffer,
+					length: nmea.len(),
+				}
+			}
+			GnssDataRepr::Position(p) => GnssData::Position(sys::nrf_gnss_pvt_data_frame_t {
+				latitude: p.latitude,
+				longitude: p.longitude,
+				altitude: p.altitude,
+				accuracy: p.accuracy,
+				speed: p.speed,
+				heading: p.heading,
+				datetime: sys::nrf_gnss_datetime_t {
+					year: p.datetime.year,
+					month: p.datetime.month,
+					day: p.datetime.day,
+					hour: p.datetime.hour,
+					minute: p.datetime.minute,
+					seconds: p.datetime.seconds,
+					ms: p.datetime.ms,
+				},
+				pdop: p.pdop,
+				hdop: p.hdop,
+				vdop: p.vdop,
+				tdop: p.tdop,
+				flags: p.flags,
+				sv: p.sv.map(|sv| sys::nrf_gnss_sv_t {
+					sv: sv.sv,
+					cn0: sv.cn0,
+					elevation: sv.elevation,
+					azimuth: sv.azimuth,
+					flags: sv.flags,
+					signal: sv.signal,
+				}),
+			}),
+			GnssDataRepr::Agps(a) => GnssData::Agps(sys::nrf_gnss_agps_data_frame_t {
+				sv_mask_ephe: a.sv_mask_ephe,
+				sv_mask_alm: a.sv_mask_alm,
+				data_flags: a.data_flags,
+			}),
+		}
+	}
+}
+
+//******************************************************************************
+// Tests
+//******************************************************************************
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::backend::mock;
+
+	#[test]
+	fn agps_request_decodes_bit_numbers() {
+		let frame = GnssData::Agps(sys::nrf_gnss_agps_data_frame_t {
+			sv_mask_ephe: 0x0000_0003,
+			sv_mask_alm: 0,
+			data_flags: (1 << sys::NRF_GNSS_AGPS_GPS_UTC_REQUEST)
+				| (1 << sys::NRF_GNSS_AGPS_POSITION_REQUEST),
+		});
+		let request = frame.agps_request().unwrap();
+		assert!(request.ephemeris);
+		assert!(!request.almanac);
+		assert!(request.utc);
+		assert!(!request.klobuchar);
+		assert!(!request.nequick);
+		assert!(!request.system_time_and_sv_tow);
+		assert!(request.position);
+		assert!(!request.integrity);
+	}
+
+	/// The bytes of a GNSS frame holding the given NMEA sentence.
+	fn nmea_frame(sentence: &str) -> [u8; core::mem::size_of::<sys::nrf_gnss_data_frame_t>()] {
+		let mut nmea: sys::nrf_gnss_nmea_data_frame_t = [0; 83];
+		for (slot, byte) in nmea.iter_mut().zip(sentence.bytes()) {
+			*slot = byte as _;
+		}
+		let frame = sys::nrf_gnss_data_frame_t {
+			data_id: sys::NRF_GNSS_NMEA_DATA_ID as u8,
+			__bindgen_anon_1: sys::nrf_gnss_data_frame_t__bindgen_ty_1 { nmea },
+		};
+		// NOTE(unsafe) - a frame is plain old data
+		unsafe { core::mem::transmute(frame) }
+	}
+
+	#[test]
+	fn nmea_sentences_are_read() {
+		const GGA: &str = "$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47";
+		const RMC: &str = "$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*6A";
+		mock::run(|modem| {
+			let gnss = GnssSocket::new().unwrap();
+			modem.push(sys::NRF_PROTO_GNSS, &nmea_frame(GGA));
+			modem.push(sys::NRF_PROTO_GNSS, &nmea_frame(RMC));
+			let fix = gnss.get_fix().unwrap().unwrap();
+			assert_eq!(fix.as_nmea(), Some(GGA));
+			assert!(nmea_checksum_valid(GGA.as_bytes()));
+			let mut piped = heapless::String::<256>::new();
+			gnss.pipe_nmea(&mut piped).unwrap();
+			assert_eq!(piped.len(), RMC.len() + 2);
+			assert!(piped.starts_with(RMC));
+		});
+	}
+
+	#[test]
+	fn poll_at_and_gnss_together() {
+		use crate::{at::AtSocket, poll, PollEntry, PollFlags};
+		const GGA: &str = "$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47";
+		mock::run(|modem| {
+			let mut at_socket = AtSocket::new().unwrap();
+			let gnss = GnssSocket::new().unwrap();
+
+			// Only the GNSS socket has something to read
+			modem.push(sys::NRF_PROTO_GNSS, &nmea_frame(GGA));
+			let mut poll_list = [
+				PollEntry::new(&at_socket, PollFlags::Read),
+				PollEntry::new(&gnss, PollFlags::Read),
+			];
+			assert_eq!(poll(&mut poll_list, 1000), Ok(1));
+			assert!(!poll_list[0].result().is_readable());
+			let fix = gnss.get_fix_after_poll(poll_list[1].result()).unwrap();
+			assert_eq!(fix.unwrap().as_nmea(), Some(GGA));
+
+			// Now only the AT socket does, and the GNSS socket isn't read
+			modem.push(sys::NRF_PROTO_AT, b"+CEREG: 1\r\n");
+			let mut poll_list = [
+				PollEntry::new(&at_socket, PollFlags::Read),
+				PollEntry::new(&gnss, PollFlags::Read),
+			];
+			assert_eq!(poll(&mut poll_list, 1000), Ok(1));
+			assert!(poll_list[0].result().is_readable());
+			assert!(gnss
+				.get_fix_after_poll(poll_list[1].result())
+				.unwrap()
+				.is_none());
+			assert_eq!(at_socket.read_urc().unwrap().unwrap().as_str(), "+CEREG: 1");
+		});
+	}
+	/// A valid position frame at the given UTC time.
+	fn fix_at(year: u16, month: u8, day: u8, hour: u8, minute: u8, seconds: u8) -> GnssData {
+		// NOTE(unsafe) - a frame is plain old data, so all zeroes is fine
+		let mut pvt: sys::nrf_gnss_pvt_data_frame_t = unsafe { core::mem::zeroed() };
+		pvt.flags = sys::NRF_GNSS_PVT_FLAG_FIX_VALID_BIT as u8;
+		pvt.datetime.year = year;
+		pvt.datetime.month = month;
+		pvt.datetime.day = day;
+		pvt.datetime.hour = hour;
+		pvt.datetime.minute = minute;
+		pvt.datetime.seconds = seconds;
+		GnssData::Position(pvt)
+	}
+
+	#[test]
+	fn unix_timestamp_of_a_fix() {
+		assert_eq!(fix_at(1970, 1, 1, 0, 0, 0).unix_timestamp(), Some(0));
+		assert_eq!(
+			fix_at(2020, 2, 29, 12, 34, 56).unix_timestamp(),
+			Some(1_582_979_696)
+		);
+		let mut invalid = fix_at(2020, 2, 29, 12, 34, 56);
+		if let GnssData::Position(ref mut pvt) = invalid {
+			pvt.flags = 0;
+		}
+		assert_eq!(invalid.unix_timestamp(), None);
+	}
+
+	#[test]
+	fn days_since_epoch_follows_the_leap_year_rules() {
+		assert_eq!(days_since_epoch(1970, 1, 1), 0);
+		assert_eq!(days_since_epoch(1969, 12, 31), -1);
+		assert_eq!(days_since_epoch(2020, 2, 29), 18_321);
+		// 2000 is a leap year, being a multiple of 400
+		assert_eq!(days_since_epoch(2000, 2, 29), 11_016);
+		assert_eq!(days_since_epoch(2000, 3, 1), 11_017);
+		// 2100 isn't, being a multiple of 100
+		assert_eq!(days_since_epoch(2100, 2, 28), 47_540);
+		assert_eq!(days_since_epoch(2100, 3, 1), 47_541);
+	}
+	#[test]
+	fn dms_seconds_never_reach_60() {
+		let dms = Dms::new(51.499_999_99, 'N', 'S');
+		assert_eq!((dms.degrees, dms.minutes, dms.seconds), (51, 30, 0.0));
+		let dms = Dms::new(0.999_999_99, 'E', 'W');
+		assert_eq!((dms.degrees, dms.minutes, dms.seconds), (1, 0, 0.0));
+	}
+
+	#[test]
+	fn dms_equator_is_north() {
+		assert_eq!(
+			Dms::new(0.0, 'N', 'S'),
+			Dms {
+				degrees: 0,
+				minutes: 0,
+				seconds: 0.0,
+				hemisphere: 'N',
+			}
+		);
+	}
+
+	#[test]
+	fn dms_negative_values_near_zero() {
+		// Rounds to zero, so isn't put in the southern hemisphere
+		assert_eq!(Dms::new(-0.000_000_01, 'N', 'S').hemisphere, 'N');
+		// A millisecond of arc is enough
+		let dms = Dms::new(-0.000_000_3, 'N', 'S');
+		assert_eq!((dms.degrees, dms.minutes, dms.seconds), (0, 0, 0.001));
+		assert_eq!(dms.hemisphere, 'S');
+		let dms = Dms::new(-33.5, 'E', 'W');
+		assert_eq!((dms.degrees, dms.minutes, dms.hemisphere), (33, 30, 'W'));
+	}
+	#[test]
+	fn nmea_checksums() {
+		const RMC: &str = "$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*6A";
+		assert!(nmea_checksum_valid(RMC.as_bytes()));
+		// Trailing line endings are ignored
+		assert!(nmea_checksum_valid(
+			b"$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*6A\r\n"
+		));
+		// Lower case hex is fine
+		assert!(nmea_checksum_valid(
+			b"$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*6a"
+		));
+		// Wrong checksum
+		assert!(!nmea_checksum_valid(
+			b"$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*6B"
+		));
+		// Missing or short checksum
+		assert!(!nmea_checksum_valid(
+			b"$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W"
+		));
+		assert!(!nmea_checksum_valid(
+			b"$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*6"
+		));
+		// Missing `$`
+		assert!(!nmea_checksum_valid(&RMC.as_bytes()[1..]));
+		assert!(!nmea_checksum_valid(b""));
+	}
+}
 
 //******************************************************************************
 // End of File
@@ -35,6 +35,8 @@
 
 pub mod api;
 pub mod at;
+mod backend;
+pub mod dfu;
 pub mod dtls;
 mod ffi;
 pub mod gnss;
@@ -49,8 +51,18 @@ pub mod udp;
 //******************************************************************************
 
 pub use api::*;
-pub use ffi::{get_last_error, NrfxErr};
-pub use raw::{poll, PollEntry, PollFlags, PollResult, Pollable};
+#[cfg(feature = "mock-backend")]
+pub use backend::{notify_event, set_backend, set_errno, Backend};
+pub use ffi::{
+	allocation_failures, clear_last_error, get_last_error, last_error, set_oom_hook,
+	set_timed_wait_impl, NrfErrno, NrfxErr,
+};
+#[cfg(feature = "socket-stats")]
+pub use raw::SocketStats;
+pub use raw::{
+	poll, poll_n, resolve, resolve_v6, PollEntry, PollFlags, PollResult, Pollable, SocketFamily,
+	MAX_RESOLVED_ADDRESSES, MAX_SOCKETS_POLL, POLL_FOREVER,
+};
 
 use core::cell::RefCell;
 use cortex_m::interrupt::Mutex;
@@ -68,11 +80,21 @@ use nrfxlib_sys as sys;
 #[repr(transparent)]
 pub struct NrfSockAddrIn(sys::nrf_sockaddr_in);
 
+/// Create a camel-case type name for IPv6 socket addresses.
+#[derive(Debug, Clone)]
+#[repr(transparent)]
+pub struct NrfSockAddrIn6(sys::nrf_sockaddr_in6);
+
 /// Create a camel-case type name for socket information.
 #[derive(Debug, Clone)]
 #[repr(transparent)]
 pub struct NrfAddrInfo(sys::nrf_addrinfo);
 
+/// The version of the Nordic modem library we were built with. See
+/// `library_version`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct LibraryVersion(&'static str);
+
 impl core::ops::Deref for NrfSockAddrIn {
 	type Target = sys::nrf_sockaddr_in;
 
@@ -81,35 +103,111 @@ impl core::ops::Deref for NrfSockAddrIn {
 	}
 }
 
+impl core::ops::Deref for NrfSockAddrIn6 {
+	type Target = sys::nrf_sockaddr_in6;
+
+	fn deref(&self) -> &sys::nrf_sockaddr_in6 {
+		&self.0
+	}
+}
+
+/// A region of the shared memory the application and modem cores use to talk
+/// to each other. See `ShmemConfig`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ShmemRegion {
+	/// The address of the start of the region
+	pub base: u32,
+	/// The size of the region in bytes
+	pub size: u32,
+}
+
+/// How the shared memory is split up between the library's buffers. See
+/// `init_with_shmem`.
+///
+/// The regions must all lie within the RAM the modem can access (the first
+/// 128 KiB of RAM, on the nRF9160) and must match your `memory.x`. The
+/// default is the layout from the NCS 1.5.1 release, starting at
+/// `0x2001_0000`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ShmemConfig {
+	/// The control region
+	pub ctrl: ShmemRegion,
+	/// The region for data going to the modem. We also use this as the TX
+	/// heap.
+	pub tx: ShmemRegion,
+	/// The region for data coming from the modem
+	pub rx: ShmemRegion,
+	/// The region for modem traces. A size of zero means no traces.
+	pub trace: ShmemRegion,
+}
+
 /// Errors that can be returned in response to an AT command.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum AtError {
 	/// Plain `ERROR` response
 	Error,
 	/// `+CME ERROR xx` response
-	CmeError(i32),
+	CmeError(at::CmeErrorCode),
 	/// `+CMS ERROR xx` response
 	CmsError(i32),
 }
 
 /// The set of error codes we can get from this API.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Error {
 	/// An error was returned by the Nordic library. We supply a string
 	/// descriptor, the return code, and the value of `errno`.
 	Nordic(&'static str, i32, i32),
 	/// An AT error (`ERROR`, `+CMS ERROR` or `+CME ERROR`) was returned by the modem.
 	AtError(AtError),
+	/// An AT error was returned by the modem in response to one of a list of
+	/// commands. We supply the index of the command in the list, and the
+	/// error.
+	AtCommandFailed(usize, AtError),
 	/// Data returned by the modem was not in a format we could understand.
 	BadDataFormat,
 	/// Given hostname was too long for internal buffers to hold
 	HostnameTooLong,
+	/// A credential contained characters which cannot be sent to the modem
+	InvalidCredential,
 	/// Unrecognised value from AT interface
 	UnrecognisedValue,
 	/// A socket write error occurred
 	WriteError,
 	/// Too many sockets given
 	TooManySockets,
+	/// We couldn't connect to any of the addresses a hostname resolved to. We
+	/// supply the number of addresses we tried, and the value of `errno` from
+	/// the last attempt.
+	ConnectFailed(usize, i32),
+	/// The network rejected our attempt to register (`+CEREG: 3`). The EMM
+	/// cause in `AT%XMONITOR` may tell you why.
+	RegistrationDenied,
+	/// A datagram was too big for the buffer it was read into. We supply the
+	/// number of bytes we kept, and the length of the whole datagram.
+	Truncated(usize, usize),
+	/// The heap given to `init_with` or `init_with_heap` was smaller than
+	/// `MIN_HEAP_SIZE`.
+	HeapTooSmall,
+	/// The operation did not complete in the time allowed
+	Timeout,
+	/// The socket was created before the library was last shut down, so it
+	/// can no longer be used
+	SocketInvalidated,
+	/// An argument was outside the range the modem accepts
+	InvalidArgument,
+	/// `init` was called when the library was already running. Call
+	/// `shutdown` first.
+	AlreadyInitialized,
+	/// The regions in a `ShmemConfig` overlap each other, or run past the end
+	/// of the address space.
+	ShmemOverlap,
+	/// The modem's DFU area still holds an old image, so a new one can't be
+	/// written. Call `dfu::erase` first.
+	DfuAreaNotBlank,
+	/// The modem rejected part of a firmware image. We supply the
+	/// `nrf_dfu_err_t` code it reported.
+	DfuFailed(i32),
 }
 
 /// We need to wrap our heap so it's creatable at run-time and accessible from an ISR.
@@ -128,7 +226,10 @@ type WrappedHeap = Mutex<RefCell<Option<Heap>>>;
 // Constants
 //******************************************************************************
 
-// None
+/// The smallest library heap, in bytes, you can give to `init_with` or
+/// `init_with_heap`. This is a sanity check, not an exact requirement - how
+/// much the library needs depends on how many sockets you open.
+pub const MIN_HEAP_SIZE: usize = 1024;
 
 //******************************************************************************
 // Global Variables
@@ -145,6 +246,27 @@ static LIBRARY_ALLOCATOR: WrappedHeap = Mutex::new(RefCell::new(None));
 /// seen by the Cortex-M33 and the modem CPU.
 static TX_ALLOCATOR: WrappedHeap = Mutex::new(RefCell::new(None));
 
+/// The value returned by `nrf_modem_init` the last time `init` was called.
+///
+/// This tells us the result of any modem firmware update which was applied
+/// during start-up. See `dfu::scheduled_update_result`.
+static INIT_RESULT: core::sync::atomic::AtomicI32 = core::sync::atomic::AtomicI32::new(0);
+
+/// Set while the library is running - i.e. between a successful `init` and
+/// the next `shutdown`.
+static INITIALISED: core::sync::atomic::AtomicBool = core::sync::atomic::AtomicBool::new(false);
+
+/// The heap (start and length) and shared memory layout the library was last
+/// started with, so `modem::full_reset` can start it again the same way.
+static LAST_START: Mutex<core::cell::Cell<Option<(usize, usize, ShmemConfig)>>> =
+	Mutex::new(core::cell::Cell::new(None));
+
+/// Counts the number of times the library has been shut down.
+///
+/// Each socket remembers the value when it was created, so we can spot
+/// sockets which were created before a shutdown and refuse to use them.
+static LIBRARY_GENERATION: core::sync::atomic::AtomicU32 = core::sync::atomic::AtomicU32::new(0);
+
 //******************************************************************************
 // Macros
 //******************************************************************************
@@ -155,14 +277,346 @@ static TX_ALLOCATOR: WrappedHeap = Mutex::new(RefCell::new(None));
 // Public Functions and Impl on Public Types
 //******************************************************************************
 
-/// Start the NRF Modem library
+/// Start the NRF Modem library, using 4 KiB of global data as the library
+/// heap.
+///
+/// You get `Error::AlreadyInitialized` if the library is already running.
 pub fn init() -> Result<(), Error> {
+	init_with_shmem(ShmemConfig::default())
+}
+
+/// Like `init`, but with the shared memory laid out as given, rather than as
+/// in the NCS 1.5.1 release. Use this if your modem firmware wants
+/// different buffer sizes, or your `memory.x` puts the shared memory
+/// somewhere else.
+///
+/// You get `Error::ShmemOverlap` if any of the regions overlap.
+///
+/// ```ignore
+/// nrfxlib::init_with_shmem(nrfxlib::ShmemConfig {
+///     trace: nrfxlib::ShmemRegion {
+///         base: 0x2001_44e8,
+///         size: 0x0000_4000,
+///     },
+///     ..Default::default()
+/// })?;
+/// ```
+pub fn init_with_shmem(shmem: ShmemConfig) -> Result<(), Error> {
+	/// Allocate some space in global data to use as a heap.
+	static mut HEAP_MEMORY: [u32; 1024] = [0u32; 1024];
+	// We only take the address here. The library may still be running on
+	// this heap, so no reference is made until `start` knows it isn't. Rust
+	// 1.82 made this safe, but our MSRV still needs the `unsafe`.
+	#[allow(unused_unsafe)]
+	let heap_start = unsafe { core::ptr::addr_of_mut!(HEAP_MEMORY) } as *mut u8;
+	let heap_size = core::mem::size_of::<[u32; 1024]>();
+	start(heap_start, heap_size, &shmem)
+}
+
+/// Start the NRF Modem library, using the given memory as the library heap.
+///
+/// The heap must be at least `MIN_HEAP_SIZE` bytes (i.e. 256 words), or you
+/// get `Error::HeapTooSmall`. Opening lots of sockets at once, or using TLS,
+/// needs more than that - `init` uses 4 KiB.
+pub fn init_with_heap(heap: &'static mut [u32]) -> Result<(), Error> {
+	let heap_size = core::mem::size_of_val(heap);
+	// NOTE(unsafe) - a `u32` slice can always be viewed as bytes.
+	let heap = unsafe { core::slice::from_raw_parts_mut(heap.as_mut_ptr() as *mut u8, heap_size) };
+	init_with(heap)
+}
+
+/// Start the NRF Modem library, using the given memory as the library heap.
+///
+/// This lets you choose how big the heap is, and where it lives - for
+/// example, you could put it in a particular RAM region with
+/// `#[link_section]`. The heap must be at least `MIN_HEAP_SIZE` bytes, or
+/// you get `Error::HeapTooSmall`.
+///
+/// If the library is already running, you get `Error::AlreadyInitialized`
+/// and nothing is changed - re-creating the heaps would corrupt the memory
+/// the library is using.
+pub fn init_with(heap: &'static mut [u8]) -> Result<(), Error> {
+	init_with_heap_and_shmem(heap, ShmemConfig::default())
+}
+
+/// Start the NRF Modem library, using the given memory as the library heap,
+/// and with the shared memory laid out as given. The other `init` functions
+/// all end up here.
+///
+/// You get the same errors as from `init_with` and `init_with_shmem`.
+pub fn init_with_heap_and_shmem(heap: &'static mut [u8], shmem: ShmemConfig) -> Result<(), Error> {
+	start(heap.as_mut_ptr(), heap.len(), &shmem)
+}
+
+/// Stop the NRF Modem library
+///
+/// Any sockets which are still open can no longer be used, and will return
+/// `Error::SocketInvalidated`. Afterwards you can call `init` again.
+pub fn shutdown() {
+	debug!("nrfxlib shutdown");
+	unsafe {
+		sys::nrf_modem_shutdown();
+	}
+	LIBRARY_GENERATION.fetch_add(1, core::sync::atomic::Ordering::SeqCst);
+	release_heaps();
+	INITIALISED.store(false, core::sync::atomic::Ordering::SeqCst);
+	trace!("nrfxlib shutdown complete");
+}
+
+/// Get the version of the Nordic modem library (e.g. `"1.5.1"`). This is not
+/// the version of the modem firmware, which you get with
+/// `modem::get_firmware_version`.
+pub fn library_version() -> Result<LibraryVersion, Error> {
+	let ptr = unsafe { sys::nrf_modem_build_version() };
+	if ptr.is_null() {
+		return Err(Error::BadDataFormat);
+	}
+	// NOTE(unsafe) - the library gives us a pointer to a null-terminated
+	// string in flash.
+	let version = unsafe { core::ffi::CStr::from_ptr(ptr as *const core::ffi::c_char) };
+	version
+		.to_str()
+		.map(LibraryVersion)
+		.map_err(|_| Error::BadDataFormat)
+}
+
+impl ShmemConfig {
+	/// The default layout, plus an 8 KiB trace region straight after the RX
+	/// region, so the modem can give us traces. See
+	/// `modem::enable_modem_trace`.
+	///
+	/// Make sure your `memory.x` leaves room for the extra region.
+	pub fn with_trace() -> ShmemConfig {
+		let default = ShmemConfig::default();
+		ShmemConfig {
+			trace: ShmemRegion {
+				base: default.rx.base + default.rx.size,
+				size: 0x0000_2000,
+			},
+			..default
+		}
+	}
+}
+
+impl Default for ShmemConfig {
+	/// The layout from the NCS 1.5.1 release, with no trace region.
+	fn default() -> ShmemConfig {
+		ShmemConfig {
+			// At start of shared memory (see memory.x)
+			ctrl: ShmemRegion {
+				base: 0x2001_0000,
+				size: 0x0000_04e8,
+			},
+			// Follows on from control buffer
+			tx: ShmemRegion {
+				base: 0x2001_04e8,
+				size: 0x0000_2000,
+			},
+			// Follows on from TX buffer
+			rx: ShmemRegion {
+				base: 0x2001_24e8,
+				size: 0x0000_2000,
+			},
+			// No trace info
+			trace: ShmemRegion { base: 0, size: 0 },
+		}
+	}
+}
+
+impl Error {
+	/// Get the `errno` carried by this error, if it has one and we recognise
+	/// it.
+	///
+	/// This lets you match on well-known failures:
+	///
+	/// ```ignore
+	/// if let Err(e) = socket.write(b"hello") {
+	///     if e.errno_kind() == Some(nrfxlib::NrfErrno::NotConn) {
+	///         // Reconnect and try again
+	///     }
+	/// }
+	/// ```
+	pub fn errno_kind(&self) -> Option<NrfErrno> {
+		match self {
+			Error::Nordic(_, _, errno) => NrfErrno::from_i32(*errno),
+			Error::ConnectFailed(_, errno) => NrfErrno::from_i32(*errno),
+			_ => None,
+		}
+	}
+}
+
+impl LibraryVersion {
+	/// Get the version as a string, e.g. `"1.5.1"`.
+	pub fn as_str(&self) -> &'static str {
+		self.0
+	}
+}
+
+impl core::fmt::Display for LibraryVersion {
+	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+		write!(f, "{}", self.0)
+	}
+}
+
+impl core::fmt::Display for AtError {
+	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+		match self {
+			AtError::Error => write!(f, "ERROR"),
+			AtError::CmeError(code) => write!(f, "+CME ERROR: {}", code),
+			AtError::CmsError(code) => write!(f, "+CMS ERROR: {}", code),
+		}
+	}
+}
+
+impl core::fmt::Display for Error {
+	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+		match self {
+			Error::Nordic(desc, result, errno) => {
+				write!(f, "{} failed: returned {}, errno {}", desc, result, errno)?;
+				if let Some(name) = NrfErrno::from_i32(*errno) {
+					write!(f, " ({:?})", name)?;
+				}
+				Ok(())
+			}
+			Error::AtError(err) => write!(f, "AT command failed: {}", err),
+			Error::AtCommandFailed(index, err) => {
+				write!(f, "AT command {} failed: {}", index, err)
+			}
+			Error::BadDataFormat => write!(f, "data from the modem was in an unexpected format"),
+			Error::HostnameTooLong => write!(f, "hostname too long"),
+			Error::InvalidCredential => write!(f, "credential contains invalid characters"),
+			Error::UnrecognisedValue => write!(f, "unrecognised value from the AT interface"),
+			Error::WriteError => write!(f, "write error"),
+			Error::TooManySockets => write!(f, "too many sockets"),
+			Error::ConnectFailed(attempts, errno) => write!(
+				f,
+				"could not connect after {} attempt(s), last errno {}",
+				attempts, errno
+			),
+			Error::RegistrationDenied => write!(f, "network registration denied"),
+			Error::Truncated(kept, actual) => {
+				write!(f, "datagram truncated: kept {} of {} bytes", kept, actual)
+			}
+			Error::HeapTooSmall => write!(f, "heap smaller than MIN_HEAP_SIZE"),
+			Error::Timeout => write!(f, "timed out"),
+			Error::SocketInvalidated => write!(f, "socket was invalidated by a library shutdown"),
+			Error::InvalidArgument => write!(f, "invalid argument"),
+			Error::AlreadyInitialized => write!(f, "library already initialised"),
+			Error::ShmemOverlap => write!(f, "shared memory regions overlap"),
+			Error::DfuAreaNotBlank => write!(f, "modem DFU area must be erased first"),
+			Error::DfuFailed(code) => write!(f, "modem DFU failed: error {}", code),
+		}
+	}
+}
+
+impl From<core::fmt::Error> for Error {
+	fn from(_err: core::fmt::Error) -> Error {
+		Error::WriteError
+	}
+}
+
+impl NrfSockAddrIn {
+	/// Create a new IPv4 socket address from the four octets of an IP address
+	/// (e.g. `[192, 168, 0, 1]`) and a port number.
+	pub fn new(octets: [u8; 4], port: u16) -> NrfSockAddrIn {
+		NrfSockAddrIn(sys::nrf_sockaddr_in {
+			sin_len: core::mem::size_of::<sys::nrf_sockaddr_in>() as u8,
+			sin_family: sys::NRF_AF_INET as i32,
+			sin_port: raw::htons(port),
+			// The address is stored in network byte order
+			sin_addr: sys::nrf_in_addr {
+				s_addr: u32::from_le_bytes(octets),
+			},
+		})
+	}
+}
+
+impl From<core::net::SocketAddrV4> for NrfSockAddrIn {
+	fn from(addr: core::net::SocketAddrV4) -> NrfSockAddrIn {
+		NrfSockAddrIn::new(addr.ip().octets(), addr.port())
+	}
+}
+
+impl core::fmt::Display for NrfSockAddrIn {
+	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+		let octets = self.sin_addr.s_addr.to_be_bytes();
+		write!(
+			f,
+			"{}.{}.{}.{}:{}",
+			octets[3],
+			octets[2],
+			octets[1],
+			octets[0],
+			u16::from_be(self.sin_port)
+		)
+	}
+}
+
+impl NrfSockAddrIn6 {
+	/// Create a new IPv6 socket address from the sixteen octets of an IP
+	/// address and a port number.
+	pub fn new(octets: [u8; 16], port: u16) -> NrfSockAddrIn6 {
+		NrfSockAddrIn6(sys::nrf_sockaddr_in6 {
+			sin6_len: core::mem::size_of::<sys::nrf_sockaddr_in6>() as u8,
+			sin6_family: sys::NRF_AF_INET6 as i32,
+			sin6_port: raw::htons(port),
+			sin6_flowinfo: 0,
+			// The octets are already in network byte order
+			sin6_addr: sys::nrf_in6_addr { s6_addr: octets },
+			sin6_scope_id: 0,
+		})
+	}
+}
+
+impl From<core::net::SocketAddrV6> for NrfSockAddrIn6 {
+	fn from(addr: core::net::SocketAddrV6) -> NrfSockAddrIn6 {
+		NrfSockAddrIn6::new(addr.ip().octets(), addr.port())
+	}
+}
+
+impl core::fmt::Display for NrfSockAddrIn6 {
+	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+		let ip = core::net::Ipv6Addr::from(self.sin6_addr.s6_addr);
+		write!(f, "[{}]:{}", ip, u16::from_be(self.sin6_port))
+	}
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+/// Does the work for the `init` functions: sets up the heaps and starts the
+/// library, using the given shared memory layout.
+///
+/// The heap is passed as a pointer and length, not a slice. A second `init`
+/// may pass the heap the running library is using, and we mustn't make a
+/// `&mut` to that.
+fn start(heap_start: *mut u8, heap_size: usize, shmem: &ShmemConfig) -> Result<(), Error> {
+	if heap_size < MIN_HEAP_SIZE {
+		return Err(Error::HeapTooSmall);
+	}
+
+	shmem.check()?;
+
+	if INITIALISED
+		.compare_exchange(
+			false,
+			true,
+			core::sync::atomic::Ordering::SeqCst,
+			core::sync::atomic::Ordering::SeqCst,
+		)
+		.is_err()
+	{
+		return Err(Error::AlreadyInitialized);
+	}
+
+	// NOTE(unsafe) - the library isn't running, so nothing else is using
+	// the heap memory.
 	unsafe {
-		/// Allocate some space in global data to use as a heap.
-		static mut HEAP_MEMORY: [u32; 1024] = [0u32; 1024];
-		let heap_start = HEAP_MEMORY.as_mut_ptr() as *mut _;
-		let heap_size = HEAP_MEMORY.len() * core::mem::size_of::<u32>();
 		cortex_m::interrupt::free(|cs| {
+			LAST_START
+				.borrow(cs)
+				.set(Some((heap_start as usize, heap_size, *shmem)));
 			*LIBRARY_ALLOCATOR.borrow(cs).borrow_mut() =
 				Some(Heap::new(heap_start, heap_size))
 		});
@@ -172,25 +626,21 @@ pub fn init() -> Result<(), Error> {
 	let params = sys::nrf_modem_init_params_t {
 		shmem: sys::nrf_modem_shmem_cfg {
 			ctrl: sys::nrf_modem_shmem_cfg__bindgen_ty_1 {
-				// At start of shared memory (see memory.x)
-				base: 0x2001_0000,
-				// This is the amount specified in the NCS 1.5.1 release.
-				size: 0x0000_04e8,
+				base: shmem.ctrl.base,
+				size: shmem.ctrl.size,
 			},
 			tx: sys::nrf_modem_shmem_cfg__bindgen_ty_2 {
-				// Follows on from control buffer
-				base: 0x2001_04e8,
-				// This is the amount specified in the NCS 1.5.1 release.
-				size: 0x0000_2000,
+				base: shmem.tx.base,
+				size: shmem.tx.size,
 			},
 			rx: sys::nrf_modem_shmem_cfg__bindgen_ty_3 {
-				// Follows on from TX buffer
-				base: 0x2001_24e8,
-				// This is the amount specified in the NCS 1.5.1 release.
-				size: 0x0000_2000,
+				base: shmem.rx.base,
+				size: shmem.rx.size,
+			},
+			trace: sys::nrf_modem_shmem_cfg__bindgen_ty_4 {
+				base: shmem.trace.base,
+				size: shmem.trace.size,
 			},
-			// No trace info
-			trace: sys::nrf_modem_shmem_cfg__bindgen_ty_4 { base: 0, size: 0 },
 		},
 		ipc_irq_prio: 0,
 	};
@@ -207,9 +657,13 @@ pub fn init() -> Result<(), Error> {
 
 	// OK, let's start the library
 	let result = unsafe { sys::nrf_modem_init(&params, sys::nrf_modem_mode_t_NORMAL_MODE) };
+	INIT_RESULT.store(result, core::sync::atomic::Ordering::SeqCst);
 
 	// Was it happy?
 	if result < 0 {
+		// The library isn't running, so let `init` be tried again
+		release_heaps();
+		INITIALISED.store(false, core::sync::atomic::Ordering::SeqCst);
 		Err(Error::Nordic("init", result, ffi::get_last_error()))
 	} else {
 		trace!("nrfxlib init complete");
@@ -217,41 +671,57 @@ pub fn init() -> Result<(), Error> {
 	}
 }
 
-/// Stop the NRF Modem library
-pub fn shutdown() {
-	debug!("nrfxlib shutdown");
-	unsafe {
-		sys::nrf_modem_shutdown();
+impl ShmemConfig {
+	/// Check none of the regions overlap. Empty regions (e.g. no trace
+	/// region) can go anywhere.
+	fn check(&self) -> Result<(), Error> {
+		let regions = [self.ctrl, self.tx, self.rx, self.trace];
+		for region in regions.iter() {
+			if region.end() > (1 << 32) {
+				return Err(Error::ShmemOverlap);
+			}
+		}
+		for (idx, first) in regions.iter().enumerate() {
+			for second in regions[idx + 1..].iter() {
+				if first.size != 0
+					&& second.size != 0
+					&& u64::from(first.base) < second.end()
+					&& u64::from(second.base) < first.end()
+				{
+					return Err(Error::ShmemOverlap);
+				}
+			}
+		}
+		Ok(())
 	}
-	trace!("nrfxlib shutdown complete");
 }
 
-impl From<core::fmt::Error> for Error {
-	fn from(_err: core::fmt::Error) -> Error {
-		Error::WriteError
+impl ShmemRegion {
+	/// The address just past the end of the region. This can be `1 << 32`,
+	/// so it doesn't fit in a `u32`.
+	fn end(&self) -> u64 {
+		u64::from(self.base) + u64::from(self.size)
 	}
 }
 
-impl core::fmt::Display for NrfSockAddrIn {
-	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
-		let octets = self.sin_addr.s_addr.to_be_bytes();
-		write!(
-			f,
-			"{}.{}.{}.{}:{}",
-			octets[3],
-			octets[2],
-			octets[1],
-			octets[0],
-			u16::from_be(self.sin_port)
-		)
+/// Start the library again, with the heap and shared memory layout it was
+/// last started with. If it has never been started, this is `init`.
+pub(crate) fn restart() -> Result<(), Error> {
+	let last_start = cortex_m::interrupt::free(|cs| LAST_START.borrow(cs).get());
+	match last_start {
+		Some((heap_start, heap_size, shmem)) => start(heap_start as *mut u8, heap_size, &shmem),
+		None => init(),
 	}
 }
 
-//******************************************************************************
-// Private Functions and Impl on Private Types
-//******************************************************************************
-
-// None
+/// Forget the library heap and the TX heap, once the library has stopped
+/// using them. Any allocation after this fails.
+fn release_heaps() {
+	cortex_m::interrupt::free(|cs| {
+		*LIBRARY_ALLOCATOR.borrow(cs).borrow_mut() = None;
+		*TX_ALLOCATOR.borrow(cs).borrow_mut() = None;
+	});
+}
 
 //******************************************************************************
 // End of File
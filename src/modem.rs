@@ -25,7 +25,7 @@ use log::debug;
 //******************************************************************************
 
 /// Identifies which radios in the nRF9160 should be active
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum SystemMode {
 	/// LTE-M only
 	LteM,
@@ -37,13 +37,428 @@ pub enum SystemMode {
 	LteMAndGnss,
 	/// NB-IOT and GNSS
 	NbIotAndGnss,
+	/// LTE-M and NB-IoT
+	LteMAndNbIot,
+	/// LTE-M, NB-IoT and GNSS
+	LteMAndNbIotAndGnss,
+}
+
+/// When both LTE-M and NB-IoT are enabled, identifies which one the modem
+/// should try first. This is the final digit of `AT%XSYSTEMMODE`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RatPreference {
+	/// No preference - the modem decides
+	Auto,
+	/// Prefer LTE-M
+	LteMPreferred,
+	/// Prefer NB-IoT
+	NbIotPreferred,
+	/// Let the network selection (PLMN) priorities decide, using LTE-M if
+	/// they are equal
+	PltwPreferred,
+}
+
+/// The state of the SIM card, as reported by `AT+CPIN?`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SimStatus {
+	/// The SIM is not waiting for any password
+	Ready,
+	/// The SIM is waiting for its PIN
+	SimPin,
+	/// The SIM is waiting for its PUK (the PIN has been entered wrongly too
+	/// many times)
+	SimPuk,
+	/// The SIM is waiting for its PIN2
+	SimPin2,
+	/// The SIM is waiting for its PUK2
+	SimPuk2,
+	/// The modem is waiting for the phone-to-SIM password
+	PhoneSimPin,
+}
+
+/// The result of a neighbour cell measurement. See `measure_neighbor_cells`.
+#[derive(Debug, Clone)]
+pub struct NeighborCells {
+	/// The cell the modem is currently camped on
+	pub serving: ServingCell,
+	/// The other cells the modem could hear
+	pub neighbors: heapless::Vec<NeighborCell, MAX_NEIGHBOR_CELLS>,
+}
+
+/// The serving cell details from a neighbour cell measurement.
+#[derive(Debug, Clone)]
+pub struct ServingCell {
+	/// E-UTRAN cell ID
+	pub cell_id: u32,
+	/// Mobile Country Code and Mobile Network Code (e.g. `"26295"`)
+	pub plmn: heapless::String<6>,
+	/// Tracking Area Code
+	pub tac: u32,
+	/// Timing advance, in units of Ts (basic time unit)
+	pub timing_advance: u16,
+	/// E-UTRA Absolute Radio Frequency Channel Number
+	pub earfcn: u32,
+	/// Physical cell ID
+	pub pci: u16,
+	/// Reference Signal Received Power, as an index (0 to 97) as per
+	/// 3GPP TS 36.133
+	pub rsrp: i16,
+	/// Reference Signal Received Quality, as an index (0 to 34) as per
+	/// 3GPP TS 36.133
+	pub rsrq: i16,
+}
+
+/// The details of one neighbour cell from a neighbour cell measurement.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct NeighborCell {
+	/// E-UTRA Absolute Radio Frequency Channel Number
+	pub earfcn: u32,
+	/// Physical cell ID
+	pub pci: u16,
+	/// Reference Signal Received Power, as an index (0 to 97) as per
+	/// 3GPP TS 36.133
+	pub rsrp: i16,
+	/// Reference Signal Received Quality, as an index (0 to 34) as per
+	/// 3GPP TS 36.133
+	pub rsrq: i16,
+	/// Time difference between the serving cell and this cell, in
+	/// milliseconds
+	pub time_diff: i32,
+}
+
+/// The number of attempts remaining to enter the SIM's PIN and PUK codes
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PinAttempts {
+	/// Number of attempts remaining to enter the PIN
+	pub pin: u8,
+	/// Number of attempts remaining to enter the PUK
+	pub puk: u8,
+}
+
+/// Configures how the modem drives the MAGPIO pins, which are typically wired
+/// to an RF switch in front of the antenna. See `configure_magpio`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MagpioConfig<'a> {
+	/// Which of MAGPIO0, MAGPIO1 and MAGPIO2 the modem should drive
+	pub pins: [bool; 3],
+	/// The pin states to use for each range of frequencies
+	pub windows: &'a [MagpioWindow],
+}
+
+/// The MAGPIO pin states to use when the modem is using a particular range of
+/// frequencies.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MagpioWindow {
+	/// The pin states - bit 0 is MAGPIO0, bit 1 is MAGPIO1 and bit 2 is
+	/// MAGPIO2
+	pub state: u8,
+	/// The lowest frequency in the range, in MHz
+	pub freq_lo_mhz: u16,
+	/// The highest frequency in the range, in MHz
+	pub freq_hi_mhz: u16,
+}
+
+/// The COEX0 pin state to use when the modem is using a particular range of
+/// frequencies. See `configure_coex0`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Coex0Window {
+	/// Whether COEX0 should be driven high
+	pub state: bool,
+	/// The lowest frequency in the range, in MHz
+	pub freq_lo_mhz: u16,
+	/// The highest frequency in the range, in MHz
+	pub freq_hi_mhz: u16,
+}
+
+/// A date and time reported by the network.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct NetworkTime {
+	/// Year (e.g. 2021)
+	pub year: u16,
+	/// Month (1 to 12)
+	pub month: u8,
+	/// Day of the month (1 to 31)
+	pub day: u8,
+	/// Hour (0 to 23)
+	pub hour: u8,
+	/// Minute (0 to 59)
+	pub minute: u8,
+	/// Second (0 to 59)
+	pub second: u8,
+	/// Offset from UTC to local time, in quarter-hours
+	pub timezone_quarter_hours: i8,
+}
+
+/// The contents of a `%XTIME` notification. The network may leave out any of
+/// the fields.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct NetworkTimeUpdate {
+	/// Offset from UTC to local time, in quarter-hours
+	pub local_timezone_quarter_hours: Option<i8>,
+	/// The current time, in UTC
+	pub universal_time: Option<NetworkTime>,
+	/// How many hours have been added to local time for daylight saving
+	pub daylight_saving_hours: Option<u8>,
+}
+
+/// Why the modem is going to sleep. See `ModemSleep`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ModemSleepKind {
+	/// Power Saving Mode
+	Psm,
+	/// No RF activity is expected (e.g. eDRX)
+	RfInactivity,
+	/// Limited service, e.g. no suitable cell
+	LimitedService,
+	/// Flight mode (`AT+CFUN=4`)
+	FlightMode,
+	/// Some other value we don't recognise
+	Other(u8),
+}
+
+/// The contents of a `%XMODEMSLEEP` notification.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ModemSleep {
+	/// Why the modem is going to sleep
+	pub kind: ModemSleepKind,
+	/// How long the modem expects to sleep for, in milliseconds. Zero means
+	/// the modem is leaving sleep. `None` means the modem will sleep until
+	/// woken up.
+	pub duration_ms: Option<u64>,
+}
+
+/// Which modem traces to produce. See `enable_modem_trace`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ModemTraceLevel {
+	/// Only a core dump, if the modem crashes (1)
+	CoredumpOnly,
+	/// Everything (2)
+	Full,
+	/// IP traces only (4)
+	IpOnly,
+	/// LTE and IP traces (5)
+	LteAndIp,
+}
+
+/// A notification the modem has sent us without being asked. See
+/// `next_notification`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Notification {
+	/// The network has told us the time (`%XTIME`). See
+	/// `enable_network_time_notifications`.
+	NetworkTime(NetworkTimeUpdate),
+	/// The modem is about to enter, or has just left, sleep
+	/// (`%XMODEMSLEEP`). See `enable_modem_sleep_notifications`.
+	ModemSleep(ModemSleep),
+}
+
+/// The network the modem has registered on. See `wait_for_registration`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Registration {
+	/// True if we are registered on a network other than our home network
+	/// (`+CEREG: 5`).
+	pub roaming: bool,
+	/// The network we are registered on - the Mobile Country Code followed by
+	/// the Mobile Network Code, e.g. `"23410"`. Taken from `AT%XMONITOR`, so
+	/// `None` if the modem couldn't tell us just then.
+	pub plmn: Option<heapless::String<6>>,
+}
+
+/// Whether the modem is registered on a network, as reported by `AT+CEREG`.
+/// See `get_registration_status`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RegistrationStatus {
+	/// Not registered, and not looking for a network (`0`)
+	NotRegistered,
+	/// Registered on our home network (`1`)
+	Registered,
+	/// Not registered, but looking for a network (`2`)
+	Searching,
+	/// The network rejected our attempt to register (`3`)
+	Denied,
+	/// Registered on a network other than our home network (`5`)
+	Roaming,
+	/// Any other status, e.g. `4` (unknown) or `90` (SIM failure)
+	Unknown,
+}
+
+/// The signal quality of the serving cell, as reported by `AT+CESQ`. See
+/// `get_signal_quality`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SignalQuality {
+	/// Reference Signal Received Power, in dBm (-141 to -44, where -141 means
+	/// below -140). `None` if the modem doesn't know.
+	pub rsrp_dbm: Option<i16>,
+	/// Reference Signal Received Quality, in dB (-20 to -3, where -20 means
+	/// below -19.5). `None` if the modem doesn't know.
+	pub rsrq_db: Option<f32>,
+}
+
+/// The cell the modem is registered on, as reported by `AT%XMONITOR`. See
+/// `get_cell_info`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CellInfo {
+	/// E-UTRAN cell ID
+	pub cell_id: u32,
+	/// Tracking Area Code
+	pub tac: u32,
+	/// Mobile Country Code and Mobile Network Code (e.g. `"26295"`)
+	pub plmn: heapless::String<6>,
+	/// The LTE band in use
+	pub band: u8,
+	/// The access technology in use, if we recognise it
+	pub access_technology: Option<AccessTechnology>,
+	/// Reference Signal Received Power, in dBm (-141 to -44, where -141 means
+	/// below -140). `None` if the modem doesn't know.
+	pub rsrp_dbm: Option<i16>,
+	/// Reference Signal Received Quality, in dB (-20 to -3, where -20 means
+	/// below -19.5). `None` if the modem doesn't know.
+	pub rsrq_db: Option<f32>,
+	/// Signal to noise ratio, in dB (-25 to 24, where -25 means below -24 and
+	/// 24 means 24 or more). `None` if the modem doesn't know.
+	pub snr_db: Option<i8>,
+}
+
+/// A radio access technology the modem supports.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AccessTechnology {
+	/// LTE-M (LTE Cat-M1)
+	LteM,
+	/// NB-IoT (LTE Cat-NB1)
+	NbIot,
+}
+
+/// The eDRX cycle lengths defined in 3GPP TS 24.008. Not all of them are
+/// allowed on NB-IoT.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum EdrxCycle {
+	/// 5.12 seconds (LTE-M only)
+	Ms5120 = 0,
+	/// 10.24 seconds (LTE-M only)
+	Ms10240 = 1,
+	/// 20.48 seconds
+	Ms20480 = 2,
+	/// 40.96 seconds
+	Ms40960 = 3,
+	/// 61.44 seconds (LTE-M only)
+	Ms61440 = 4,
+	/// 81.92 seconds
+	Ms81920 = 5,
+	/// 102.4 seconds (LTE-M only)
+	Ms102400 = 6,
+	/// 122.88 seconds (LTE-M only)
+	Ms122880 = 7,
+	/// 143.36 seconds (LTE-M only)
+	Ms143360 = 8,
+	/// 163.84 seconds
+	Ms163840 = 9,
+	/// 327.68 seconds
+	Ms327680 = 10,
+	/// 655.36 seconds
+	Ms655360 = 11,
+	/// 1310.72 seconds
+	Ms1310720 = 12,
+	/// 2621.44 seconds
+	Ms2621440 = 13,
+	/// 5242.88 seconds
+	Ms5242880 = 14,
+	/// 10485.76 seconds
+	Ms10485760 = 15,
+}
+
+/// The Paging Time Window - how long the modem listens for paging after each
+/// eDRX cycle. It is a multiple of 1.28 seconds on LTE-M, or 2.56 seconds on
+/// NB-IoT. See `set_edrx`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PagingTimeWindow(u8);
+
+/// Whether we can use a network found by `scan_operators`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OperatorStatus {
+	/// The modem doesn't know
+	Unknown,
+	/// We could register on this network
+	Available,
+	/// We are registered on this network
+	Current,
+	/// We are not allowed to register on this network
+	Forbidden,
+}
+
+/// A network found by `scan_operators`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OperatorInfo {
+	/// Whether we can use this network
+	pub status: OperatorStatus,
+	/// The operator's name in full. Often empty, as the modem may not know it.
+	pub long_name: heapless::String<32>,
+	/// The operator's short name. Often empty, as the modem may not know it.
+	pub short_name: heapless::String<16>,
+	/// The network's PLMN - the Mobile Country Code followed by the Mobile
+	/// Network Code, e.g. `"23410"`.
+	pub plmn: heapless::String<6>,
+	/// The access technology the network was found on, if we recognise it.
+	pub access_technology: Option<AccessTechnology>,
 }
 
 //******************************************************************************
 // Constants
 //******************************************************************************
 
-// None
+/// The most neighbour cells the modem will report in a `%NCELLMEAS`
+/// notification.
+pub const MAX_NEIGHBOR_CELLS: usize = 17;
+
+/// How long to wait for the `%NCELLMEAS` notification once the measurement
+/// has started.
+const NCELLMEAS_TIMEOUT_MS: u32 = 10_000;
+
+/// Room for a `%NCELLMEAS` notification reporting `MAX_NEIGHBOR_CELLS`
+/// neighbours. The serving cell takes under 96 bytes, and each neighbour
+/// (`,<earfcn>,<pci>,<rsrp>,<rsrq>,<time_diff>`) under 32.
+const NCELLMEAS_LENGTH: usize = 96 + MAX_NEIGHBOR_CELLS * 32;
+
+/// Room for an `AT+COPS=?` response listing 16 networks. Each network takes
+/// at most 72 bytes (`(3,"<long name>","<short name>","310410",9),`), and
+/// then come the lists of supported modes and formats.
+const COPS_LIST_LENGTH: usize = 16 * 72 + 64;
+
+/// The longest `wait_for_lte_timeout` and `next_notification` wait in one
+/// call to `poll`.
+const NOTIFICATION_POLL_MS: u32 = 1_000;
+
+/// The MAGPIO configuration for the nRF9160-DK (PCA10090NS) and Actinius
+/// Icarus, which routes 1574 MHz to 1577 MHz to the GNSS antenna.
+pub const PCA10090NS_MAGPIO: MagpioConfig<'static> = MagpioConfig {
+	pins: [true, false, false],
+	windows: &[MagpioWindow {
+		state: 1,
+		freq_lo_mhz: 1574,
+		freq_hi_mhz: 1577,
+	}],
+};
+
+/// The units for the periodic TAU timer (T3412 extended), as per 3GPP TS
+/// 24.008 (GPRS Timer 3). Each is the bit pattern for the top three bits and
+/// the length of the unit in seconds, shortest first.
+const T3412_UNITS: [(u8, u64); 7] = [
+	(0b011, 2),
+	(0b100, 30),
+	(0b101, 60),
+	(0b000, 10 * 60),
+	(0b001, 60 * 60),
+	(0b010, 10 * 60 * 60),
+	(0b110, 320 * 60 * 60),
+];
+
+/// The units for the active time timer (T3324), as per 3GPP TS 24.008 (GPRS
+/// Timer 2). Each is the bit pattern for the top three bits and the length of
+/// the unit in seconds, shortest first.
+const T3324_UNITS: [(u8, u64); 3] = [(0b000, 2), (0b001, 60), (0b010, 6 * 60)];
+
+/// The highest LTE band number `AT%XBANDLOCK` accepts.
+pub const MAX_LTE_BAND: u8 = 88;
 
 //******************************************************************************
 // Global Variables
@@ -63,34 +478,283 @@ pub enum SystemMode {
 
 /// Waits for the modem to connect to a network.
 ///
-/// The list of acceptable CEREG response indications is taken from the Nordic
-/// `lte_link_control` driver.
+/// See `wait_for_registration` if you need to know which network, or whether
+/// we are roaming.
 pub fn wait_for_lte() -> Result<(), Error> {
-	debug!("Waiting for LTE...");
-	let skt = crate::at::AtSocket::new()?;
-	// Subscribe
-	skt.write(b"AT+CEREG=2")?;
+	wait_for_registration_within(None).map(|_| ())
+}
 
-	let connected_indications = ["+CEREG: 1", "+CEREG:1", "+CEREG: 5", "+CEREG:5"];
-	'outer: loop {
-		let mut buf = [0u8; 128];
-		let maybe_length = skt.recv(&mut buf)?;
-		if let Some(length) = maybe_length {
-			let s = unsafe { core::str::from_utf8_unchecked(&buf[0..length - 1]) };
-			for line in s.lines() {
-				let line = line.trim();
-				debug!("RX {:?}", line);
-				for ind in &connected_indications {
-					if line.starts_with(ind) {
-						break 'outer;
-					}
-				}
-			}
-		} else {
-			cortex_m::asm::wfe();
+/// Like `wait_for_lte`, but gives up with `Error::Timeout` if the modem
+/// hasn't connected within `timeout`.
+///
+/// We have no clock, so the time is measured by counting how long the
+/// library spends in `nrf_modem_os_timedwait` (see `set_timed_wait_impl`).
+/// Other notifications arriving in the meantime don't use up the timeout,
+/// and don't restart it either.
+pub fn wait_for_lte_timeout(timeout: core::time::Duration) -> Result<(), Error> {
+	let timeout_ms = timeout.as_millis().min(u128::from(u32::MAX)) as u32;
+	wait_for_registration_within(Some(timeout_ms)).map(|_| ())
+}
+
+/// Waits for the modem to connect to a network, then tells you which network
+/// it is and whether we are roaming.
+///
+/// The network comes from `AT%XMONITOR`, which leaves the modem's settings
+/// alone (unlike `current_plmn`). If the modem can't tell us yet, `plmn` is
+/// `None`.
+///
+/// The list of acceptable CEREG response indications is taken from the Nordic
+/// `lte_link_control` driver. If the network rejects our attempt to register,
+/// you get `Error::RegistrationDenied` straight away, rather than waiting
+/// forever.
+pub fn wait_for_registration() -> Result<Registration, Error> {
+	let roaming = wait_for_registration_within(None)?;
+	// Unlike `current_plmn`, `AT%XMONITOR` doesn't change any settings. It
+	// might not have caught up with the `+CEREG` yet, so this is best effort.
+	let plmn = query_line::<{ crate::at::AT_BUFFER_LENGTH }>("AT%XMONITOR", "%XMONITOR:")
+		.ok()
+		.and_then(|xmonitor| parse_xmonitor(&xmonitor).ok().flatten())
+		.map(|info| info.plmn);
+	Ok(Registration { roaming, plmn })
+}
+
+/// Find out whether the modem is registered on a network right now, using
+/// `AT+CEREG?`. Unlike `wait_for_registration`, this doesn't wait.
+pub fn get_registration_status() -> Result<RegistrationStatus, Error> {
+	// The response looks like `+CEREG: <n>,<stat>[,...]`
+	let cereg = query_single("AT+CEREG?", "+CEREG:")?;
+	let stat = cereg.split(',').nth(1).ok_or(Error::BadDataFormat)?;
+	Ok(RegistrationStatus::from_field(Some(stat)))
+}
+
+/// Sends a list of AT commands to the modem, in order, stopping at the first
+/// one which fails. Useful for initialisation sequences.
+///
+/// See `at::send_at_commands`.
+pub fn send_commands(commands: &[&str]) -> Result<(), Error> {
+	crate::at::send_at_commands(commands)
+}
+
+/// Send an AT command which returns a single value, and get that value.
+///
+/// Finds the first line of the response which starts with `prefix`, and
+/// returns the rest of that line with any whitespace trimmed. For example,
+/// `query_single("AT%XICCID", "%XICCID:")` gives you the SIM's ICCID.
+///
+/// Returns `Error::UnrecognisedValue` if no line starts with `prefix`.
+pub fn query_single(cmd: &str, prefix: &str) -> Result<heapless::String<64>, Error> {
+	query_line(cmd, prefix)
+}
+
+/// Register on the given network only, rather than letting the modem pick
+/// one. `plmn` is the Mobile Country Code followed by the Mobile Network Code
+/// (e.g. `"23410"`).
+///
+/// Sends `AT+COPS=1`. Returns `Error::InvalidArgument` if `plmn` isn't five
+/// or six digits. Use `set_operator_automatic` to undo this.
+pub fn set_operator(plmn: &str, act: AccessTechnology) -> Result<(), Error> {
+	let valid = (plmn.len() == 5 || plmn.len() == 6) && plmn.bytes().all(|b| b.is_ascii_digit());
+	if !valid {
+		return Err(Error::InvalidArgument);
+	}
+	crate::at::AtCommand::new("AT+COPS")
+		.arg(1)
+		.arg(2)
+		.quoted_arg(plmn)
+		.arg(act.cops_act())
+		.send(|_| {})
+}
+
+/// Let the modem pick which network to register on (`AT+COPS=0`). This is
+/// the default.
+pub fn set_operator_automatic() -> Result<(), Error> {
+	crate::at::send_at_command("AT+COPS=0", |_| {})
+}
+
+/// Search for the networks in range, using `AT+COPS=?`.
+///
+/// This can take several minutes. Only the first eight networks found are
+/// returned. If the modem finds so many that its response doesn't fit in our
+/// buffer, you get `Error::Truncated`.
+pub fn scan_operators() -> Result<heapless::Vec<OperatorInfo, 8>, Error> {
+	let mut result = Err(Error::UnrecognisedValue);
+	crate::at::send_long_at_command::<COPS_LIST_LENGTH, _>("AT+COPS=?", |res| {
+		if let Some(fields) = res.strip_prefix("+COPS:") {
+			result = parse_cops_list(fields);
+		}
+	})?;
+	result
+}
+
+/// Get the time from the network, using `AT+CCLK?`.
+///
+/// The result is in local time, with `timezone_quarter_hours` giving the
+/// offset from UTC. Returns `Error::BadDataFormat` if the modem doesn't know
+/// the time yet (it learns it when it registers on a network).
+pub fn get_network_time() -> Result<NetworkTime, Error> {
+	parse_cclk(&query_single("AT+CCLK?", "+CCLK:")?)
+}
+
+/// Get the modem's idea of which network operator we are on, as reported by
+/// `AT%XOPERID`. Zero means the operator is unknown; see Nordic's AT
+/// command reference for the other values.
+pub fn operator_id() -> Result<u8, Error> {
+	parse_field(Some(&query_single("AT%XOPERID", "%XOPERID:")?))
+}
+
+/// Get the version of the modem firmware (e.g. `"mfw_nrf9160_1.3.1"`), using
+/// `AT+CGMR`.
+pub fn get_firmware_version() -> Result<heapless::String<40>, Error> {
+	let mut result = Err(Error::UnrecognisedValue);
+	// The response is just the version, with no prefix
+	crate::at::send_at_command("AT+CGMR", |res| {
+		if result.is_err() && !res.is_empty() {
+			let mut s = heapless::String::new();
+			result = s
+				.push_str(res.trim())
+				.map(|_| s)
+				.map_err(|_| Error::BadDataFormat);
+		}
+	})?;
+	result
+}
+
+/// Get the modem's UUID, using `AT%XMODEMUUID`. This identifies the modem
+/// (and so the device) uniquely, and doesn't change if the firmware is
+/// updated.
+pub fn get_modem_uuid() -> Result<heapless::String<36>, Error> {
+	let uuid = query_single("AT%XMODEMUUID", "%XMODEMUUID:")?;
+	let mut result = heapless::String::new();
+	result.push_str(&uuid).map_err(|_| Error::BadDataFormat)?;
+	Ok(result)
+}
+
+/// Get the details of the cell the modem is registered on, using
+/// `AT%XMONITOR`. RSRQ isn't in that response, so it comes from `AT+CESQ`.
+///
+/// Returns `None` if the modem isn't registered on a network.
+pub fn get_cell_info() -> Result<Option<CellInfo>, Error> {
+	let xmonitor: heapless::String<{ crate::at::AT_BUFFER_LENGTH }> =
+		query_line("AT%XMONITOR", "%XMONITOR:")?;
+	match parse_xmonitor(&xmonitor)? {
+		Some(mut info) => {
+			info.rsrq_db = get_signal_quality()?.rsrq_db;
+			Ok(Some(info))
 		}
+		None => Ok(None),
 	}
-	Ok(())
+}
+
+/// Get the signal quality of the serving cell, using `AT+CESQ`.
+pub fn get_signal_quality() -> Result<SignalQuality, Error> {
+	parse_cesq(&query_single("AT+CESQ", "+CESQ:")?)
+}
+
+/// Ask the network for Power Saving Mode, with the given periodic TAU (how
+/// often the modem wakes up to tell the network it is still there) and active
+/// time (how long it stays reachable after each wake-up).
+///
+/// The network only has a limited set of timer values, so each duration is
+/// rounded up to the next one it can represent (or down, if it's more than the
+/// longest). The network may give you something else entirely - check
+/// `AT+CEREG?` to see what it granted.
+pub fn set_psm(
+	requested_tau: core::time::Duration,
+	requested_active: core::time::Duration,
+) -> Result<(), Error> {
+	let tau = timer_bits(encode_gprs_timer(requested_tau, &T3412_UNITS));
+	let active = timer_bits(encode_gprs_timer(requested_active, &T3324_UNITS));
+	crate::at::AtCommand::new("AT+CPSMS")
+		.arg(1)
+		.empty_arg()
+		.empty_arg()
+		.quoted_arg(&tau)
+		.quoted_arg(&active)
+		.send(|_| {})
+}
+
+/// Stop asking the network for Power Saving Mode.
+pub fn disable_psm() -> Result<(), Error> {
+	crate::at::send_at_command("AT+CPSMS=0", |_| {})
+}
+
+/// Ask the network for extended discontinuous reception (eDRX) on the given
+/// access technology. The modem will sleep for `edrx_cycle` between each
+/// paging window, so the longer the cycle, the longer it takes to reach the
+/// device from the network.
+///
+/// Sends `AT+CEDRXS` for the cycle and `AT%XPTW` for the paging time window.
+/// The network may grant something different.
+pub fn set_edrx(
+	mode: AccessTechnology,
+	edrx_cycle: EdrxCycle,
+	ptw: PagingTimeWindow,
+) -> Result<(), Error> {
+	let mut skt = crate::at::AtSocket::new()?;
+	let command = crate::at::AtCommand::new("AT+CEDRXS")
+		.arg(2)
+		.arg(mode.edrx_act_type())
+		.quoted_arg(&nibble_bits(edrx_cycle as u8));
+	skt.send_command(command.as_str()?)?;
+	skt.poll_response(|_| {})?;
+	let command = crate::at::AtCommand::new("AT%XPTW")
+		.arg(mode.edrx_act_type())
+		.quoted_arg(&nibble_bits(ptw.0));
+	skt.send_command(command.as_str()?)?;
+	skt.poll_response(|_| {})
+}
+
+/// Only let the modem use the given LTE bands (e.g. `&[3, 8, 20]`), using
+/// `AT%XBANDLOCK`.
+///
+/// Returns `Error::InvalidArgument` if `bands` is empty, or holds a band
+/// outside `1..=MAX_LTE_BAND`. Set the lock while the modem is off, before
+/// calling `on`.
+pub fn set_band_lock(bands: &[u8]) -> Result<(), Error> {
+	let highest = bands.iter().copied().max().ok_or(Error::InvalidArgument)?;
+	if highest > MAX_LTE_BAND || bands.contains(&0) {
+		return Err(Error::InvalidArgument);
+	}
+	// One character per band, with the highest band first and band 1 last
+	let mut mask: heapless::String<{ MAX_LTE_BAND as usize }> = heapless::String::new();
+	for band in (1..=highest).rev() {
+		let bit = if bands.contains(&band) { '1' } else { '0' };
+		// Can't fail - there are at most MAX_LTE_BAND characters
+		let _ = mask.push(bit);
+	}
+	crate::at::AtCommand::new("AT%XBANDLOCK")
+		.arg(1)
+		.quoted_arg(&mask)
+		.send(|_| {})
+}
+
+/// Let the modem use every band it supports again.
+pub fn clear_band_lock() -> Result<(), Error> {
+	crate::at::send_at_command("AT%XBANDLOCK=0", |_| {})
+}
+
+/// Stop using eDRX, and forget any eDRX settings.
+pub fn disable_edrx() -> Result<(), Error> {
+	crate::at::send_at_command("AT+CEDRXS=3", |_| {})
+}
+
+/// Get the network we are registered on - the Mobile Country Code followed by
+/// the Mobile Network Code, e.g. `"23410"`. Uses `AT+COPS?`.
+///
+/// Returns `Error::UnrecognisedValue` if we are not registered.
+pub fn current_plmn() -> Result<heapless::String<6>, Error> {
+	// Ask for the operator in numeric format
+	crate::at::send_at_command("AT+COPS=3,2", |_| {})?;
+	// The response looks like `+COPS: 0,2,"23410",7`
+	let cops = query_single("AT+COPS?", "+COPS:")?;
+	let plmn = cops
+		.split(',')
+		.nth(2)
+		.map(|f| f.trim().trim_matches('"'))
+		.ok_or(Error::UnrecognisedValue)?;
+	let mut result = heapless::String::new();
+	result.push_str(plmn).map_err(|_| Error::BadDataFormat)?;
+	Ok(result)
 }
 
 /// Powers the modem on and sets it to auto-register, but does not wait for it
@@ -115,60 +779,1302 @@ pub fn off() -> Result<(), Error> {
 	Ok(())
 }
 
+/// Resets the modem by powering it off and back on again.
+///
+/// The library stays initialised. Any open IP sockets are disconnected by
+/// the modem, and will return an error from their next operation. You will
+/// need to call `wait_for_lte` again before creating new ones.
+pub fn reset() -> Result<(), Error> {
+	debug!("Resetting modem");
+	off()?;
+	on()
+}
+
+/// Resets the modem by shutting down the library and starting it again, with
+/// the same heap and shared memory layout as before.
+///
+/// All existing sockets can no longer be used, and will return
+/// `Error::SocketInvalidated`. You will need to configure the modem (e.g.
+/// with `set_system_mode`) and call `on` again afterwards.
+pub fn full_reset() -> Result<(), Error> {
+	debug!("Fully resetting modem");
+	crate::shutdown();
+	crate::restart()
+}
+
 /// Enable GNSS on the nRF9160-DK (PCA10090NS)
 ///
 /// Sends a AT%XMAGPIO command which activates the off-chip GNSS RF routing
 /// switch when receiving signals between 1574 MHz and 1577 MHz.
 ///
 /// Works on the nRF9160-DK (PCA10090NS) and Actinius Icarus. Other PCBs may
-/// use different MAGPIO pins to control the GNSS switch.
+/// use different MAGPIO pins to control the GNSS switch - see
+/// `configure_magpio`.
 pub fn configure_gnss_on_pca10090ns() -> Result<(), Error> {
 	debug!("Configuring XMAGPIO pins for 1574-1577 MHz");
 	// Configure the GNSS antenna. See `nrf/samples/nrf9160/gps/src/main.c`.
-	crate::at::send_at_command("AT%XMAGPIO=1,0,0,1,1,1574,1577", |_| {})?;
+	configure_magpio(PCA10090NS_MAGPIO)
+}
+
+/// Configure which MAGPIO pins the modem drives, and how, with an
+/// `AT%XMAGPIO` command.
+///
+/// Use this to enable the GNSS antenna switch on boards other than the
+/// nRF9160-DK.
+pub fn configure_magpio(config: MagpioConfig) -> Result<(), Error> {
+	use core::fmt::Write;
+	let mut command: heapless::String<128> = heapless::String::new();
+	write!(
+		command,
+		"AT%XMAGPIO={},{},{},{}",
+		config.pins[0] as u8,
+		config.pins[1] as u8,
+		config.pins[2] as u8,
+		config.windows.len()
+	)?;
+	for window in config.windows {
+		write!(
+			command,
+			",{},{},{}",
+			window.state, window.freq_lo_mhz, window.freq_hi_mhz
+		)?;
+	}
+	debug!("{:?} => {:?}", config, command);
+	crate::at::send_at_command(&command, |_| {})?;
+	Ok(())
+}
+
+/// Configure when the modem drives the COEX0 pin, with an `AT%XCOEX0`
+/// command. Boards often use COEX0 to enable an external GNSS LNA.
+///
+/// Passing an empty list disables COEX0.
+pub fn configure_coex0(windows: &[Coex0Window]) -> Result<(), Error> {
+	use core::fmt::Write;
+	let mut command: heapless::String<128> = heapless::String::new();
+	write!(command, "AT%XCOEX0")?;
+	if !windows.is_empty() {
+		write!(command, "={}", windows.len())?;
+		for window in windows {
+			write!(
+				command,
+				",{},{},{}",
+				window.state as u8, window.freq_lo_mhz, window.freq_hi_mhz
+			)?;
+		}
+	}
+	debug!("{:?} => {:?}", windows, command);
+	crate::at::send_at_command(&command, |_| {})?;
 	Ok(())
 }
 
-/// Set which radios should be active. Only works when modem is off.
-pub fn set_system_mode(mode: SystemMode) -> Result<(), Error> {
-	let at_command = match mode {
-		SystemMode::LteM => "AT%XSYSTEMMODE=1,0,0,0",
-		SystemMode::NbIot => "AT%XSYSTEMMODE=0,1,0,0",
-		SystemMode::GnssOnly => "AT%XSYSTEMMODE=0,0,1,0",
-		SystemMode::LteMAndGnss => "AT%XSYSTEMMODE=1,0,1,0",
-		SystemMode::NbIotAndGnss => "AT%XSYSTEMMODE=0,1,1,0",
+/// Get the state of the SIM card.
+///
+/// Check this before calling `wait_for_lte`, as the modem cannot register on
+/// the network while the SIM is waiting for a PIN.
+pub fn sim_status() -> Result<SimStatus, Error> {
+	let mut result = Err(Error::UnrecognisedValue);
+	crate::at::send_at_command("AT+CPIN?", |res| {
+		if let Some(status) = res.strip_prefix("+CPIN:") {
+			result = match status.trim() {
+				"READY" => Ok(SimStatus::Ready),
+				"SIM PIN" => Ok(SimStatus::SimPin),
+				"SIM PUK" => Ok(SimStatus::SimPuk),
+				"SIM PIN2" => Ok(SimStatus::SimPin2),
+				"SIM PUK2" => Ok(SimStatus::SimPuk2),
+				"PH-SIM PIN" => Ok(SimStatus::PhoneSimPin),
+				_ => Err(Error::UnrecognisedValue),
+			};
+		}
+		debug!("{:?} => {:?}", res, result);
+	})?;
+	result
+}
+
+/// Unlock the SIM card by entering its PIN.
+///
+/// The PIN must be 4 to 8 decimal digits.
+pub fn enter_pin(pin: &str) -> Result<(), Error> {
+	if pin.len() < 4 || pin.len() > 8 || !pin.bytes().all(|b| b.is_ascii_digit()) {
+		return Err(Error::BadDataFormat);
+	}
+	crate::at::AtCommand::new("AT+CPIN")
+		.quoted_arg(pin)
+		.send(|_| {})
+}
+
+/// Get the number of attempts remaining to enter the SIM's PIN and PUK.
+pub fn remaining_pin_attempts() -> Result<PinAttempts, Error> {
+	Ok(PinAttempts {
+		pin: get_remaining_attempts("AT+CPINR=\"SIM PIN\"")?,
+		puk: get_remaining_attempts("AT+CPINR=\"SIM PUK\"")?,
+	})
+}
+
+/// Measure the serving cell and any neighbouring cells.
+///
+/// Sends `AT%NCELLMEAS`, then waits for the `%NCELLMEAS` notification with
+/// the results. Returns `Error::Timeout` if the results don't arrive within
+/// ten seconds. The modem must be registered on the network.
+pub fn measure_neighbor_cells() -> Result<NeighborCells, Error> {
+	let mut skt = crate::at::AtSocket::new()?;
+	let mut result = None;
+	skt.send_command("AT%NCELLMEAS")?;
+	// The results might arrive before the OK
+	skt.read_response::<NCELLMEAS_LENGTH, _>(None, |res| {
+		if res.starts_with("%NCELLMEAS:") {
+			result = Some(parse_ncellmeas(res));
+		}
+	})?;
+	match result {
+		Some(result) => result,
+		None => {
+			wait_for_notification::<NCELLMEAS_LENGTH, _, _>(&skt, NCELLMEAS_TIMEOUT_MS, |res| {
+				if res.starts_with("%NCELLMEAS:") {
+					Some(parse_ncellmeas(res))
+				} else {
+					None
+				}
+			})?
+		}
+	}
+}
+
+/// Ask the modem to send a `%XTIME` notification on the given socket whenever
+/// the network tells it the time. Read them with `next_notification`.
+///
+/// Networks usually send the time when the modem registers.
+pub fn enable_network_time_notifications(skt: &mut crate::at::AtSocket) -> Result<(), Error> {
+	skt.send_command("AT%XTIME=1")?;
+	skt.poll_response(|_| {})
+}
+
+/// Ask the modem to send a `%XMODEMSLEEP` notification on the given socket
+/// before it goes to sleep, and again when it wakes up. Read them with
+/// `next_notification`.
+///
+/// The notification is sent `warning_time_ms` before the modem sleeps, and
+/// only for sleeps of at least `threshold_ms`.
+pub fn enable_modem_sleep_notifications(
+	skt: &mut crate::at::AtSocket,
+	warning_time_ms: u32,
+	threshold_ms: u32,
+) -> Result<(), Error> {
+	use core::fmt::Write;
+	let mut command: heapless::String<48> = heapless::String::new();
+	write!(
+		command,
+		"AT%XMODEMSLEEP=1,{},{}",
+		warning_time_ms, threshold_ms
+	)?;
+	skt.send_command(&command)?;
+	skt.poll_response(|_| {})
+}
+
+/// Wait up to `timeout` for a notification we understand to arrive on the
+/// given socket. Anything else received is ignored.
+///
+/// The time is measured the same way as in `wait_for_lte_timeout`.
+pub fn next_notification(
+	skt: &crate::at::AtSocket,
+	timeout: core::time::Duration,
+) -> Result<Notification, Error> {
+	let timeout_ms = timeout.as_millis().min(u128::from(u32::MAX)) as u32;
+	wait_for_notification::<{ crate::at::AT_BUFFER_LENGTH }, _, _>(
+		skt,
+		timeout_ms,
+		Notification::parse,
+	)
+}
+
+/// Set which radios should be active, and which of LTE-M and NB-IoT is
+/// preferred. Only works when modem is off.
+pub fn set_system_mode(mode: SystemMode, preference: RatPreference) -> Result<(), Error> {
+	use core::fmt::Write;
+	let mode_str = match mode {
+		SystemMode::LteM => "1,0,0",
+		SystemMode::NbIot => "0,1,0",
+		SystemMode::GnssOnly => "0,0,1",
+		SystemMode::LteMAndGnss => "1,0,1",
+		SystemMode::NbIotAndGnss => "0,1,1",
+		SystemMode::LteMAndNbIot => "1,1,0",
+		SystemMode::LteMAndNbIotAndGnss => "1,1,1",
 	};
-	debug!("{:?} => {:?}", mode, at_command);
-	crate::at::send_at_command(at_command, |_| {})?;
+	let mut at_command: heapless::String<32> = heapless::String::new();
+	write!(
+		at_command,
+		"AT%XSYSTEMMODE={},{}",
+		mode_str,
+		preference.as_digit()
+	)?;
+	debug!("{:?}/{:?} => {:?}", mode, preference, at_command);
+	crate::at::send_at_command(&at_command, |_| {})?;
 	Ok(())
 }
 
-/// Get which radios should be active
-pub fn get_system_mode() -> Result<SystemMode, Error> {
+/// Get which radios should be active, and which of LTE-M and NB-IoT is
+/// preferred.
+pub fn get_system_mode() -> Result<(SystemMode, RatPreference), Error> {
 	let mut result = Err(Error::UnrecognisedValue);
-	// Don't care about final digit - that's just the LTE/NB-IOT preference
 	crate::at::send_at_command("AT%XSYSTEMMODE?", |res| {
-		if res.starts_with("%XSYSTEMMODE: 1,0,0,") {
-			result = Ok(SystemMode::LteM);
+		let mode = if res.starts_with("%XSYSTEMMODE: 1,0,0,") {
+			Some(SystemMode::LteM)
 		} else if res.starts_with("%XSYSTEMMODE: 0,1,0,") {
-			result = Ok(SystemMode::NbIot);
+			Some(SystemMode::NbIot)
 		} else if res.starts_with("%XSYSTEMMODE: 0,0,1,") {
-			result = Ok(SystemMode::GnssOnly);
+			Some(SystemMode::GnssOnly)
 		} else if res.starts_with("%XSYSTEMMODE: 1,0,1,") {
-			result = Ok(SystemMode::LteMAndGnss);
+			Some(SystemMode::LteMAndGnss)
 		} else if res.starts_with("%XSYSTEMMODE: 0,1,1,") {
-			result = Ok(SystemMode::NbIotAndGnss);
+			Some(SystemMode::NbIotAndGnss)
+		} else if res.starts_with("%XSYSTEMMODE: 1,1,0,") {
+			Some(SystemMode::LteMAndNbIot)
+		} else if res.starts_with("%XSYSTEMMODE: 1,1,1,") {
+			Some(SystemMode::LteMAndNbIotAndGnss)
+		} else {
+			None
+		};
+		// The preference is the final digit
+		let preference = res
+			.rsplit(',')
+			.next()
+			.and_then(|digit| RatPreference::from_digit(digit.trim()));
+		if let (Some(mode), Some(preference)) = (mode, preference) {
+			result = Ok((mode, preference));
 		}
 		debug!("{:?} => {:?}", res, result);
 	})?;
 	result
 }
 
+impl PagingTimeWindow {
+	/// Create a Paging Time Window which is `windows` lots of 1.28 seconds
+	/// (LTE-M) or 2.56 seconds (NB-IoT) long. `windows` must be from 1 to 16.
+	pub fn new(windows: u8) -> Option<PagingTimeWindow> {
+		if (1..=16).contains(&windows) {
+			Some(PagingTimeWindow(windows - 1))
+		} else {
+			None
+		}
+	}
+}
+
+impl Notification {
+	/// Parse a line received from an AT socket. Returns `None` if it is not a
+	/// notification we understand.
+	pub fn parse(line: &str) -> Option<Notification> {
+		if let Some(fields) = line.strip_prefix("%XTIME:") {
+			parse_xtime(fields).map(Notification::NetworkTime)
+		} else if let Some(fields) = line.strip_prefix("%XMODEMSLEEP:") {
+			parse_xmodemsleep(fields).map(Notification::ModemSleep)
+		} else {
+			None
+		}
+	}
+}
+
+impl RatPreference {
+	/// The digit used for this preference in `AT%XSYSTEMMODE`.
+	fn as_digit(self) -> u8 {
+		match self {
+			RatPreference::Auto => 0,
+			RatPreference::LteMPreferred => 1,
+			RatPreference::NbIotPreferred => 2,
+			RatPreference::PltwPreferred => 3,
+		}
+	}
+
+	/// Convert a digit from `AT%XSYSTEMMODE?` into a preference.
+	fn from_digit(digit: &str) -> Option<RatPreference> {
+		match digit {
+			"0" => Some(RatPreference::Auto),
+			"1" => Some(RatPreference::LteMPreferred),
+			"2" => Some(RatPreference::NbIotPreferred),
+			"3" => Some(RatPreference::PltwPreferred),
+			_ => None,
+		}
+	}
+}
+
+/// Collect modem trace data, for forwarding to Nordic's trace tools.
+///
+/// The modem library hands trace data to us as it arrives, and we hold it in
+/// a fixed-size buffer until you call this function. Up to `buf.len()` bytes
+/// are moved out of that buffer, and the number of bytes copied is returned.
+/// A return value of zero means there is no trace data waiting.
+///
+/// If you don't call this function often enough, the buffer fills up and any
+/// further trace data is dropped (the data already in the buffer is kept).
+/// Use `trace_bytes_dropped` to find out if this has happened.
+///
+/// The modem only produces trace data if a trace region has been given to
+/// the library (see `ShmemConfig::with_trace`) and tracing has been turned
+/// on with `enable_modem_trace`.
+pub fn read_trace(buf: &mut [u8]) -> Result<usize, Error> {
+	Ok(crate::ffi::read_trace(buf))
+}
+
+/// Get the number of modem trace bytes which have been dropped (because
+/// `read_trace` wasn't called often enough) since this function was last
+/// called.
+pub fn trace_bytes_dropped() -> u32 {
+	crate::ffi::take_trace_bytes_dropped()
+}
+
+/// Have modem trace data passed to `sink` as it arrives (e.g. to stream it
+/// out over a UART), rather than buffered for `read_trace`. Pass `None` to
+/// go back to buffering.
+///
+/// `sink` is called from the trace interrupt (see `trace_irq_handler`), so
+/// it should be quick.
+pub fn set_trace_sink(sink: Option<fn(&[u8])>) {
+	crate::ffi::set_trace_sink(sink);
+}
+
+/// Ask the modem to start producing traces, with `AT%XMODEMTRACE`.
+///
+/// You also need to give the library a trace region, by starting it with
+/// `init_with_shmem(ShmemConfig::with_trace())`, and to collect the data with
+/// `read_trace` or `set_trace_sink`.
+pub fn enable_modem_trace(level: ModemTraceLevel) -> Result<(), Error> {
+	crate::at::AtCommand::new("AT%XMODEMTRACE")
+		.arg(1)
+		.arg(level.as_u8())
+		.send(|_| {})
+}
+
+/// Ask the modem to stop producing traces.
+pub fn disable_modem_trace() -> Result<(), Error> {
+	crate::at::send_at_command("AT%XMODEMTRACE=0", |_| {})
+}
+
 //******************************************************************************
 // Private Functions and Impl on Private Types
 //******************************************************************************
 
-// None
+/// Wait for a notification to arrive on the given AT socket, reading it into
+/// a buffer of `N` bytes.
+///
+/// Each line received is passed to `function` until it returns `Some`. If
+/// that hasn't happened within `timeout_ms` milliseconds, gives up and
+/// returns `Error::Timeout`. Lines `function` isn't interested in don't
+/// restart the timeout; see `wait_for_at_socket` for how the time is
+/// measured.
+fn wait_for_notification<const N: usize, T, F>(
+	skt: &crate::at::AtSocket,
+	timeout_ms: u32,
+	mut function: F,
+) -> Result<T, Error>
+where
+	F: FnMut(&str) -> Option<T>,
+{
+	let mut remaining_ms = timeout_ms;
+	loop {
+		if remaining_ms == 0 {
+			return Err(Error::Timeout);
+		}
+		let (ready, waited_ms) = wait_for_at_socket(skt, remaining_ms)?;
+		remaining_ms -= waited_ms;
+		if !ready {
+			continue;
+		}
+		let mut buf = [0u8; N];
+		if let Some(length) = skt.recv_datagram(&mut buf)? {
+			let s = core::str::from_utf8(&buf[0..length]).map_err(|_| Error::BadDataFormat)?;
+			for line in s.lines() {
+				let line = line.trim_matches(|c: char| c.is_whitespace() || c == '\0');
+				debug!("RX {:?}", line);
+				if let Some(value) = function(line) {
+					return Ok(value);
+				}
+			}
+		}
+	}
+}
+
+/// Wait up to `remaining_ms` (but no more than `NOTIFICATION_POLL_MS`) for
+/// something to read on the given AT socket. Tells you whether there is
+/// anything, and how many milliseconds the wait used up.
+///
+/// If `poll` timed out, the whole wait was used. Otherwise we ask
+/// `nrf_modem_os_timedwait` how long the library slept, so a burst of
+/// notifications we don't care about costs next to nothing.
+fn wait_for_at_socket(skt: &crate::at::AtSocket, remaining_ms: u32) -> Result<(bool, u32), Error> {
+	let wait_ms = remaining_ms.min(NOTIFICATION_POLL_MS);
+	let waited_before = crate::ffi::waited_ms();
+	let mut poll_list = [crate::PollEntry::new(skt, crate::PollFlags::Read)];
+	let ready = crate::poll(&mut poll_list, wait_ms as i32)? != 0;
+	let waited_ms = if ready {
+		crate::ffi::waited_ms()
+			.wrapping_sub(waited_before)
+			.min(wait_ms)
+	} else {
+		wait_ms
+	};
+	Ok((ready, waited_ms))
+}
+
+/// Parse a `%NCELLMEAS` notification.
+///
+/// It looks like `%NCELLMEAS: <status>,<cell_id>,<plmn>,<tac>,
+/// <timing_advance>,<earfcn>,<phys_cell_id>,<rsrp>,<rsrq>,<measurement_time>`
+/// followed by `<n_earfcn>,<n_phys_cell_id>,<n_rsrp>,<n_rsrq>,<time_diff>` for
+/// each neighbour cell, and optionally a final
+/// `<timing_advance_measurement_time>`.
+fn parse_ncellmeas(line: &str) -> Result<NeighborCells, Error> {
+	let fields = line
+		.strip_prefix("%NCELLMEAS:")
+		.ok_or(Error::BadDataFormat)?;
+	let mut fields = fields.split(',').map(|f| f.trim().trim_matches('"'));
+	let status: u8 = parse_field(fields.next())?;
+	if status != 0 {
+		return Err(Error::AtError(crate::AtError::Error));
+	}
+	let cell_id = u32::from_str_radix(fields.next().ok_or(Error::BadDataFormat)?, 16)
+		.map_err(|_| Error::BadDataFormat)?;
+	let mut plmn = heapless::String::new();
+	plmn.push_str(fields.next().ok_or(Error::BadDataFormat)?)
+		.map_err(|_| Error::BadDataFormat)?;
+	let tac = u32::from_str_radix(fields.next().ok_or(Error::BadDataFormat)?, 16)
+		.map_err(|_| Error::BadDataFormat)?;
+	let serving = ServingCell {
+		cell_id,
+		plmn,
+		tac,
+		timing_advance: parse_field(fields.next())?,
+		earfcn: parse_field(fields.next())?,
+		pci: parse_field(fields.next())?,
+		rsrp: parse_field(fields.next())?,
+		rsrq: parse_field(fields.next())?,
+	};
+	// Skip the measurement time
+	let _: u64 = parse_field(fields.next())?;
+	let mut neighbors = heapless::Vec::new();
+	while let Some(earfcn) = fields.next() {
+		let pci = match fields.next() {
+			Some(pci) => pci,
+			// This was the timing advance measurement time, not a neighbour
+			None => break,
+		};
+		let neighbor = NeighborCell {
+			earfcn: parse_field(Some(earfcn))?,
+			pci: parse_field(Some(pci))?,
+			rsrp: parse_field(fields.next())?,
+			rsrq: parse_field(fields.next())?,
+			time_diff: parse_field(fields.next())?,
+		};
+		neighbors.push(neighbor).map_err(|_| Error::BadDataFormat)?;
+	}
+	Ok(NeighborCells { serving, neighbors })
+}
+
+/// Parse a decimal AT response field.
+fn parse_field<T>(field: Option<&str>) -> Result<T, Error>
+where
+	T: core::str::FromStr,
+{
+	field
+		.ok_or(Error::BadDataFormat)?
+		.parse()
+		.map_err(|_| Error::BadDataFormat)
+}
+
+impl RegistrationStatus {
+	/// Convert the `<stat>` field of a `+CEREG` response.
+	fn from_field(field: Option<&str>) -> RegistrationStatus {
+		match field.map(str::trim) {
+			Some("0") => RegistrationStatus::NotRegistered,
+			Some("1") => RegistrationStatus::Registered,
+			Some("2") => RegistrationStatus::Searching,
+			Some("3") => RegistrationStatus::Denied,
+			Some("5") => RegistrationStatus::Roaming,
+			_ => RegistrationStatus::Unknown,
+		}
+	}
+}
+
+impl AccessTechnology {
+	/// The value for this access technology in `AT+COPS`.
+	fn cops_act(self) -> u8 {
+		match self {
+			AccessTechnology::LteM => 7,
+			AccessTechnology::NbIot => 9,
+		}
+	}
+
+	/// Convert an `AT+COPS` access technology value, if we recognise it.
+	fn from_cops_act(value: u8) -> Option<AccessTechnology> {
+		match value {
+			7 => Some(AccessTechnology::LteM),
+			9 => Some(AccessTechnology::NbIot),
+			_ => None,
+		}
+	}
+
+	/// The value for this access technology in `AT+CEDRXS` and `AT%XPTW`.
+	fn edrx_act_type(self) -> u8 {
+		match self {
+			AccessTechnology::LteM => 4,
+			AccessTechnology::NbIot => 5,
+		}
+	}
+}
+
+impl ModemTraceLevel {
+	/// The `<set_id>` for this level in `AT%XMODEMTRACE`.
+	fn as_u8(self) -> u8 {
+		match self {
+			ModemTraceLevel::CoredumpOnly => 1,
+			ModemTraceLevel::Full => 2,
+			ModemTraceLevel::IpOnly => 4,
+			ModemTraceLevel::LteAndIp => 5,
+		}
+	}
+}
+
+/// Format the bottom four bits of `value` as the string of four binary digits
+/// the modem wants.
+fn nibble_bits(value: u8) -> heapless::String<4> {
+	use core::fmt::Write;
+	let mut result = heapless::String::new();
+	// Can't fail - the string is exactly four characters
+	let _ = write!(result, "{:04b}", value & 0x0F);
+	result
+}
+
+/// Encode a duration as a GPRS timer octet: a unit in the top three bits and
+/// a multiplier (0 to 31) in the bottom five. Uses the shortest unit which
+/// can hold the duration, rounding up.
+fn encode_gprs_timer(duration: core::time::Duration, units: &[(u8, u64)]) -> u8 {
+	let secs = duration.as_secs() + u64::from(duration.subsec_nanos() != 0);
+	for (bits, unit_secs) in units {
+		let value = secs.div_ceil(*unit_secs);
+		if value <= 31 {
+			return (bits << 5) | value as u8;
+		}
+	}
+	// Too long - use the biggest value we can
+	let (bits, _) = units[units.len() - 1];
+	(bits << 5) | 31
+}
+
+/// Format a GPRS timer octet as the string of eight binary digits the modem
+/// wants.
+fn timer_bits(value: u8) -> heapless::String<8> {
+	use core::fmt::Write;
+	let mut result = heapless::String::new();
+	// Can't fail - the string is exactly eight characters
+	let _ = write!(result, "{:08b}", value);
+	result
+}
+
+/// Parse the fields of a `+CESQ` response.
+///
+/// It looks like `+CESQ: <rxlev>,<ber>,<rscp>,<ecno>,<rsrq>,<rsrp>`. Only
+/// the last two apply to LTE. Each is an index as per 3GPP TS 27.007, where
+/// 255 means unknown.
+fn parse_cesq(fields: &str) -> Result<SignalQuality, Error> {
+	let mut fields = fields.split(',').map(str::trim).skip(4);
+	let rsrq_db = rsrq_index_to_db(parse_field(fields.next())?)?;
+	let rsrp_dbm = rsrp_index_to_dbm(parse_field(fields.next())?)?;
+	Ok(SignalQuality { rsrp_dbm, rsrq_db })
+}
+
+/// Convert an RSRP index, as per 3GPP TS 36.133, into dBm. 255 means unknown.
+fn rsrp_index_to_dbm(index: u8) -> Result<Option<i16>, Error> {
+	match index {
+		// Index 0 means less than -140 dBm and each step is 1 dB
+		0..=97 => Ok(Some(i16::from(index) - 141)),
+		255 => Ok(None),
+		_ => Err(Error::BadDataFormat),
+	}
+}
+
+/// Convert an RSRQ index, as per 3GPP TS 36.133, into dB. 255 means unknown.
+fn rsrq_index_to_db(index: u8) -> Result<Option<f32>, Error> {
+	match index {
+		// Index 0 means less than -19.5 dB and each step is 0.5 dB
+		0..=34 => Ok(Some(-20.0 + f32::from(index) * 0.5)),
+		255 => Ok(None),
+		_ => Err(Error::BadDataFormat),
+	}
+}
+
+/// Parse the fields of a `%XMONITOR` response.
+///
+/// It looks like `%XMONITOR: <reg_status>,<full_name>,<short_name>,<plmn>,
+/// <tac>,<AcT>,<band>,<cell_id>,<phys_cell_id>,<EARFCN>,<rsrp>,<snr>,...`,
+/// where the TAC and cell ID are quoted hex strings. If the modem isn't
+/// registered, only `<reg_status>` is present. RSRQ is not included, so is
+/// left as `None`.
+fn parse_xmonitor(fields: &str) -> Result<Option<CellInfo>, Error> {
+	let mut fields = fields.split(',').map(|f| f.trim().trim_matches('"'));
+	match RegistrationStatus::from_field(fields.next()) {
+		RegistrationStatus::Registered | RegistrationStatus::Roaming => {}
+		_ => return Ok(None),
+	}
+	let mut fields = fields.skip(2);
+	let mut plmn = heapless::String::new();
+	plmn.push_str(fields.next().ok_or(Error::BadDataFormat)?)
+		.map_err(|_| Error::BadDataFormat)?;
+	let tac = u32::from_str_radix(fields.next().ok_or(Error::BadDataFormat)?, 16)
+		.map_err(|_| Error::BadDataFormat)?;
+	let access_technology = AccessTechnology::from_cops_act(parse_field(fields.next())?);
+	let band = parse_field(fields.next())?;
+	let cell_id = u32::from_str_radix(fields.next().ok_or(Error::BadDataFormat)?, 16)
+		.map_err(|_| Error::BadDataFormat)?;
+	// Skip the physical cell ID and EARFCN
+	let mut fields = fields.skip(2);
+	let rsrp_dbm = rsrp_index_to_dbm(parse_field(fields.next())?)?;
+	let snr_db = match parse_field::<u8>(fields.next())? {
+		// Index 0 means less than -24 dB and each step is 1 dB
+		index @ 0..=49 => Some(index as i8 - 25),
+		127 => None,
+		_ => return Err(Error::BadDataFormat),
+	};
+	Ok(Some(CellInfo {
+		cell_id,
+		tac,
+		plmn,
+		band,
+		access_technology,
+		rsrp_dbm,
+		rsrq_db: None,
+		snr_db,
+	}))
+}
+
+/// Does the work for `wait_for_registration` and `wait_for_lte_timeout`.
+/// `timeout_ms` of `None` means wait forever. Tells you whether we are
+/// roaming.
+fn wait_for_registration_within(timeout_ms: Option<u32>) -> Result<bool, Error> {
+	debug!("Waiting for LTE...");
+	let skt = crate::at::AtSocket::new()?;
+	// Subscribe
+	skt.write(b"AT+CEREG=2")?;
+
+	let mut remaining_ms = timeout_ms;
+	let roaming = 'outer: loop {
+		let ready = match remaining_ms.as_mut() {
+			Some(0) => return Err(Error::Timeout),
+			Some(ms) => {
+				let (ready, waited_ms) = wait_for_at_socket(&skt, *ms)?;
+				*ms -= waited_ms;
+				ready
+			}
+			None => {
+				let mut poll_list = [crate::PollEntry::new(&skt, crate::PollFlags::Read)];
+				crate::poll(&mut poll_list, crate::POLL_FOREVER)? != 0
+			}
+		};
+		if !ready {
+			continue;
+		}
+		let mut buf = [0u8; crate::at::AT_BUFFER_LENGTH];
+		let maybe_length = skt.recv_datagram(&mut buf)?;
+		if let Some(length) = maybe_length {
+			let s = core::str::from_utf8(&buf[0..length]).map_err(|_| Error::BadDataFormat)?;
+			for line in s.lines() {
+				let line = line.trim_matches(|c: char| c.is_whitespace() || c == '\0');
+				debug!("RX {:?}", line);
+				// The notifications look like `+CEREG: <stat>[,...]`
+				if let Some(fields) = line.strip_prefix("+CEREG:") {
+					match RegistrationStatus::from_field(fields.split(',').next()) {
+						RegistrationStatus::Registered => break 'outer false,
+						RegistrationStatus::Roaming => break 'outer true,
+						RegistrationStatus::Denied => return Err(Error::RegistrationDenied),
+						_ => {}
+					}
+				}
+			}
+		}
+	};
+	Ok(roaming)
+}
+
+/// Does the work for `query_single`, but lets you choose how long a value you
+/// can take.
+fn query_line<const N: usize>(cmd: &str, prefix: &str) -> Result<heapless::String<N>, Error> {
+	let mut result = Err(Error::UnrecognisedValue);
+	crate::at::send_at_command(cmd, |res| {
+		if result.is_err() {
+			if let Some(value) = res.strip_prefix(prefix) {
+				let mut s = heapless::String::new();
+				result = s
+					.push_str(value.trim())
+					.map(|_| s)
+					.map_err(|_| Error::WriteError);
+			}
+		}
+	})?;
+	result
+}
+
+/// Parse the fields of a `+COPS=?` response.
+///
+/// It looks like `+COPS: (2,"","","23410",7),(1,"","","23415",7),,(0,1,3,4),(0,2)`.
+/// Each network is a bracketed `<stat>,<long>,<short>,<numeric>,<AcT>`. The
+/// lists of supported modes and formats come after the empty field.
+///
+/// The operator names are quoted, and may themselves contain commas or
+/// brackets, so we only split on those outside quotes.
+fn parse_cops_list(fields: &str) -> Result<heapless::Vec<OperatorInfo, 8>, Error> {
+	let mut result = heapless::Vec::new();
+	let mut rest = fields.trim();
+	// The empty field before the supported modes ends the list of networks
+	while let Some(group) = rest.strip_prefix('(') {
+		let end = find_unquoted(group, ')').ok_or(Error::BadDataFormat)?;
+		let network = &group[..end];
+		rest = &group[end + 1..];
+		rest = rest.strip_prefix(',').unwrap_or(rest);
+		let mut fields = split_unquoted(network).map(|f| f.trim().trim_matches('"'));
+		let status = match parse_field::<u8>(fields.next())? {
+			1 => OperatorStatus::Available,
+			2 => OperatorStatus::Current,
+			3 => OperatorStatus::Forbidden,
+			_ => OperatorStatus::Unknown,
+		};
+		let mut info = OperatorInfo {
+			status,
+			long_name: heapless::String::new(),
+			short_name: heapless::String::new(),
+			plmn: heapless::String::new(),
+			access_technology: None,
+		};
+		info.long_name
+			.push_str(fields.next().ok_or(Error::BadDataFormat)?)
+			.map_err(|_| Error::BadDataFormat)?;
+		info.short_name
+			.push_str(fields.next().ok_or(Error::BadDataFormat)?)
+			.map_err(|_| Error::BadDataFormat)?;
+		info.plmn
+			.push_str(fields.next().ok_or(Error::BadDataFormat)?)
+			.map_err(|_| Error::BadDataFormat)?;
+		info.access_technology = AccessTechnology::from_cops_act(parse_field(fields.next())?);
+		if result.push(info).is_err() {
+			break;
+		}
+	}
+	Ok(result)
+}
+
+/// Find the first `needle` in `s` which isn't inside double quotes.
+fn find_unquoted(s: &str, needle: char) -> Option<usize> {
+	let mut in_quotes = false;
+	for (idx, c) in s.char_indices() {
+		if c == '"' {
+			in_quotes = !in_quotes;
+		} else if c == needle && !in_quotes {
+			return Some(idx);
+		}
+	}
+	None
+}
+
+/// Split `s` at each comma which isn't inside double quotes.
+fn split_unquoted(s: &str) -> impl Iterator<Item = &str> {
+	let mut in_quotes = false;
+	s.split(move |c| {
+		if c == '"' {
+			in_quotes = !in_quotes;
+		}
+		c == ',' && !in_quotes
+	})
+}
+
+/// Parse the field of a `+CCLK` response.
+///
+/// It looks like `+CCLK: "21/03/04,05:06:07+04"`, where the last part is the
+/// offset from UTC in quarter-hours.
+fn parse_cclk(field: &str) -> Result<NetworkTime, Error> {
+	let field = field.trim().trim_matches('"');
+	// The time zone is the last three characters, e.g. `+04` or `-10`
+	if field.len() != 20 || !field.is_char_boundary(17) {
+		return Err(Error::BadDataFormat);
+	}
+	let (date_time, timezone) = field.split_at(17);
+	let timezone: i8 = timezone
+		.trim_start_matches('+')
+		.parse()
+		.map_err(|_| Error::BadDataFormat)?;
+	let mut parts = date_time.split(['/', ',', ':']);
+	let year: u16 = parse_field(parts.next())?;
+	Ok(NetworkTime {
+		year: 2000 + year,
+		month: parse_field(parts.next())?,
+		day: parse_field(parts.next())?,
+		hour: parse_field(parts.next())?,
+		minute: parse_field(parts.next())?,
+		second: parse_field(parts.next())?,
+		timezone_quarter_hours: timezone,
+	})
+}
+
+/// Parse the fields of a `%XTIME` notification.
+///
+/// It looks like `%XTIME: <local_time_zone>,<universal_time>,
+/// <daylight_saving_time>`, where each field is a quoted string of hex bytes
+/// encoded as per 3GPP TS 24.008, and may be empty.
+fn parse_xtime(fields: &str) -> Option<NetworkTimeUpdate> {
+	let mut fields = fields.split(',').map(|f| f.trim().trim_matches('"'));
+	let local_timezone = fields.next()?;
+	let universal_time = fields.next().unwrap_or("");
+	let daylight_saving = fields.next().unwrap_or("");
+	Some(NetworkTimeUpdate {
+		local_timezone_quarter_hours: if local_timezone.is_empty() {
+			None
+		} else {
+			Some(decode_timezone(local_timezone)?)
+		},
+		universal_time: if universal_time.is_empty() {
+			None
+		} else {
+			Some(decode_universal_time(universal_time)?)
+		},
+		daylight_saving_hours: if daylight_saving.is_empty() {
+			None
+		} else {
+			Some(u8::from_str_radix(daylight_saving, 16).ok()? & 0x03)
+		},
+	})
+}
+
+/// Parse the fields of a `%XMODEMSLEEP` notification.
+///
+/// It looks like `%XMODEMSLEEP: <type>[,<time>]`.
+fn parse_xmodemsleep(fields: &str) -> Option<ModemSleep> {
+	let mut fields = fields.split(',').map(|f| f.trim());
+	let kind = match fields.next()?.parse().ok()? {
+		1 => ModemSleepKind::Psm,
+		3 => ModemSleepKind::RfInactivity,
+		4 => ModemSleepKind::LimitedService,
+		5 => ModemSleepKind::FlightMode,
+		n => ModemSleepKind::Other(n),
+	};
+	let duration_ms = match fields.next() {
+		Some(time) if !time.is_empty() => Some(time.parse().ok()?),
+		_ => None,
+	};
+	Some(ModemSleep { kind, duration_ms })
+}
+
+/// Decode a 3GPP TS 24.008 time and time zone, which is seven semi-octet
+/// bytes - year, month, day, hour, minute, second and time zone.
+fn decode_universal_time(hex: &str) -> Option<NetworkTime> {
+	if hex.len() != 14 {
+		return None;
+	}
+	Some(NetworkTime {
+		year: 2000 + u16::from(decode_semi_octets(hex.get(0..2)?)?),
+		month: decode_semi_octets(hex.get(2..4)?)?,
+		day: decode_semi_octets(hex.get(4..6)?)?,
+		hour: decode_semi_octets(hex.get(6..8)?)?,
+		minute: decode_semi_octets(hex.get(8..10)?)?,
+		second: decode_semi_octets(hex.get(10..12)?)?,
+		timezone_quarter_hours: decode_timezone(hex.get(12..14)?)?,
+	})
+}
+
+/// Decode a byte with two BCD digits, written as two hex characters, where
+/// the first character is the units and the second is the tens.
+fn decode_semi_octets(hex: &str) -> Option<u8> {
+	let mut chars = hex.chars();
+	let units = chars.next()?.to_digit(10)?;
+	let tens = chars.next()?.to_digit(10)?;
+	Some((tens * 10 + units) as u8)
+}
+
+/// Decode a 3GPP TS 24.008 time zone. This is like `decode_semi_octets`, but
+/// bit 3 of the tens digit is a sign bit. The result is in quarter-hours.
+fn decode_timezone(hex: &str) -> Option<i8> {
+	let mut chars = hex.chars();
+	let units = chars.next()?.to_digit(10)?;
+	let tens = chars.next()?.to_digit(16)?;
+	let value = ((tens & 0x07) * 10 + units) as i8;
+	if (tens & 0x08) != 0 {
+		Some(-value)
+	} else {
+		Some(value)
+	}
+}
+
+/// Send an `AT+CPINR` command and parse the `+CPINR: <code>,<retries>`
+/// response.
+fn get_remaining_attempts(command: &str) -> Result<u8, Error> {
+	let mut result = Err(Error::UnrecognisedValue);
+	crate::at::send_at_command(command, |res| {
+		if let Some(fields) = res.strip_prefix("+CPINR:") {
+			result = fields
+				.rsplit(',')
+				.next()
+				.and_then(|retries| retries.trim().parse().ok())
+				.ok_or(Error::BadDataFormat);
+		}
+		debug!("{:?} => {:?}", res, result);
+	})?;
+	result
+}
+
+//******************************************************************************
+// Tests
+//******************************************************************************
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::backend::mock;
+
+	#[test]
+	fn cereg_status() {
+		mock::run(|modem| {
+			modem.reply("AT+CEREG?", "+CEREG: 2,5,\"00B7\",\"00011B07\",7\r\nOK\r\n");
+			assert_eq!(get_registration_status(), Ok(RegistrationStatus::Roaming));
+		});
+	}
+
+	#[test]
+	fn xmonitor_cell_info() {
+		mock::run(|modem| {
+			modem.reply(
+				"AT%XMONITOR",
+				"%XMONITOR: 1,\"EDAV\",\"EDAV\",\"26295\",\"00B7\",7,20,\"00011B07\",\
+				 7,2300,63,39,\"\",\"11100000\",\"11100000\",\"01001001\"\r\nOK\r\n",
+			);
+			modem.reply("AT+CESQ", "+CESQ: 99,99,255,255,31,62\r\nOK\r\n");
+			let info = get_cell_info().unwrap().unwrap();
+			assert_eq!(info.cell_id, 0x11B07);
+			assert_eq!(info.tac, 0xB7);
+			assert_eq!(info.plmn.as_str(), "26295");
+			assert_eq!(info.band, 20);
+			assert_eq!(info.access_technology, Some(AccessTechnology::LteM));
+			assert_eq!(info.rsrp_dbm, Some(-78));
+			assert_eq!(info.rsrq_db, Some(-4.5));
+			assert_eq!(info.snr_db, Some(14));
+		});
+	}
+
+	#[test]
+	fn registration_reads_plmn_without_changing_cops_format() {
+		mock::run(|modem| {
+			modem.reply(
+				"AT+CEREG=2",
+				"OK\r\n\0+CEREG: 5,\"00B7\",\"00011B07\",7\r\n",
+			);
+			modem.reply(
+				"AT%XMONITOR",
+				"%XMONITOR: 5,\"EDAV\",\"EDAV\",\"26295\",\"00B7\",7,20,\"00011B07\",\
+				 7,2300,63,39,\"\",\"11100000\",\"11100000\",\"01001001\"\r\nOK\r\n",
+			);
+			let registration = wait_for_registration().unwrap();
+			assert!(registration.roaming);
+			assert_eq!(registration.plmn.as_deref(), Some("26295"));
+			assert!(!modem.commands().iter().any(|c| c.starts_with("AT+COPS")));
+		});
+	}
+
+	#[test]
+	fn xmonitor_not_registered() {
+		mock::run(|modem| {
+			modem.reply("AT%XMONITOR", "%XMONITOR: 2\r\nOK\r\n");
+			assert_eq!(get_cell_info(), Ok(None));
+		});
+	}
+
+	#[test]
+	fn cops_names_may_contain_separators() {
+		let list = parse_cops_list(
+			" (2,\"Foo, (Bar),, Baz\",\"F,B\",\"23410\",7),(3,\"\",\"\",\"23415\",9),,(0,1,3,4),(0,2)",
+		)
+		.unwrap();
+		assert_eq!(list.len(), 2);
+		assert_eq!(list[0].status, OperatorStatus::Current);
+		assert_eq!(list[0].long_name.as_str(), "Foo, (Bar),, Baz");
+		assert_eq!(list[0].short_name.as_str(), "F,B");
+		assert_eq!(list[0].plmn.as_str(), "23410");
+		assert_eq!(list[1].status, OperatorStatus::Forbidden);
+		assert_eq!(list[1].plmn.as_str(), "23415");
+	}
+
+	#[test]
+	fn long_operator_scan_is_read() {
+		mock::run(|modem| {
+			let mut reply = heapless::String::<1024>::new();
+			reply.push_str("+COPS: ").unwrap();
+			for _ in 0..8 {
+				reply
+					.push_str("(1,\"A Very Long Operator Name\",\"Short Name\",\"310410\",7),")
+					.unwrap();
+			}
+			reply.push_str(",(0,1,3,4),(0,2)\r\nOK\r\n").unwrap();
+			assert!(reply.len() > crate::at::AT_BUFFER_LENGTH);
+			modem.reply("AT+COPS=?", &reply);
+			let list = scan_operators().unwrap();
+			assert_eq!(list.len(), 8);
+			assert_eq!(list[7].short_name.as_str(), "Short Name");
+		});
+	}
+
+	/// A `%NCELLMEAS` notification with the longest fields we expect
+	fn ncellmeas_line(neighbors: usize) -> heapless::String<1024> {
+		let mut line = heapless::String::new();
+		line.push_str(
+			"%NCELLMEAS: 0,\"0FFFFFFF\",\"310410\",\"FFFF\",65535,262143,503,-17,-30,\
+			 18446744073709551615",
+		)
+		.unwrap();
+		for _ in 0..neighbors {
+			line.push_str(",262143,503,-17,-30,-2147483648").unwrap();
+		}
+		line.push_str(",18446744073709551615\r\n").unwrap();
+		line
+	}
+
+	#[test]
+	fn ncellmeas_with_every_neighbor() {
+		mock::run(|modem| {
+			let mut reply = heapless::String::<1024>::new();
+			reply.push_str("OK\r\n\0").unwrap();
+			reply.push_str(&ncellmeas_line(MAX_NEIGHBOR_CELLS)).unwrap();
+			assert!(reply.len() > crate::at::AT_BUFFER_LENGTH);
+			modem.reply("AT%NCELLMEAS", &reply);
+			let cells = measure_neighbor_cells().unwrap();
+			assert_eq!(cells.serving.cell_id, 0x0FFF_FFFF);
+			assert_eq!(cells.neighbors.len(), MAX_NEIGHBOR_CELLS);
+			assert_eq!(cells.neighbors[16].time_diff, i32::MIN);
+		});
+	}
+
+	#[test]
+	fn other_notifications_do_not_use_up_the_timeout() {
+		mock::run(|modem| {
+			let mut reply = heapless::String::<1024>::new();
+			reply.push_str("OK\r\n").unwrap();
+			// More than there are `NOTIFICATION_POLL_MS` in NCELLMEAS_TIMEOUT_MS
+			for _ in 0..=(NCELLMEAS_TIMEOUT_MS / NOTIFICATION_POLL_MS) {
+				reply.push_str("\0+CEREG: 1\r\n").unwrap();
+			}
+			reply.push_str("\0").unwrap();
+			reply.push_str(&ncellmeas_line(0)).unwrap();
+			modem.reply("AT%NCELLMEAS", &reply);
+			let cells = measure_neighbor_cells().unwrap();
+			assert!(cells.neighbors.is_empty());
+		});
+	}
+
+	#[test]
+	fn no_notification_is_a_timeout() {
+		mock::run(|modem| {
+			modem.reply("AT%NCELLMEAS", "OK\r\n");
+			assert!(matches!(measure_neighbor_cells(), Err(Error::Timeout)));
+		});
+	}
+	/// Check `set_psm` sends exactly `expected`.
+	fn check_psm(tau_secs: u64, active_secs: u64, expected: &str) {
+		mock::run(|modem| {
+			modem.reply(expected, "OK\r\n");
+			set_psm(
+				core::time::Duration::from_secs(tau_secs),
+				core::time::Duration::from_secs(active_secs),
+			)
+			.unwrap();
+			assert_eq!(modem.commands(), [expected]);
+		});
+	}
+
+	#[test]
+	fn psm_timers_use_the_shortest_unit() {
+		// 31 lots of 2 seconds
+		check_psm(62, 62, "AT+CPSMS=1,,,\"01111111\",\"00011111\"");
+	}
+
+	#[test]
+	fn psm_timers_round_up_to_the_next_unit() {
+		// 3 lots of 30 seconds, and 2 lots of a minute
+		check_psm(63, 63, "AT+CPSMS=1,,,\"10000011\",\"00100010\"");
+	}
+
+	#[test]
+	fn psm_timers_are_capped() {
+		// 31 lots of 320 hours, and 31 lots of 6 minutes
+		check_psm(
+			31 * 320 * 60 * 60 + 1,
+			31 * 6 * 60 + 1,
+			"AT+CPSMS=1,,,\"11011111\",\"01011111\"",
+		);
+	}
+
+	#[test]
+	fn psm_timers_can_be_zero() {
+		check_psm(0, 0, "AT+CPSMS=1,,,\"01100000\",\"00000000\"");
+	}
+	#[test]
+	fn band_lock_mask_puts_band_1_last() {
+		mock::run(|modem| {
+			let command = "AT%XBANDLOCK=1,\"10000000000010000101\"";
+			modem.reply(command, "OK\r\n");
+			modem.reply("AT%XBANDLOCK=1,\"1\"", "OK\r\n");
+			set_band_lock(&[3, 20, 1, 8]).unwrap();
+			set_band_lock(&[1]).unwrap();
+			assert_eq!(modem.commands(), [command, "AT%XBANDLOCK=1,\"1\""]);
+		});
+	}
+
+	#[test]
+	fn band_lock_rejects_bad_bands() {
+		mock::run(|modem| {
+			assert_eq!(set_band_lock(&[]), Err(Error::InvalidArgument));
+			assert_eq!(set_band_lock(&[0, 3]), Err(Error::InvalidArgument));
+			assert_eq!(
+				set_band_lock(&[3, MAX_LTE_BAND + 1]),
+				Err(Error::InvalidArgument)
+			);
+			assert!(modem.commands().is_empty());
+		});
+	}
+	#[test]
+	fn edrx_sends_cycle_and_window() {
+		mock::run(|modem| {
+			let commands = [
+				"AT+CEDRXS=2,4,\"0010\"",
+				"AT%XPTW=4,\"0000\"",
+				"AT+CEDRXS=2,5,\"1111\"",
+				"AT%XPTW=5,\"1111\"",
+			];
+			for command in commands.iter() {
+				modem.reply(command, "OK\r\n");
+			}
+			set_edrx(
+				AccessTechnology::LteM,
+				EdrxCycle::Ms20480,
+				PagingTimeWindow::new(1).unwrap(),
+			)
+			.unwrap();
+			set_edrx(
+				AccessTechnology::NbIot,
+				EdrxCycle::Ms10485760,
+				PagingTimeWindow::new(16).unwrap(),
+			)
+			.unwrap();
+			assert_eq!(modem.commands(), commands);
+		});
+	}
+
+	#[test]
+	fn paging_time_window_range() {
+		assert_eq!(PagingTimeWindow::new(0), None);
+		assert_eq!(PagingTimeWindow::new(17), None);
+	}
+
+	#[test]
+	fn nibble_bits_ignores_the_top_nibble() {
+		assert_eq!(nibble_bits(0).as_str(), "0000");
+		assert_eq!(nibble_bits(0x1A).as_str(), "1010");
+	}
+	#[test]
+	fn cclk_negative_timezone() {
+		mock::run(|modem| {
+			modem.reply("AT+CCLK?", "+CCLK: \"21/03/04,05:06:07-10\"\r\nOK\r\n");
+			assert_eq!(
+				get_network_time(),
+				Ok(NetworkTime {
+					year: 2021,
+					month: 3,
+					day: 4,
+					hour: 5,
+					minute: 6,
+					second: 7,
+					timezone_quarter_hours: -10,
+				})
+			);
+		});
+	}
+
+	#[test]
+	fn cclk_positive_timezone() {
+		let time = parse_cclk(" \"99/12/31,23:59:59+04\"").unwrap();
+		assert_eq!(time.year, 2099);
+		assert_eq!(time.timezone_quarter_hours, 4);
+	}
+
+	#[test]
+	fn cclk_empty_response() {
+		assert_eq!(parse_cclk(""), Err(Error::BadDataFormat));
+		assert_eq!(parse_cclk(" \"\""), Err(Error::BadDataFormat));
+		mock::run(|modem| {
+			modem.reply("AT+CCLK?", "OK\r\n");
+			assert!(get_network_time().is_err());
+		});
+	}
+	#[test]
+	fn xtime_semi_octets_are_swapped() {
+		let update = Notification::parse("%XTIME: \"4A\",\"12304051627388\",\"01\"");
+		assert_eq!(
+			update,
+			Some(Notification::NetworkTime(NetworkTimeUpdate {
+				local_timezone_quarter_hours: Some(-24),
+				universal_time: Some(NetworkTime {
+					year: 2021,
+					month: 3,
+					day: 4,
+					hour: 15,
+					minute: 26,
+					second: 37,
+					timezone_quarter_hours: -8,
+				}),
+				daylight_saving_hours: Some(1),
+			}))
+		);
+	}
+
+	#[test]
+	fn xtime_fields_may_be_missing() {
+		assert_eq!(
+			parse_xtime(" \"40\""),
+			Some(NetworkTimeUpdate {
+				local_timezone_quarter_hours: Some(4),
+				universal_time: None,
+				daylight_saving_hours: None,
+			})
+		);
+		assert_eq!(
+			parse_xtime(" \"\",\"\",\"\""),
+			Some(NetworkTimeUpdate {
+				local_timezone_quarter_hours: None,
+				universal_time: None,
+				daylight_saving_hours: None,
+			})
+		);
+		// The universal time must be all seven bytes
+		assert_eq!(parse_xtime(" \"40\",\"123040\",\"00\""), None);
+	}
+
+	#[test]
+	fn timezone_sign_bit() {
+		// Units first, then tens
+		assert_eq!(decode_timezone("21"), Some(12));
+		// Bit 3 of the tens digit makes it negative
+		assert_eq!(decode_timezone("29"), Some(-12));
+		assert_eq!(decode_timezone("08"), Some(0));
+		// The units digit has no sign bit
+		assert_eq!(decode_timezone("A1"), None);
+		assert_eq!(decode_timezone("2"), None);
+	}
+	#[test]
+	fn next_notification_takes_a_long_timeout() {
+		mock::run(|modem| {
+			let skt = crate::at::AtSocket::new().unwrap();
+			modem.push(nrfxlib_sys::NRF_PROTO_AT, b"%XMODEMSLEEP: 1,3600000\r\n");
+			let notification =
+				next_notification(&skt, core::time::Duration::from_secs(24 * 60 * 60)).unwrap();
+			assert_eq!(
+				notification,
+				Notification::ModemSleep(ModemSleep {
+					kind: ModemSleepKind::Psm,
+					duration_ms: Some(3_600_000),
+				})
+			);
+		});
+	}
+}
 
 //******************************************************************************
 // End of File
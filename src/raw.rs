@@ -17,7 +17,12 @@
 // Imports
 //******************************************************************************
 
-use super::{get_last_error, Error};
+use super::{
+	backend, clear_last_error, get_last_error, Error, NrfErrno, NrfSockAddrIn, NrfSockAddrIn6,
+};
+use core::mem::MaybeUninit;
+use core::time::Duration;
+use log::debug;
 use nrfxlib_sys as sys;
 
 //******************************************************************************
@@ -29,6 +34,31 @@ use nrfxlib_sys as sys;
 #[derive(Debug)]
 pub struct Socket {
 	pub(crate) fd: i32,
+	/// The domain this socket was created in.
+	domain: SocketDomain,
+	/// The type this socket was created with.
+	skt_type: SocketType,
+	/// The protocol this socket was created with.
+	protocol: SocketProtocol,
+	/// The value of `LIBRARY_GENERATION` when this socket was created.
+	generation: u32,
+	/// The error which made the last `core::fmt::Write` call fail, if any.
+	last_write_error: Option<Error>,
+	/// The address this socket is connected to, if we know it.
+	peer: core::cell::Cell<Option<SockAddr>>,
+	/// How much data has passed through this socket.
+	#[cfg(feature = "socket-stats")]
+	stats: core::cell::Cell<SocketStats>,
+}
+
+/// How much data has passed through a socket, since it was created.
+#[cfg(feature = "socket-stats")]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct SocketStats {
+	/// The number of bytes written to the socket
+	pub bytes_sent: u64,
+	/// The number of bytes read from the socket
+	pub bytes_received: u64,
 }
 
 /// The options that can be passed to a socket.
@@ -43,6 +73,8 @@ pub(crate) enum SocketOption<'a> {
 	TlsSessionCache(sys::nrf_sec_session_cache_t),
 	/// A list of the TLS security/key tags you want to use
 	TlsTagList(&'a [sys::nrf_sec_tag_t]),
+	/// A list of the TLS cipher suites (IANA values) you are willing to use
+	TlsCipherList(&'a [sys::nrf_sec_cipher_t]),
 	/// Defines the interval between each fix in seconds. The default is 1. A
 	/// value of 0 means single-fix mode.
 	GnssFixInterval(sys::nrf_gnss_fix_interval_t),
@@ -51,10 +83,40 @@ pub(crate) enum SocketOption<'a> {
 	GnssFixRetry(sys::nrf_gnss_fix_retry_t),
 	/// Controls which, if any, NMEA frames are provided by the GNSS system
 	GnssNmeaMask(sys::nrf_gnss_nmea_mask_t),
+	/// Satellites below this elevation (in degrees above the horizon) are not
+	/// used for fixes
+	GnssElevationMask(sys::nrf_gnss_elevation_mask_t),
+	/// Tells the GNSS system how it will be used (single or multiple starts,
+	/// normal or low accuracy)
+	GnssUseCase(sys::nrf_gnss_use_case_t),
+	/// Selects the GNSS power saving mode (duty cycling)
+	GnssPowerSaveMode(sys::nrf_gnss_power_save_mode_t),
 	/// Starts the GNSS system, after deleting the specified non-volatile values.
 	GnssStart(sys::nrf_gnss_delete_mask_t),
 	/// Stops the GNSS system
 	GnssStop,
+	/// Sets the offset within the firmware image at which the next DFU write
+	/// will be stored
+	DfuOffset(sys::nrf_dfu_fw_offset_t),
+	/// Schedules the received firmware image to be applied on next reboot
+	DfuApply,
+	/// How long a blocking receive waits for data before giving up. Zero
+	/// means wait forever.
+	ReceiveTimeout(sys::nrf_timeval),
+	/// How long a blocking send waits for the modem before giving up. Zero
+	/// means wait forever.
+	SendTimeout(sys::nrf_timeval),
+	/// Deletes the backup firmware image, making space for a new one
+	DfuBackupDelete,
+}
+
+/// Which version of IP an internet socket uses.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SocketFamily {
+	/// IPv4. Host names are looked up with A records.
+	Ipv4,
+	/// IPv6. Host names are looked up with AAAA records.
+	Ipv6,
 }
 
 /// The domain for a socket
@@ -64,14 +126,25 @@ pub(crate) enum SocketDomain {
 	Lte,
 	/// Corresponds to NRF_AF_INET. Used for IPv4 sockets.
 	Inet,
+	/// Corresponds to NRF_AF_INET6. Used for IPv6 sockets.
+	Inet6,
 	/// Corresponds to NRF_AF_LOCAL. Used for talking to the Nordic library (e.g. GNSS functions).
 	Local,
 }
 
+/// An IPv4 or IPv6 socket address, as the library stores them.
+#[derive(Debug, Copy, Clone)]
+pub(crate) enum SockAddr {
+	/// An IPv4 address
+	V4(sys::nrf_sockaddr_in),
+	/// An IPv6 address
+	V6(sys::nrf_sockaddr_in6),
+}
+
 /// The type of socket (Stream, Datagram, or neither)
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub(crate) enum SocketType {
-	/// Used with `SocketDomain::Inet` for TCP and TLS streams
+	/// Used with `SocketDomain::Inet` for TCP and TLS streams, and for DFU
 	Stream,
 	/// Used with UDP sockets, and for GPS
 	Datagram,
@@ -94,6 +167,8 @@ pub(crate) enum SocketProtocol {
 	Dtls1v2,
 	/// A connection to the GPS/GNSS sub-system
 	Gnss,
+	/// A connection to the modem firmware update sub-system
+	Dfu,
 }
 
 /// Describes something we can poll on.
@@ -111,7 +186,7 @@ pub struct PollEntry<'a> {
 }
 
 /// The ways in which you can poll on a particular socket
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 #[repr(i16)]
 pub enum PollFlags {
 	/// Wake up if this socket is readable
@@ -123,20 +198,37 @@ pub enum PollFlags {
 }
 
 /// The ways a socket can respond to a poll.
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct PollResult(u32);
 
 //******************************************************************************
 // Constants
 //******************************************************************************
 
-const MAX_SOCKETS_POLL: usize = 8;
+/// The most sockets `poll` will accept. Use `poll_n` if you need more.
+pub const MAX_SOCKETS_POLL: usize = 8;
+
+/// Pass this to `poll` to wait forever.
+pub const POLL_FOREVER: i32 = -1;
+
+/// The most addresses `resolve` and `resolve_v6` will return.
+pub const MAX_RESOLVED_ADDRESSES: usize = 4;
+
+/// How many tasks can be waiting for the modem at once. See
+/// `register_waker`.
+#[cfg(feature = "async")]
+const MAX_WAKERS: usize = 8;
 
 //******************************************************************************
 // Global Variables
 //******************************************************************************
 
-// None
+/// Tasks waiting for one of their sockets to become ready. They are all woken
+/// the next time the library handles an application interrupt.
+#[cfg(feature = "async")]
+static WAKERS: cortex_m::interrupt::Mutex<
+	core::cell::RefCell<heapless::Vec<core::task::Waker, MAX_WAKERS>>,
+> = cortex_m::interrupt::Mutex::new(core::cell::RefCell::new(heapless::Vec::new()));
 
 //******************************************************************************
 // Macros
@@ -154,25 +246,120 @@ impl Socket {
 		skt_type: SocketType,
 		protocol: SocketProtocol,
 	) -> Result<Socket, Error> {
-		let result = unsafe { sys::nrf_socket(domain.into(), skt_type.into(), protocol.into()) };
+		clear_last_error();
+		let result = backend::socket(domain.into(), skt_type.into(), protocol.into());
 		if result < 0 {
 			Err(Error::Nordic("new_socket", result, get_last_error()))
 		} else {
-			Ok(Socket { fd: result })
+			Ok(Socket {
+				fd: result,
+				domain,
+				skt_type,
+				protocol,
+				generation: crate::LIBRARY_GENERATION.load(core::sync::atomic::Ordering::SeqCst),
+				last_write_error: None,
+				peer: core::cell::Cell::new(None),
+				#[cfg(feature = "socket-stats")]
+				stats: core::cell::Cell::new(SocketStats::default()),
+			})
+		}
+	}
+
+	/// Check this socket was created since the library was last started, and
+	/// still has a file descriptor (see `reopen`). If not, the file descriptor
+	/// is no longer ours to use.
+	pub(crate) fn check_valid(&self) -> Result<(), Error> {
+		if self.fd >= 0
+			&& self.generation
+				== crate::LIBRARY_GENERATION.load(core::sync::atomic::Ordering::SeqCst)
+		{
+			Ok(())
+		} else {
+			Err(Error::SocketInvalidated)
 		}
 	}
 
+	/// If a `write!` to this socket failed, get the error which caused it.
+	///
+	/// `core::fmt::Write` can only report that something went wrong, so we
+	/// keep the real error here. Taking it clears it.
+	pub fn take_last_write_error(&mut self) -> Option<Error> {
+		self.last_write_error.take()
+	}
+
+	/// Get the number of bytes sent and received on this socket so far.
+	#[cfg(feature = "socket-stats")]
+	pub fn stats(&self) -> SocketStats {
+		self.stats.get()
+	}
+
+	/// Fetch and clear any error pending on this socket (`SO_ERROR`).
+	///
+	/// After a non-blocking `connect`, wait for `poll` to report the socket
+	/// as writable (or errored), then call this to find out whether the
+	/// connection actually succeeded. Returns `None` if there is no error
+	/// pending.
+	pub fn take_error(&self) -> Result<Option<NrfErrno>, Error> {
+		self.start_operation()?;
+		let mut length: u32 = core::mem::size_of::<i32>() as u32;
+		let mut value = 0i32;
+		let result = backend::get_option(
+			self.fd,
+			sys::NRF_SOL_SOCKET as i32,
+			sys::NRF_SO_ERROR as i32,
+			&mut value as *mut i32 as *mut sys::ctypes::c_void,
+			&mut length,
+		);
+		if result < 0 {
+			Err(Error::Nordic("take_error", result, get_last_error()))
+		} else if value == 0 {
+			Ok(None)
+		} else {
+			NrfErrno::from_i32(value)
+				.map(Some)
+				.ok_or(Error::UnrecognisedValue)
+		}
+	}
+
+	/// Get ready to call into the library with this socket. Checks the socket
+	/// is still valid, and clears the last error so any error reported
+	/// afterwards belongs to this operation.
+	pub(crate) fn start_operation(&self) -> Result<(), Error> {
+		self.check_valid()?;
+		clear_last_error();
+		Ok(())
+	}
+
+	/// Close this socket and open a fresh one of the same kind in its place.
+	/// The modem gives us no other way to return a socket to the unconnected
+	/// state.
+	///
+	/// Options set on the old socket are not carried over. If we can't get a
+	/// new socket, this one is left with no file descriptor, and everything
+	/// you do with it fails with `Error::SocketInvalidated`.
+	pub(crate) fn reopen(&mut self) -> Result<(), Error> {
+		let (domain, skt_type, protocol) = (self.domain, self.skt_type, self.protocol);
+		// The old socket goes first, as the modem only has a few to give out.
+		if self.check_valid().is_ok() {
+			let _ = backend::close(self.fd);
+		}
+		self.fd = -1;
+		// Dropping the old value doesn't close anything, as it has no file
+		// descriptor any more.
+		*self = Socket::new(domain, skt_type, protocol)?;
+		Ok(())
+	}
+
 	pub(crate) fn set_option<'a>(&'a self, option: SocketOption<'a>) -> Result<(), Error> {
+		self.start_operation()?;
 		let length = option.get_length();
-		let result = unsafe {
-			sys::nrf_setsockopt(
-				self.fd,
-				option.get_level(),
-				option.get_name(),
-				option.get_value(),
-				length,
-			)
-		};
+		let result = backend::set_option(
+			self.fd,
+			option.get_level(),
+			option.get_name(),
+			option.get_value(),
+			length,
+		);
 		if result < 0 {
 			Err(Error::Nordic("set_option", result, get_last_error()))
 		} else {
@@ -180,36 +367,270 @@ impl Socket {
 		}
 	}
 
-	/// Perform a non-blocking write on the socket.
-	pub fn send(&self, buf: &[u8]) -> Result<Option<usize>, Error> {
-		let length = buf.len();
-		let ptr = buf.as_ptr();
-		let result = unsafe {
-			sys::nrf_send(
-				self.fd,
-				ptr as *const _,
-				length as u32,
-				sys::NRF_MSG_DONTWAIT as i32,
-			)
+	/// Limit how long the blocking receive functions (e.g. `recv_wait`) wait
+	/// for data. If no data arrives in time, they return `Error::Timeout`.
+	///
+	/// A zero `timeout` means wait forever, which is the default. The modem
+	/// works in whole microseconds.
+	pub fn set_receive_timeout(&self, timeout: Duration) -> Result<(), Error> {
+		self.set_option(SocketOption::ReceiveTimeout(to_timeval(timeout)))
+	}
+
+	/// Limit how long the blocking send functions (e.g. `write`) wait for the
+	/// modem to accept data. If it doesn't in time, they return
+	/// `Error::Timeout`.
+	///
+	/// A zero `timeout` means wait forever, which is the default.
+	pub fn set_send_timeout(&self, timeout: Duration) -> Result<(), Error> {
+		self.set_option(SocketOption::SendTimeout(to_timeval(timeout)))
+	}
+
+	/// Set a socket option which the typed `SocketOption` API doesn't cover.
+	///
+	/// `level` and `name` are the Nordic `NRF_SOL_*` and `NRF_SO_*` values,
+	/// and `value` is passed to the library as-is, so it must hold whatever
+	/// the option expects, in the modem's (little-endian) layout.
+	pub fn set_option_raw(&self, level: i32, name: i32, value: &[u8]) -> Result<(), Error> {
+		self.start_operation()?;
+		let result = backend::set_option(
+			self.fd,
+			level,
+			name,
+			value.as_ptr() as *const sys::ctypes::c_void,
+			value.len() as u32,
+		);
+		if result < 0 {
+			Err(Error::Nordic("set_option_raw", result, get_last_error()))
+		} else {
+			Ok(())
+		}
+	}
+
+	/// Get a socket option which the typed API doesn't cover.
+	///
+	/// The option's value is written into the start of `buf`, and the number
+	/// of bytes written is returned. See `set_option_raw`.
+	pub fn get_option_raw(&self, level: i32, name: i32, buf: &mut [u8]) -> Result<usize, Error> {
+		self.start_operation()?;
+		let mut length = buf.len() as u32;
+		let result = backend::get_option(
+			self.fd,
+			level,
+			name,
+			buf.as_mut_ptr() as *mut sys::ctypes::c_void,
+			&mut length,
+		);
+		if result < 0 {
+			Err(Error::Nordic("get_option_raw", result, get_last_error()))
+		} else {
+			Ok(length as usize)
+		}
+	}
+
+	/// Connect this socket to the given address, which must be of the
+	/// socket's family. If the library fails, you get an `Error::Nordic`
+	/// named `label` (e.g. `"tcp_connect"`).
+	pub(crate) fn connect_labelled(
+		&self,
+		label: &'static str,
+		addr: SockAddr,
+	) -> Result<(), Error> {
+		self.start_operation()?;
+		let result = match addr {
+			SockAddr::V4(ref addr) => {
+				debug!("{} to {}", label, NrfSockAddrIn(*addr));
+				backend::connect(self.fd, addr)
+			}
+			SockAddr::V6(ref addr) => {
+				debug!("{} to {}", label, NrfSockAddrIn6(*addr));
+				backend::connect6(self.fd, addr)
+			}
 		};
+		if result != 0 {
+			Err(Error::Nordic(label, result, get_last_error()))
+		} else {
+			self.peer.set(Some(addr));
+			Ok(())
+		}
+	}
+
+	/// Look up the hostname and try to connect to each address it resolves
+	/// to, in turn.
+	///
+	/// A socket which failed to connect can't be used again, so before each
+	/// attempt after the first we reopen the socket, and call `configure` on
+	/// the new one to set whatever options it needs.
+	///
+	/// Stops at the first address which accepts the connection. If none of
+	/// them do (or there weren't any), you get `Error::ConnectFailed`. If the
+	/// look-up itself fails, you get the error from `lookup`.
+	pub(crate) fn connect_by_name<F>(
+		&mut self,
+		hostname: &str,
+		port: u16,
+		configure: F,
+	) -> Result<(), Error>
+	where
+		F: Fn(&Socket) -> Result<(), Error>,
+	{
+		let addresses = lookup(hostname, self.family(), self.skt_type, port)?;
+		let mut attempts = 0;
+		let mut connect_result = Err(Error::ConnectFailed(0, 0));
+		for addr in addresses.iter() {
+			if attempts > 0 {
+				self.reopen()?;
+				configure(self)?;
+			}
+			// try and connect to this result
+			attempts += 1;
+			connect_result = match self.connect_labelled("connect", *addr) {
+				Ok(()) => Ok(()),
+				Err(Error::Nordic(_, _, errno)) => Err(Error::ConnectFailed(attempts, errno)),
+				Err(e) => Err(e),
+			};
+			if connect_result.is_ok() {
+				break;
+			}
+		}
+		connect_result
+	}
+
+	/// Fail with `Error::InvalidArgument` if this is an IPv6 socket.
+	pub(crate) fn require_ipv4(&self) -> Result<(), Error> {
+		match self.family() {
+			SocketFamily::Ipv4 => Ok(()),
+			SocketFamily::Ipv6 => Err(Error::InvalidArgument),
+		}
+	}
+
+	/// Whether this is an IPv4 or IPv6 socket. Sockets which aren't
+	/// internet sockets at all count as IPv4.
+	pub(crate) fn family(&self) -> SocketFamily {
+		if self.domain == SocketDomain::Inet6 {
+			SocketFamily::Ipv6
+		} else {
+			SocketFamily::Ipv4
+		}
+	}
+
+	/// Get the address this socket is connected to.
+	///
+	/// This is the address given to the `connect` call which succeeded (so
+	/// after connecting by hostname, it tells you which of the looked-up
+	/// addresses was used), or the remote address of an accepted
+	/// connection. Returns `None` if the socket isn't connected, or is
+	/// connected over IPv6 (see `peer_addr_v6`).
+	pub fn peer_addr(&self) -> Option<NrfSockAddrIn> {
+		match self.peer.get() {
+			Some(SockAddr::V4(addr)) => Some(NrfSockAddrIn(addr)),
+			_ => None,
+		}
+	}
+
+	/// Like `peer_addr`, but for sockets connected over IPv6.
+	pub fn peer_addr_v6(&self) -> Option<NrfSockAddrIn6> {
+		match self.peer.get() {
+			Some(SockAddr::V6(addr)) => Some(NrfSockAddrIn6(addr)),
+			_ => None,
+		}
+	}
+
+	/// Bind this socket to the given local address. We only handle IPv4
+	/// addresses, so on an IPv6 socket you get `Error::InvalidArgument`.
+	pub(crate) fn bind(&self, addr: &NrfSockAddrIn) -> Result<(), Error> {
+		self.start_operation()?;
+		self.require_ipv4()?;
+		let result = backend::bind(self.fd, &addr.0);
+		if result != 0 {
+			Err(Error::Nordic("bind", result, get_last_error()))
+		} else {
+			Ok(())
+		}
+	}
+
+	/// Start listening for incoming connections on this socket.
+	pub(crate) fn listen(&self, backlog: i32) -> Result<(), Error> {
+		self.start_operation()?;
+		let result = backend::listen(self.fd, backlog);
+		if result != 0 {
+			Err(Error::Nordic("listen", result, get_last_error()))
+		} else {
+			Ok(())
+		}
+	}
+
+	/// Wait for an incoming connection on this (listening) socket. Gives you a
+	/// new socket for the connection, and the address of the remote device.
+	///
+	/// As with `bind`, this only works on an IPv4 socket.
+	pub(crate) fn accept(&self) -> Result<(Socket, NrfSockAddrIn), Error> {
+		self.start_operation()?;
+		self.require_ipv4()?;
+		let mut addr = NrfSockAddrIn::new([0, 0, 0, 0], 0);
+		let result = backend::accept(self.fd, &mut addr.0);
+		if result < 0 {
+			Err(Error::Nordic("accept", result, get_last_error()))
+		} else {
+			let socket = Socket {
+				fd: result,
+				domain: self.domain,
+				skt_type: self.skt_type,
+				protocol: self.protocol,
+				generation: self.generation,
+				last_write_error: None,
+				peer: core::cell::Cell::new(Some(SockAddr::V4(addr.0))),
+				#[cfg(feature = "socket-stats")]
+				stats: core::cell::Cell::new(SocketStats::default()),
+			};
+			Ok((socket, addr))
+		}
+	}
+
+	/// Perform a non-blocking write on the socket. Returns `Ok(None)` if the
+	/// modem can't take any data right now, rather than waiting like `write`
+	/// does. You can use `poll` with `PollFlags::Write` to find out when it
+	/// can.
+	pub fn send(&self, buf: &[u8]) -> Result<Option<usize>, Error> {
+		self.start_operation()?;
+		let result = backend::send(self.fd, buf, sys::NRF_MSG_DONTWAIT as i32);
 		if result == -1 && get_last_error() == sys::NRF_EAGAIN as i32 {
 			// This is EAGAIN
 			Ok(None)
 		} else if result < 0 {
 			Err(Error::Nordic("send", result as i32, get_last_error()))
 		} else {
+			self.record_sent(result);
 			Ok(Some(result as usize))
 		}
 	}
 
-	/// Perform a blocking write on the socket.
+	/// Perform a blocking write on the socket, sending the data to the given
+	/// address. The socket does not need to be connected, so one datagram
+	/// socket can talk to several remote devices.
+	pub fn send_to(&self, buf: &[u8], addr: &NrfSockAddrIn) -> Result<usize, Error> {
+		self.start_operation()?;
+		let result = backend::send_to(self.fd, buf, 0, &addr.0);
+		if result < 0 {
+			Err(blocking_error("send_to", result as i32))
+		} else {
+			self.record_sent(result);
+			Ok(result as usize)
+		}
+	}
+
+	/// Perform a blocking write on the socket. See `send` for a version which
+	/// doesn't block.
+	///
+	/// The 1.5.1 release of the Nordic library has no Release Assistance
+	/// Indication (RAI) socket options, so there is no way to mark a write as
+	/// the last one of a burst.
 	pub fn write(&self, buf: &[u8]) -> Result<usize, Error> {
-		let length = buf.len();
-		let ptr = buf.as_ptr();
-		let result = unsafe { sys::nrf_write(self.fd, ptr as *const _, length as u32) };
+		self.start_operation()?;
+		let result = backend::write(self.fd, buf);
 		if result < 0 {
-			Err(Error::Nordic("write", result as i32, get_last_error()))
+			Err(blocking_error("write", result as i32))
 		} else {
+			self.record_sent(result);
 			Ok(result as usize)
 		}
 	}
@@ -218,54 +639,195 @@ impl Socket {
 	/// all of the given buffer. You must slice the buffer using the returned
 	/// `usize` value.
 	pub fn recv(&self, buf: &mut [u8]) -> Result<Option<usize>, Error> {
-		let length = buf.len();
-		let ptr = buf.as_mut_ptr();
-		let result = unsafe {
-			sys::nrf_recv(
-				self.fd,
-				ptr as *mut _,
-				length as u32,
-				sys::NRF_MSG_DONTWAIT as i32,
-			)
+		// NOTE(unsafe) - `MaybeUninit<u8>` has the same layout as `u8`, and
+		// `recv_uninit` only ever writes initialised bytes into the buffer.
+		let buf = unsafe {
+			core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut MaybeUninit<u8>, buf.len())
 		};
+		self.recv_uninit(buf)
+	}
+
+	/// Perform a non-blocking read on the socket, into a buffer which need not
+	/// be initialised first. Will fill up none, some or all of the given
+	/// buffer. If this returns `Ok(Some(n))`, the first `n` bytes of the
+	/// buffer have been initialised.
+	pub fn recv_uninit(&self, buf: &mut [MaybeUninit<u8>]) -> Result<Option<usize>, Error> {
+		self.start_operation()?;
+		let result = backend::recv(self.fd, buf, sys::NRF_MSG_DONTWAIT as i32);
 		if result == -1 && get_last_error() == sys::NRF_EAGAIN as i32 {
 			// This is EAGAIN
 			Ok(None)
 		} else if result < 0 {
 			Err(Error::Nordic("recv", result as i32, get_last_error()))
 		} else {
+			self.record_received(result);
+			Ok(Some(result as usize))
+		}
+	}
+
+	/// Look at the data waiting on the socket without removing it, so the
+	/// next read gets the same bytes. Like `recv`, this doesn't block, and
+	/// returns `Ok(None)` if there is no data waiting. You must slice the
+	/// buffer using the returned `usize` value.
+	pub fn peek(&self, buf: &mut [u8]) -> Result<Option<usize>, Error> {
+		self.start_operation()?;
+		// NOTE(unsafe) - `MaybeUninit<u8>` has the same layout as `u8`, and
+		// the library only ever writes initialised bytes into the buffer.
+		let buf = unsafe {
+			core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut MaybeUninit<u8>, buf.len())
+		};
+		let flags = sys::NRF_MSG_PEEK | sys::NRF_MSG_DONTWAIT;
+		let result = backend::recv(self.fd, buf, flags as i32);
+		if result == -1 && get_last_error() == sys::NRF_EAGAIN as i32 {
+			// This is EAGAIN
+			Ok(None)
+		} else if result < 0 {
+			Err(Error::Nordic("peek", result as i32, get_last_error()))
+		} else {
+			// Nothing was consumed, so don't count it as received
+			Ok(Some(result as usize))
+		}
+	}
+
+	/// Perform a non-blocking read on the socket, and find out who sent the
+	/// data. Useful on datagram sockets which aren't connected. You must slice
+	/// the buffer using the returned `usize` value.
+	pub fn recv_from(&self, buf: &mut [u8]) -> Result<Option<(usize, NrfSockAddrIn)>, Error> {
+		self.start_operation()?;
+		// NOTE(unsafe) - `MaybeUninit<u8>` has the same layout as `u8`, and
+		// the library only ever writes initialised bytes into the buffer.
+		let buf = unsafe {
+			core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut MaybeUninit<u8>, buf.len())
+		};
+		let mut addr = NrfSockAddrIn::new([0, 0, 0, 0], 0);
+		let result = backend::recv_from(self.fd, buf, sys::NRF_MSG_DONTWAIT as i32, &mut addr.0);
+		if result == -1 && get_last_error() == sys::NRF_EAGAIN as i32 {
+			// This is EAGAIN
+			Ok(None)
+		} else if result < 0 {
+			Err(Error::Nordic("recv_from", result as i32, get_last_error()))
+		} else {
+			self.record_received(result);
+			Ok(Some((result as usize, addr)))
+		}
+	}
+
+	/// Perform a non-blocking read of one datagram from a datagram socket
+	/// (e.g. UDP or DTLS). You must slice the buffer using the returned
+	/// `usize` value.
+	///
+	/// If the datagram was too big for the buffer, the rest of it is lost and
+	/// you get `Error::Truncated`. The buffer still holds the start of the
+	/// datagram. Don't use this on stream sockets.
+	pub fn recv_datagram(&self, buf: &mut [u8]) -> Result<Option<usize>, Error> {
+		self.start_operation()?;
+		// NOTE(unsafe) - `MaybeUninit<u8>` has the same layout as `u8`, and
+		// the library only ever writes initialised bytes into the buffer.
+		let buf = unsafe {
+			core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut MaybeUninit<u8>, buf.len())
+		};
+		// With `MSG_TRUNC`, we get the real length of the datagram, even if
+		// it didn't fit.
+		let result = backend::recv(
+			self.fd,
+			buf,
+			(sys::NRF_MSG_DONTWAIT | sys::NRF_MSG_TRUNC) as i32,
+		);
+		if result == -1 && get_last_error() == sys::NRF_EAGAIN as i32 {
+			// This is EAGAIN
+			Ok(None)
+		} else if result < 0 {
+			Err(Error::Nordic(
+				"recv_datagram",
+				result as i32,
+				get_last_error(),
+			))
+		} else if result as usize > buf.len() {
+			self.record_received(buf.len() as sys::ssize_t);
+			Err(Error::Truncated(buf.len(), result as usize))
+		} else {
+			self.record_received(result);
 			Ok(Some(result as usize))
 		}
 	}
 
+	/// Wait for this socket to become ready, as described by `flags`, for up
+	/// to `timeout` (which is capped at `i32::MAX` milliseconds).
+	///
+	/// If the timeout expires first, every flag in the returned `PollResult`
+	/// is clear.
+	pub fn poll(&self, flags: PollFlags, timeout: Duration) -> Result<PollResult, Error> {
+		let mut poll_list = [PollEntry::new(self, flags)];
+		poll(&mut poll_list, duration_to_ms(timeout))?;
+		Ok(poll_list[0].result())
+	}
+
+	/// Perform a read on the socket, waiting up to `timeout` (which is capped
+	/// at `i32::MAX` milliseconds) for some data to arrive. Will fill up
+	/// some or all of the given buffer. You must slice the buffer using the
+	/// returned `usize` value.
+	///
+	/// Returns `Ok(None)` if no data arrived in time.
+	pub fn recv_timeout(&self, buf: &mut [u8], timeout: Duration) -> Result<Option<usize>, Error> {
+		let result = self.poll(PollFlags::Read, timeout)?;
+		if result == PollResult::default() {
+			// Timed out
+			Ok(None)
+		} else {
+			self.recv(buf)
+		}
+	}
+
 	/// Perform a blocking read on the socket. Will fill up some or all of the
 	/// given buffer. You must slice the buffer using the returned `usize`
 	/// value.
 	pub fn recv_wait(&self, buf: &mut [u8]) -> Result<usize, Error> {
-		let length = buf.len();
-		let ptr = buf.as_mut_ptr();
-		let result = unsafe { sys::nrf_recv(self.fd, ptr as *mut _, length as u32, 0) };
+		self.start_operation()?;
+		// NOTE(unsafe) - `MaybeUninit<u8>` has the same layout as `u8`, and
+		// the library only ever writes initialised bytes into the buffer.
+		let buf = unsafe {
+			core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut MaybeUninit<u8>, buf.len())
+		};
+		let result = backend::recv(self.fd, buf, 0);
 		if result < 0 {
-			Err(Error::Nordic("recv_wait", result as i32, get_last_error()))
+			Err(blocking_error("recv_wait", result as i32))
 		} else {
+			self.record_received(result);
 			Ok(result as usize)
 		}
 	}
 }
 
 impl core::fmt::Write for Socket {
+	/// Writes the whole string to the socket. If that fails, the error is
+	/// kept for `take_last_write_error`.
 	fn write_str(&mut self, s: &str) -> core::fmt::Result {
-		match self.write(s.as_bytes()) {
-			Ok(_n) => Ok(()),
-			Err(_e) => Err(core::fmt::Error),
+		let mut remaining = s.as_bytes();
+		while !remaining.is_empty() {
+			match self.write(remaining) {
+				Ok(0) => {
+					self.last_write_error = Some(Error::WriteError);
+					return Err(core::fmt::Error);
+				}
+				Ok(n) => {
+					remaining = &remaining[n..];
+				}
+				Err(e) => {
+					self.last_write_error = Some(e);
+					return Err(core::fmt::Error);
+				}
+			}
 		}
+		Ok(())
 	}
 }
 
 impl Drop for Socket {
 	fn drop(&mut self) {
-		unsafe {
-			let _ = sys::nrf_close(self.fd);
+		// If the library has been restarted, this file descriptor may now
+		// belong to someone else. If `reopen` failed, there isn't one.
+		if self.check_valid().is_ok() {
+			let _ = backend::close(self.fd);
 		}
 	}
 }
@@ -277,11 +839,20 @@ impl<'a> SocketOption<'a> {
 			SocketOption::TlsPeerVerify(_) => sys::NRF_SOL_SECURE as i32,
 			SocketOption::TlsSessionCache(_) => sys::NRF_SOL_SECURE as i32,
 			SocketOption::TlsTagList(_) => sys::NRF_SOL_SECURE as i32,
+			SocketOption::TlsCipherList(_) => sys::NRF_SOL_SECURE as i32,
 			SocketOption::GnssFixInterval(_) => sys::NRF_SOL_GNSS as i32,
 			SocketOption::GnssFixRetry(_) => sys::NRF_SOL_GNSS as i32,
 			SocketOption::GnssNmeaMask(_) => sys::NRF_SOL_GNSS as i32,
+			SocketOption::GnssElevationMask(_) => sys::NRF_SOL_GNSS as i32,
+			SocketOption::GnssUseCase(_) => sys::NRF_SOL_GNSS as i32,
+			SocketOption::GnssPowerSaveMode(_) => sys::NRF_SOL_GNSS as i32,
 			SocketOption::GnssStart(_) => sys::NRF_SOL_GNSS as i32,
 			SocketOption::GnssStop => sys::NRF_SOL_GNSS as i32,
+			SocketOption::DfuOffset(_) => sys::NRF_SOL_DFU as i32,
+			SocketOption::DfuApply => sys::NRF_SOL_DFU as i32,
+			SocketOption::DfuBackupDelete => sys::NRF_SOL_DFU as i32,
+			SocketOption::ReceiveTimeout(_) => sys::NRF_SOL_SOCKET as i32,
+			SocketOption::SendTimeout(_) => sys::NRF_SOL_SOCKET as i32,
 		}
 	}
 
@@ -291,11 +862,20 @@ impl<'a> SocketOption<'a> {
 			SocketOption::TlsPeerVerify(_) => sys::NRF_SO_SEC_PEER_VERIFY as i32,
 			SocketOption::TlsSessionCache(_) => sys::NRF_SO_SEC_SESSION_CACHE as i32,
 			SocketOption::TlsTagList(_) => sys::NRF_SO_SEC_TAG_LIST as i32,
+			SocketOption::TlsCipherList(_) => sys::NRF_SO_CIPHERSUITE_LIST as i32,
 			SocketOption::GnssFixInterval(_) => sys::NRF_SO_GNSS_FIX_INTERVAL as i32,
 			SocketOption::GnssFixRetry(_) => sys::NRF_SO_GNSS_FIX_RETRY as i32,
 			SocketOption::GnssNmeaMask(_) => sys::NRF_SO_GNSS_NMEA_MASK as i32,
+			SocketOption::GnssElevationMask(_) => sys::NRF_SO_GNSS_ELEVATION_MASK as i32,
+			SocketOption::GnssUseCase(_) => sys::NRF_SO_GNSS_USE_CASE as i32,
+			SocketOption::GnssPowerSaveMode(_) => sys::NRF_SO_GNSS_POWER_SAVE_MODE as i32,
 			SocketOption::GnssStart(_) => sys::NRF_SO_GNSS_START as i32,
 			SocketOption::GnssStop => sys::NRF_SO_GNSS_STOP as i32,
+			SocketOption::DfuOffset(_) => sys::NRF_SO_DFU_OFFSET as i32,
+			SocketOption::DfuApply => sys::NRF_SO_DFU_APPLY as i32,
+			SocketOption::DfuBackupDelete => sys::NRF_SO_DFU_BACKUP_DELETE as i32,
+			SocketOption::ReceiveTimeout(_) => sys::NRF_SO_RCVTIMEO as i32,
+			SocketOption::SendTimeout(_) => sys::NRF_SO_SNDTIMEO as i32,
 		}
 	}
 
@@ -305,11 +885,20 @@ impl<'a> SocketOption<'a> {
 			SocketOption::TlsPeerVerify(x) => x as *const _ as *const sys::ctypes::c_void,
 			SocketOption::TlsSessionCache(x) => x as *const _ as *const sys::ctypes::c_void,
 			SocketOption::TlsTagList(x) => x.as_ptr() as *const sys::ctypes::c_void,
+			SocketOption::TlsCipherList(x) => x.as_ptr() as *const sys::ctypes::c_void,
 			SocketOption::GnssFixInterval(x) => x as *const _ as *const sys::ctypes::c_void,
 			SocketOption::GnssFixRetry(x) => x as *const _ as *const sys::ctypes::c_void,
 			SocketOption::GnssNmeaMask(x) => x as *const _ as *const sys::ctypes::c_void,
+			SocketOption::GnssElevationMask(x) => x as *const _ as *const sys::ctypes::c_void,
+			SocketOption::GnssUseCase(x) => x as *const _ as *const sys::ctypes::c_void,
+			SocketOption::GnssPowerSaveMode(x) => x as *const _ as *const sys::ctypes::c_void,
 			SocketOption::GnssStart(x) => x as *const _ as *const sys::ctypes::c_void,
 			SocketOption::GnssStop => core::ptr::null(),
+			SocketOption::DfuOffset(x) => x as *const _ as *const sys::ctypes::c_void,
+			SocketOption::DfuApply => core::ptr::null(),
+			SocketOption::DfuBackupDelete => core::ptr::null(),
+			SocketOption::ReceiveTimeout(x) => x as *const _ as *const sys::ctypes::c_void,
+			SocketOption::SendTimeout(x) => x as *const _ as *const sys::ctypes::c_void,
 		}
 	}
 
@@ -318,12 +907,21 @@ impl<'a> SocketOption<'a> {
 			SocketOption::TlsHostName(s) => s.len() as u32,
 			SocketOption::TlsPeerVerify(x) => core::mem::size_of_val(x) as u32,
 			SocketOption::TlsSessionCache(x) => core::mem::size_of_val(x) as u32,
-			SocketOption::TlsTagList(x) => core::mem::size_of_val(x) as u32,
+			SocketOption::TlsTagList(x) => core::mem::size_of_val(*x) as u32,
+			SocketOption::TlsCipherList(x) => core::mem::size_of_val(*x) as u32,
 			SocketOption::GnssFixInterval(x) => core::mem::size_of_val(x) as u32,
 			SocketOption::GnssFixRetry(x) => core::mem::size_of_val(x) as u32,
 			SocketOption::GnssNmeaMask(x) => core::mem::size_of_val(x) as u32,
+			SocketOption::GnssElevationMask(x) => core::mem::size_of_val(x) as u32,
+			SocketOption::GnssUseCase(x) => core::mem::size_of_val(x) as u32,
+			SocketOption::GnssPowerSaveMode(x) => core::mem::size_of_val(x) as u32,
 			SocketOption::GnssStart(x) => core::mem::size_of_val(x) as u32,
 			SocketOption::GnssStop => 0u32,
+			SocketOption::DfuOffset(x) => core::mem::size_of_val(x) as u32,
+			SocketOption::DfuApply => 0u32,
+			SocketOption::DfuBackupDelete => 0u32,
+			SocketOption::ReceiveTimeout(x) => core::mem::size_of_val(x) as u32,
+			SocketOption::SendTimeout(x) => core::mem::size_of_val(x) as u32,
 		}
 	}
 }
@@ -335,6 +933,16 @@ impl From<SocketDomain> for i32 {
 			Local => sys::NRF_AF_LOCAL as i32,
 			Lte => sys::NRF_AF_LTE as i32,
 			Inet => sys::NRF_AF_INET as i32,
+			Inet6 => sys::NRF_AF_INET6 as i32,
+		}
+	}
+}
+
+impl From<SocketFamily> for SocketDomain {
+	fn from(family: SocketFamily) -> SocketDomain {
+		match family {
+			SocketFamily::Ipv4 => SocketDomain::Inet,
+			SocketFamily::Ipv6 => SocketDomain::Inet6,
 		}
 	}
 }
@@ -360,6 +968,7 @@ impl From<SocketProtocol> for i32 {
 			Tls1v3 => sys::NRF_SPROTO_TLS1v3 as i32,
 			Dtls1v2 => sys::NRF_SPROTO_DTLS1v2 as i32,
 			Gnss => sys::NRF_PROTO_GNSS as i32,
+			Dfu => sys::NRF_PROTO_DFU as i32,
 		}
 	}
 }
@@ -445,18 +1054,39 @@ impl<'a> PollEntry<'a> {
 ///     }
 /// }
 /// ```
-pub fn poll(poll_list: &mut [PollEntry], timeout_ms: u16) -> Result<i32, Error> {
+///
+/// Waits for up to `timeout_ms` milliseconds, or forever if `timeout_ms` is
+/// negative (e.g. `POLL_FOREVER`). Returns `Ok(0)` if the timeout expires
+/// before any socket is ready.
+///
+/// You can poll up to `MAX_SOCKETS_POLL` sockets; you get
+/// `Error::TooManySockets` if `poll_list` is longer than that.
+pub fn poll(poll_list: &mut [PollEntry], timeout_ms: i32) -> Result<i32, Error> {
+	poll_n::<MAX_SOCKETS_POLL>(poll_list, timeout_ms)
+}
+
+/// Like `poll`, but can poll up to `N` sockets instead of
+/// `MAX_SOCKETS_POLL`.
+///
+/// `N` sets the size of an array on the stack, so don't make it much larger
+/// than you need. You get `Error::TooManySockets` if `poll_list` is longer
+/// than `N`.
+///
+/// ```ignore
+/// nrfxlib::poll_n::<12>(&mut poll_list, 100)?;
+/// ```
+pub fn poll_n<const N: usize>(poll_list: &mut [PollEntry], timeout_ms: i32) -> Result<i32, Error> {
 	let mut count = 0;
 
-	if poll_list.len() > MAX_SOCKETS_POLL {
+	if poll_list.len() > N {
 		return Err(Error::TooManySockets);
 	}
 
-	let mut poll_fds: [sys::nrf_pollfd; MAX_SOCKETS_POLL] = [sys::nrf_pollfd {
+	let mut poll_fds: [sys::nrf_pollfd; N] = [sys::nrf_pollfd {
 		fd: 0,
 		events: 0,
 		revents: 0,
-	}; MAX_SOCKETS_POLL];
+	}; N];
 
 	for (poll_entry, pollfd) in poll_list.iter_mut().zip(poll_fds.iter_mut()) {
 		pollfd.fd = poll_entry.socket.get_fd();
@@ -464,7 +1094,7 @@ pub fn poll(poll_list: &mut [PollEntry], timeout_ms: u16) -> Result<i32, Error>
 		count += 1;
 	}
 
-	let result = unsafe { sys::nrf_poll(poll_fds.as_mut_ptr(), count, timeout_ms as i32) };
+	let result = backend::poll(&mut poll_fds[0..count], timeout_ms);
 
 	match result {
 		-1 => Err(Error::Nordic("poll", -1, get_last_error())),
@@ -478,16 +1108,287 @@ pub fn poll(poll_list: &mut [PollEntry], timeout_ms: u16) -> Result<i32, Error>
 	}
 }
 
+/// Look up the IPv4 addresses (A records) for `hostname`.
+///
+/// You get at most `MAX_RESOLVED_ADDRESSES` addresses, in the order the modem
+/// gave them to us. Their port numbers are zero.
+///
+/// ```ignore
+/// for addr in nrfxlib::resolve("example.com")? {
+///     info!("example.com is at {}", addr);
+/// }
+/// ```
+pub fn resolve(
+	hostname: &str,
+) -> Result<heapless::Vec<NrfSockAddrIn, MAX_RESOLVED_ADDRESSES>, Error> {
+	let mut result = heapless::Vec::new();
+	for addr in lookup(hostname, SocketFamily::Ipv4, SocketType::Stream, 0)? {
+		if let SockAddr::V4(addr) = addr {
+			// Can't fail - both lists are the same size
+			let _ = result.push(NrfSockAddrIn(addr));
+		}
+	}
+	Ok(result)
+}
+
+/// Like `resolve`, but looks up IPv6 addresses (AAAA records).
+pub fn resolve_v6(
+	hostname: &str,
+) -> Result<heapless::Vec<NrfSockAddrIn6, MAX_RESOLVED_ADDRESSES>, Error> {
+	let mut result = heapless::Vec::new();
+	for addr in lookup(hostname, SocketFamily::Ipv6, SocketType::Stream, 0)? {
+		if let SockAddr::V6(addr) = addr {
+			// Can't fail - both lists are the same size
+			let _ = result.push(NrfSockAddrIn6(addr));
+		}
+	}
+	Ok(result)
+}
+
 //******************************************************************************
 // Private Functions and Impl on Private Types
 //******************************************************************************
 
+/// Look up `hostname` with `getaddrinfo`, and get up to
+/// `MAX_RESOLVED_ADDRESSES` of the results, with their port set to `port`.
+/// Results which are neither IPv4 nor IPv6 are skipped.
+///
+/// If the look-up fails, you get `Error::Nordic("resolve", ...)`.
+pub(crate) fn lookup(
+	hostname: &str,
+	family: SocketFamily,
+	skt_type: SocketType,
+	port: u16,
+) -> Result<heapless::Vec<SockAddr, MAX_RESOLVED_ADDRESSES>, Error> {
+	use core::fmt::Write;
+
+	// Make a null-terminated hostname
+	let mut hostname_smallstring: heapless::String<64> = heapless::String::new();
+	write!(hostname_smallstring, "{}\0", hostname).map_err(|_| Error::HostnameTooLong)?;
+	// Now call getaddrinfo with some hints
+	let hints = sys::nrf_addrinfo {
+		ai_flags: 0,
+		ai_family: SocketDomain::from(family).into(),
+		ai_socktype: skt_type.into(),
+		ai_protocol: 0,
+		ai_addrlen: 0,
+		ai_addr: core::ptr::null_mut(),
+		ai_canonname: core::ptr::null_mut(),
+		ai_next: core::ptr::null_mut(),
+	};
+	let mut output_ptr: *mut sys::nrf_addrinfo = core::ptr::null_mut();
+	clear_last_error();
+	let result = backend::getaddrinfo(&hostname_smallstring, &hints, &mut output_ptr);
+	if result != 0 {
+		// The DNS look-up failed
+		return Err(Error::Nordic("resolve", result, get_last_error()));
+	}
+	let mut addresses = heapless::Vec::new();
+	let mut record_ptr = output_ptr;
+	while !record_ptr.is_null() && !addresses.is_full() {
+		let record: &sys::nrf_addrinfo = unsafe { &*record_ptr };
+		if let Some(addr) = SockAddr::from_addrinfo(record, port) {
+			// Can't fail - we checked there was space
+			let _ = addresses.push(addr);
+		}
+		record_ptr = record.ai_next;
+	}
+	if !output_ptr.is_null() {
+		backend::freeaddrinfo(output_ptr);
+	}
+	Ok(addresses)
+}
+
+/// Convert a `Duration` into a poll timeout, saturating at `i32::MAX`
+/// milliseconds.
+fn duration_to_ms(duration: Duration) -> i32 {
+	let ms = duration.as_millis();
+	if ms > i32::MAX as u128 {
+		i32::MAX
+	} else {
+		ms as i32
+	}
+}
+
+/// Build the error for a failed blocking call. The library reports `EAGAIN`
+/// when a send or receive timeout (see `Socket::set_receive_timeout`) expires.
+fn blocking_error(operation: &'static str, result: i32) -> Error {
+	let errno = get_last_error();
+	if errno == sys::NRF_EAGAIN as i32 {
+		Error::Timeout
+	} else {
+		Error::Nordic(operation, result, errno)
+	}
+}
+
+/// Convert a `Duration` into the Nordic library's time format, saturating at
+/// the largest value it can hold.
+fn to_timeval(duration: Duration) -> sys::nrf_timeval {
+	sys::nrf_timeval {
+		tv_sec: duration.as_secs().min(u64::from(u32::MAX)) as _,
+		tv_usec: duration.subsec_micros() as _,
+	}
+}
+
+impl Socket {
+	/// Add `count` to the number of bytes sent on this socket.
+	#[allow(unused_variables)]
+	fn record_sent(&self, count: sys::ssize_t) {
+		#[cfg(feature = "socket-stats")]
+		{
+			let mut stats = self.stats.get();
+			stats.bytes_sent += count as u64;
+			self.stats.set(stats);
+		}
+	}
+
+	/// Add `count` to the number of bytes received on this socket.
+	#[allow(unused_variables)]
+	fn record_received(&self, count: sys::ssize_t) {
+		#[cfg(feature = "socket-stats")]
+		{
+			let mut stats = self.stats.get();
+			stats.bytes_received += count as u64;
+			self.stats.set(stats);
+		}
+	}
+}
+
 pub(crate) fn htons(input: u16) -> u16 {
 	let top: u16 = (input >> 8) & 0xFF;
 	let bottom: u16 = input & 0xFF;
 	(bottom << 8) | top
 }
 
+impl SockAddr {
+	/// Get the address from a `getaddrinfo` result, with the port set to
+	/// `port`. Returns `None` if the result is neither IPv4 nor IPv6.
+	pub(crate) fn from_addrinfo(record: &sys::nrf_addrinfo, port: u16) -> Option<SockAddr> {
+		if record.ai_addr.is_null() {
+			return None;
+		}
+		match record.ai_family as u32 {
+			sys::NRF_AF_INET => {
+				let dns_addr: &sys::nrf_sockaddr_in =
+					unsafe { &*(record.ai_addr as *const sys::nrf_sockaddr_in) };
+				Some(SockAddr::V4(sys::nrf_sockaddr_in {
+					sin_len: core::mem::size_of::<sys::nrf_sockaddr_in>() as u8,
+					sin_family: sys::NRF_AF_INET as i32,
+					sin_port: htons(port),
+					sin_addr: dns_addr.sin_addr,
+				}))
+			}
+			sys::NRF_AF_INET6 => {
+				let dns_addr: &sys::nrf_sockaddr_in6 =
+					unsafe { &*(record.ai_addr as *const sys::nrf_sockaddr_in6) };
+				Some(SockAddr::V6(sys::nrf_sockaddr_in6 {
+					sin6_len: core::mem::size_of::<sys::nrf_sockaddr_in6>() as u8,
+					sin6_family: sys::NRF_AF_INET6 as i32,
+					sin6_port: htons(port),
+					sin6_flowinfo: 0,
+					sin6_addr: dns_addr.sin6_addr,
+					sin6_scope_id: dns_addr.sin6_scope_id,
+				}))
+			}
+			_ => None,
+		}
+	}
+}
+
+/// Ask for `waker` to be woken the next time the library handles an
+/// application interrupt, which is when sockets may have become ready.
+///
+/// If there are already `MAX_WAKERS` tasks waiting, the waker is woken
+/// straight away so the task polls again rather than sleeping forever.
+#[cfg(feature = "async")]
+pub(crate) fn register_waker(waker: &core::task::Waker) {
+	let registered = cortex_m::interrupt::free(|cs| {
+		let mut wakers = WAKERS.borrow(cs).borrow_mut();
+		if wakers.iter().any(|w| w.will_wake(waker)) {
+			true
+		} else {
+			wakers.push(waker.clone()).is_ok()
+		}
+	});
+	if !registered {
+		waker.wake_by_ref();
+	}
+}
+
+/// Wake every task registered with `register_waker`. Called from
+/// `application_irq_handler`.
+#[cfg(feature = "async")]
+pub(crate) fn wake_all() {
+	let wakers =
+		cortex_m::interrupt::free(|cs| core::mem::take(&mut *WAKERS.borrow(cs).borrow_mut()));
+	for waker in wakers {
+		waker.wake();
+	}
+}
+
+//******************************************************************************
+// Tests
+//******************************************************************************
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn list_option_length_covers_every_entry() {
+		let tags = [1, 2, 3];
+		assert_eq!(
+			SocketOption::TlsTagList(&tags).get_length() as usize,
+			3 * core::mem::size_of::<sys::nrf_sec_tag_t>()
+		);
+		let ciphers = [0xC02B, 0xC0AE];
+		assert_eq!(
+			SocketOption::TlsCipherList(&ciphers).get_length() as usize,
+			2 * core::mem::size_of::<sys::nrf_sec_cipher_t>()
+		);
+	}
+
+	#[test]
+	fn reopen_closes_each_socket_once() {
+		crate::backend::mock::run(|modem| {
+			let mut skt =
+				Socket::new(SocketDomain::Inet, SocketType::Stream, SocketProtocol::Tcp).unwrap();
+			let old_fd = skt.fd;
+			skt.reopen().unwrap();
+			let new_fd = skt.fd;
+			assert_ne!(old_fd, new_fd);
+			assert_eq!(modem.closed(), [old_fd]);
+			drop(skt);
+			assert_eq!(modem.closed(), [old_fd, new_fd]);
+		});
+	}
+
+	#[test]
+	fn connect_errors_are_labelled() {
+		crate::backend::mock::run(|modem| {
+			let skt = crate::tcp::TcpSocket::new().unwrap();
+			modem.refuse(1);
+			let result = skt.connect_ip(core::net::Ipv4Addr::new(192, 0, 2, 1), 80);
+			assert!(matches!(result, Err(Error::Nordic("tcp_connect", _, _))));
+			skt.connect_ip(core::net::Ipv4Addr::new(192, 0, 2, 1), 80)
+				.unwrap();
+			assert_eq!(
+				skt.peer_addr().map(|addr| u16::from_be(addr.sin_port)),
+				Some(80)
+			);
+		});
+	}
+
+	#[test]
+	fn ipv6_sockets_cannot_be_servers() {
+		crate::backend::mock::run(|_| {
+			let skt = crate::tcp::TcpSocket::new_with_family(SocketFamily::Ipv6).unwrap();
+			assert!(matches!(skt.bind(80), Err(Error::InvalidArgument)));
+			assert!(matches!(skt.accept(), Err(Error::InvalidArgument)));
+		});
+	}
+}
+
 //******************************************************************************
 // End of File
 //******************************************************************************
@@ -17,10 +17,9 @@
 // Imports
 //******************************************************************************
 
-use super::{get_last_error, Error};
+use super::{Error, NrfSockAddrIn, NrfSockAddrIn6};
 use crate::raw::*;
 use log::debug;
-use nrfxlib_sys as sys;
 
 //******************************************************************************
 // Types
@@ -61,9 +60,14 @@ pub struct TcpSocket {
 //******************************************************************************
 
 impl TcpSocket {
-	/// Create a new TCP socket.
+	/// Create a new IPv4 TCP socket.
 	pub fn new() -> Result<TcpSocket, Error> {
-		let socket = Socket::new(SocketDomain::Inet, SocketType::Stream, SocketProtocol::Tcp)?;
+		TcpSocket::new_with_family(SocketFamily::Ipv4)
+	}
+
+	/// Create a new TCP socket, using IPv4 or IPv6.
+	pub fn new_with_family(family: SocketFamily) -> Result<TcpSocket, Error> {
+		let socket = Socket::new(family.into(), SocketType::Stream, SocketProtocol::Tcp)?;
 
 		// Now configure this socket
 
@@ -71,81 +75,95 @@ impl TcpSocket {
 	}
 
 	/// Look up the hostname and for each result returned, try to connect to
-	/// it.
-	pub fn connect(&self, hostname: &str, port: u16) -> Result<(), Error> {
-		use core::fmt::Write;
-
+	/// it. An IPv6 socket looks up AAAA records, and an IPv4 socket looks up
+	/// A records.
+	///
+	/// Stops at the first address which accepts the connection. If none of
+	/// them do, you get `Error::ConnectFailed`. The underlying socket is
+	/// replaced after each failed attempt.
+	pub fn connect(&mut self, hostname: &str, port: u16) -> Result<(), Error> {
 		debug!("Connecting via TCP to {}:{}", hostname, port);
 
-		// Now, make a null-terminated hostname
-		let mut hostname_smallstring: heapless::String<64> = heapless::String::new();
-		write!(hostname_smallstring, "{}\0", hostname).map_err(|_| Error::HostnameTooLong)?;
-		// Now call getaddrinfo with some hints
-		let hints = sys::nrf_addrinfo {
-			ai_flags: 0,
-			ai_family: sys::NRF_AF_INET as i32,
-			ai_socktype: sys::NRF_SOCK_STREAM as i32,
-			ai_protocol: 0,
-			ai_addrlen: 0,
-			ai_addr: core::ptr::null_mut(),
-			ai_canonname: core::ptr::null_mut(),
-			ai_next: core::ptr::null_mut(),
-		};
-		let mut output_ptr: *mut sys::nrf_addrinfo = core::ptr::null_mut();
-		let mut result = unsafe {
-			sys::nrf_getaddrinfo(
-				// hostname
-				hostname_smallstring.as_ptr(),
-				// service
-				core::ptr::null(),
-				// hints
-				&hints,
-				// output pointer
-				&mut output_ptr,
-			)
-		};
-		if (result == 0) && (!output_ptr.is_null()) {
-			let mut record: &sys::nrf_addrinfo = unsafe { &*output_ptr };
-			loop {
-				let dns_addr: &sys::nrf_sockaddr_in =
-					unsafe { &*(record.ai_addr as *const sys::nrf_sockaddr_in) };
-				// Create a new sockaddr_in with the right port
-				let connect_addr = sys::nrf_sockaddr_in {
-					sin_len: core::mem::size_of::<sys::nrf_sockaddr_in>() as u8,
-					sin_family: sys::NRF_AF_INET as i32,
-					sin_port: htons(port),
-					sin_addr: dns_addr.sin_addr,
-				};
-
-				debug!("Trying IP address {}", &crate::NrfSockAddrIn(connect_addr));
-
-				// try and connect to this result
-				result = unsafe {
-					sys::nrf_connect(
-						self.socket.fd,
-						&connect_addr as *const sys::nrf_sockaddr_in as *const _,
-						connect_addr.sin_len as u32,
-					)
-				};
-				if result == 0 {
-					break;
-				}
-				if !record.ai_next.is_null() {
-					record = unsafe { &*record.ai_next };
-				} else {
-					break;
-				}
-			}
-			unsafe {
-				sys::nrf_freeaddrinfo(output_ptr);
-			}
-		}
-		if result != 0 {
-			Err(Error::Nordic("tcp_connect", result, get_last_error()))
+		self.socket.connect_by_name(hostname, port, |_| Ok(()))
+	}
+
+	/// Connect to the given IPv4 address and port, without doing a DNS
+	/// look-up.
+	pub fn connect_ip(&self, addr: core::net::Ipv4Addr, port: u16) -> Result<(), Error> {
+		self.connect_addr(&core::net::SocketAddrV4::new(addr, port).into())
+	}
+
+	/// Like `connect_ip`, but taking the address and port together.
+	pub fn connect_addr(&self, addr: &NrfSockAddrIn) -> Result<(), Error> {
+		self.socket
+			.connect_labelled("tcp_connect", SockAddr::V4(addr.0))
+	}
+
+	/// Connect to the given IPv6 address and port, without doing a DNS
+	/// look-up. The socket must have been created with `SocketFamily::Ipv6`.
+	pub fn connect_ipv6(&self, addr: core::net::Ipv6Addr, port: u16) -> Result<(), Error> {
+		self.connect_addr6(&core::net::SocketAddrV6::new(addr, port, 0, 0).into())
+	}
+
+	/// Like `connect_ipv6`, but taking the address and port together.
+	pub fn connect_addr6(&self, addr: &NrfSockAddrIn6) -> Result<(), Error> {
+		self.socket
+			.connect_labelled("tcp_connect", SockAddr::V6(addr.0))
+	}
+
+	/// Bind this socket to the given local port, on any local address, so you
+	/// can `listen` on it.
+	///
+	/// Only IPv4 sockets can act as servers. On a socket made with
+	/// `SocketFamily::Ipv6`, this and `accept` give you
+	/// `Error::InvalidArgument`.
+	pub fn bind(&self, port: u16) -> Result<(), Error> {
+		debug!("Binding TCP socket to port {}", port);
+		self.socket.bind(&NrfSockAddrIn::new([0, 0, 0, 0], port))
+	}
+
+	/// Start accepting incoming connections on this socket, which must have
+	/// been bound with `bind`. `backlog` is how many connections can be
+	/// waiting to be accepted.
+	pub fn listen(&self, backlog: i32) -> Result<(), Error> {
+		self.socket.listen(backlog)
+	}
+
+	/// Accept an incoming connection, if there is one waiting. You get a new
+	/// socket for the connection, and the address of the remote device.
+	///
+	/// Returns `Ok(None)` if no connection is waiting, like `recv`.
+	pub fn accept(&self) -> Result<Option<(TcpSocket, NrfSockAddrIn)>, Error> {
+		self.socket.require_ipv4()?;
+		let result = self
+			.socket
+			.poll(PollFlags::Read, core::time::Duration::from_millis(0))?;
+		if result.is_readable() {
+			self.accept_wait().map(Some)
 		} else {
-			Ok(())
+			Ok(None)
 		}
 	}
+
+	/// Wait for an incoming connection. You get a new socket for the
+	/// connection, and the address of the remote device.
+	pub fn accept_wait(&self) -> Result<(TcpSocket, NrfSockAddrIn), Error> {
+		let (socket, addr) = self.socket.accept()?;
+		debug!("Accepted TCP connection from {}", addr);
+		Ok((TcpSocket { socket }, addr))
+	}
+
+	/// Drop any connection (or half-finished connection attempt) and return
+	/// this socket to the unconnected state, ready for another call to
+	/// `connect`.
+	///
+	/// The modem can't reset a socket in place, so this closes the underlying
+	/// socket and opens a new one. If that fails, this `TcpSocket` is left
+	/// unusable and every operation on it will return an error.
+	pub fn disconnect(&mut self) -> Result<(), Error> {
+		debug!("Disconnecting TCP socket {}", self.socket.fd);
+		self.socket.reopen()
+	}
 }
 
 impl Pollable for TcpSocket {
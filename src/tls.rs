@@ -17,7 +17,8 @@
 // Imports
 //******************************************************************************
 
-use super::{get_last_error, AtError, Error};
+use super::{AtError, Error, NrfSockAddrIn, NrfSockAddrIn6};
+use crate::at::CmeErrorCode;
 use crate::raw::*;
 use core::fmt::Write;
 use log::debug;
@@ -28,9 +29,25 @@ use nrfxlib_sys as sys;
 //******************************************************************************
 
 /// Represents a connection to a remote TCP/IP device using TLS.
+///
+/// If you connect by IP address (e.g. with `connect_ip`), the server's
+/// certificate is not checked against a hostname, as we don't know it. Use
+/// `connect` if you need that.
 #[derive(Debug)]
 pub struct TlsSocket {
 	socket: Socket,
+	/// The security options we have set on `socket`
+	settings: SecureSettings,
+}
+
+/// The security options set on a TLS or DTLS socket. If `connect` has to
+/// replace the socket, it sets them again on the new one.
+#[derive(Debug, Clone)]
+pub(crate) struct SecureSettings {
+	peer_verify: sys::nrf_sec_peer_verify_t,
+	session_cache: sys::nrf_sec_session_cache_t,
+	security_tags: heapless::Vec<sys::nrf_sec_tag_t, MAX_SECURITY_TAGS>,
+	cipher_suites: heapless::Vec<sys::nrf_sec_cipher_t, MAX_CIPHER_SUITES>,
 }
 
 /// Specify which version of the TLS standard to use
@@ -54,16 +71,35 @@ pub enum PeerVerification {
 	Disabled,
 }
 
-#[derive(Debug, Copy, Clone)]
-enum CredentialType {
+/// Identifies a set of credentials stored in the modem. See
+/// `provision_certificates`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct SecurityTag(sys::nrf_sec_tag_t);
+
+/// The kinds of credential the modem can store under a `SecurityTag`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CredentialType {
+	/// A root CA certificate
 	RootCA = 0,
+	/// A client certificate
 	ClientCert = 1,
+	/// A client private key
 	ClientPrivate = 2,
+	/// A pre-shared key
+	Psk = 3,
+	/// A PSK identity
+	PskIdentity = 4,
+	/// A public key
+	PublicKey = 5,
+	/// The device identity public key
+	DeviceIdentityPublicKey = 6,
 }
 
 #[derive(Debug, Copy, Clone)]
 enum CredentialOpcode {
 	Write = 0,
+	List = 1,
 	Delete = 3,
 }
 
@@ -71,7 +107,50 @@ enum CredentialOpcode {
 // Constants
 //******************************************************************************
 
-// None
+/// Security tags from this value upwards are reserved for use by the modem.
+const FIRST_RESERVED_SECURITY_TAG: u32 = 0x8000_0000;
+
+/// The most security tags the modem accepts on one socket
+/// (`NRF_SOCKET_TLS_MAX_SEC_TAG_LIST_SIZE`).
+const MAX_SECURITY_TAGS: usize = 7;
+
+/// The most cipher suites we remember for `set_cipher_suites`. That's more
+/// than the modem supports.
+const MAX_CIPHER_SUITES: usize = 32;
+
+/// Room for the response to `AT%CMNG=1` listing 16 credentials. Each line is
+/// at most 90 bytes (`%CMNG: 4294967295,255,"<64 hex digits>"\r\n`), and
+/// then there's the `OK`.
+const CREDENTIAL_LIST_LENGTH: usize = 16 * 90 + 16;
+
+/// TLS_ECDHE_ECDSA_WITH_AES_256_CBC_SHA384, for `set_cipher_suites`
+pub const TLS_ECDHE_ECDSA_WITH_AES_256_CBC_SHA384: u32 = 0xC024;
+/// TLS_ECDHE_ECDSA_WITH_AES_256_CBC_SHA, for `set_cipher_suites`
+pub const TLS_ECDHE_ECDSA_WITH_AES_256_CBC_SHA: u32 = 0xC00A;
+/// TLS_ECDHE_ECDSA_WITH_AES_128_CBC_SHA256, for `set_cipher_suites`
+pub const TLS_ECDHE_ECDSA_WITH_AES_128_CBC_SHA256: u32 = 0xC023;
+/// TLS_ECDHE_ECDSA_WITH_AES_128_CBC_SHA, for `set_cipher_suites`
+pub const TLS_ECDHE_ECDSA_WITH_AES_128_CBC_SHA: u32 = 0xC009;
+/// TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256, for `set_cipher_suites`
+pub const TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256: u32 = 0xC02B;
+/// TLS_ECDHE_ECDSA_WITH_AES_128_CCM_8, for `set_cipher_suites`
+pub const TLS_ECDHE_ECDSA_WITH_AES_128_CCM_8: u32 = 0xC0AE;
+/// TLS_ECDHE_RSA_WITH_AES_256_CBC_SHA, for `set_cipher_suites`
+pub const TLS_ECDHE_RSA_WITH_AES_256_CBC_SHA: u32 = 0xC014;
+/// TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA256, for `set_cipher_suites`
+pub const TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA256: u32 = 0xC027;
+/// TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA, for `set_cipher_suites`
+pub const TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA: u32 = 0xC013;
+/// TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256, for `set_cipher_suites`
+pub const TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256: u32 = 0xC02F;
+/// TLS_PSK_WITH_AES_256_CBC_SHA, for `set_cipher_suites`
+pub const TLS_PSK_WITH_AES_256_CBC_SHA: u32 = 0x008D;
+/// TLS_PSK_WITH_AES_128_CBC_SHA256, for `set_cipher_suites`
+pub const TLS_PSK_WITH_AES_128_CBC_SHA256: u32 = 0x00AE;
+/// TLS_PSK_WITH_AES_128_CBC_SHA, for `set_cipher_suites`
+pub const TLS_PSK_WITH_AES_128_CBC_SHA: u32 = 0x008C;
+/// TLS_PSK_WITH_AES_128_CCM_8, for `set_cipher_suites`
+pub const TLS_PSK_WITH_AES_128_CCM_8: u32 = 0xC0A8;
 
 //******************************************************************************
 // Global Variables
@@ -90,120 +169,102 @@ enum CredentialOpcode {
 //******************************************************************************
 
 impl TlsSocket {
-	/// Create a new TLS socket. Only supports TLS v1.2/1.3 and IPv4 at the moment.
+	/// Create a new IPv4 TLS socket. Only supports TLS v1.2/1.3 at the moment.
+	///
+	/// The modem takes at most seven `security_tags`; you get
+	/// `Error::InvalidArgument` if you give more.
 	pub fn new(
 		peer_verify: PeerVerification,
-		security_tags: &[u32],
+		security_tags: &[SecurityTag],
 		version: Version,
+	) -> Result<TlsSocket, Error> {
+		TlsSocket::new_with_family(peer_verify, security_tags, version, SocketFamily::Ipv4)
+	}
+
+	/// Create a new TLS socket, using IPv4 or IPv6. See `new`.
+	pub fn new_with_family(
+		peer_verify: PeerVerification,
+		security_tags: &[SecurityTag],
+		version: Version,
+		family: SocketFamily,
 	) -> Result<TlsSocket, Error> {
 		let nrf_tls_version = match version {
 			Version::Tls1v2 => SocketProtocol::Tls1v2,
 			Version::Tls1v3 => SocketProtocol::Tls1v3,
 		};
 
-		let socket = Socket::new(SocketDomain::Inet, SocketType::Stream, nrf_tls_version)?;
+		let socket = Socket::new(family.into(), SocketType::Stream, nrf_tls_version)?;
 
 		// Now configure this socket
+		let settings = SecureSettings::new(peer_verify.as_integer(), security_tags)?;
+		settings.apply(&socket)?;
 
-		// Set whether we verify the peer
-		socket.set_option(SocketOption::TlsPeerVerify(peer_verify.as_integer()))?;
-
-		// Always enable session caching to speed up connecting. 0 = enabled, 1
-		// = disabled (the default).
-		socket.set_option(SocketOption::TlsSessionCache(0))?;
-
-		// We don't set the cipher list, and assume the defaults are sensible.
-
-		if !security_tags.is_empty() {
-			// Configure the socket to use the pre-stored certificates. See
-			// `provision_certificates`.
-			socket.set_option(SocketOption::TlsTagList(security_tags))?;
-		}
-
-		Ok(TlsSocket { socket })
+		Ok(TlsSocket { socket, settings })
 	}
 
 	/// Look up the hostname and for each result returned, try to connect to
-	/// it.
-	pub fn connect(&self, hostname: &str, port: u16) -> Result<(), Error> {
+	/// it. An IPv6 socket looks up AAAA records, and an IPv4 socket looks up
+	/// A records.
+	///
+	/// Stops at the first address which accepts the connection. If none of
+	/// them do, you get `Error::ConnectFailed`. The underlying socket is
+	/// replaced after each failed attempt, with the same security options.
+	pub fn connect(&mut self, hostname: &str, port: u16) -> Result<(), Error> {
 		debug!("Connecting via TLS to {}:{}", hostname, port);
 
 		// First we set the hostname
 		self.socket
 			.set_option(SocketOption::TlsHostName(hostname))?;
 
-		let mut result;
-		// Now, make a null-terminated hostname
-		let mut hostname_smallstring: heapless::String<64> = heapless::String::new();
-		write!(hostname_smallstring, "{}\0", hostname).map_err(|_| Error::HostnameTooLong)?;
-		// Now call getaddrinfo with some hints
-		let hints = sys::nrf_addrinfo {
-			ai_flags: 0,
-			ai_family: sys::NRF_AF_INET as i32,
-			ai_socktype: sys::NRF_SOCK_STREAM as i32,
-			ai_protocol: 0,
-			ai_addrlen: 0,
-			ai_addr: core::ptr::null_mut(),
-			ai_canonname: core::ptr::null_mut(),
-			ai_next: core::ptr::null_mut(),
-		};
-		let mut output_ptr: *mut sys::nrf_addrinfo = core::ptr::null_mut();
-		result = unsafe {
-			sys::nrf_getaddrinfo(
-				// hostname
-				hostname_smallstring.as_ptr(),
-				// service
-				core::ptr::null(),
-				// hints
-				&hints,
-				// output pointer
-				&mut output_ptr,
-			)
-		};
+		let settings = &self.settings;
+		self.socket.connect_by_name(hostname, port, |socket| {
+			settings.apply(socket)?;
+			socket.set_option(SocketOption::TlsHostName(hostname))
+		})
+	}
 
-		if (result != 0) && output_ptr.is_null() {
-			return Err(Error::Nordic("tls_dns", result, get_last_error()));
-		} else {
-			let mut record: &sys::nrf_addrinfo = unsafe { &*output_ptr };
-			loop {
-				let dns_addr: &sys::nrf_sockaddr_in =
-					unsafe { &*(record.ai_addr as *const sys::nrf_sockaddr_in) };
-				// Create a new sockaddr_in with the right port
-				let connect_addr = sys::nrf_sockaddr_in {
-					sin_len: core::mem::size_of::<sys::nrf_sockaddr_in>() as u8,
-					sin_family: sys::NRF_AF_INET as i32,
-					sin_port: htons(port),
-					sin_addr: dns_addr.sin_addr,
-				};
-
-				debug!("Trying IP address {}", &crate::NrfSockAddrIn(connect_addr));
-
-				// try and connect to this result
-				result = unsafe {
-					sys::nrf_connect(
-						self.socket.fd,
-						&connect_addr as *const sys::nrf_sockaddr_in as *const _,
-						connect_addr.sin_len as u32,
-					)
-				};
-				if result == 0 {
-					break;
-				}
-				if !record.ai_next.is_null() {
-					record = unsafe { &*record.ai_next };
-				} else {
-					break;
-				}
-			}
-			unsafe {
-				sys::nrf_freeaddrinfo(output_ptr);
-			}
-		}
-		if result != 0 {
-			Err(Error::Nordic("tls_connect", result, get_last_error()))
-		} else {
-			Ok(())
-		}
+	/// Choose whether the modem caches the TLS session, so it can resume it
+	/// next time rather than doing a full handshake. `new` turns this on.
+	///
+	/// Resuming a session makes connecting much faster and uses less data,
+	/// but lets the server link your connections together, and some servers
+	/// handle resumption badly. Call this before `connect`.
+	pub fn set_session_cache(&mut self, enabled: bool) -> Result<(), Error> {
+		self.settings.set_session_cache(&self.socket, enabled)
+	}
+
+	/// Restrict the cipher suites the modem will offer to the ones in
+	/// `suites`, given as IANA values (see the `TLS_*` constants in the
+	/// `tls` module). By default the modem offers every suite it supports.
+	///
+	/// Call this before `connect`. You get `Error::InvalidArgument` if
+	/// `suites` lists more than 32 suites.
+	pub fn set_cipher_suites(&mut self, suites: &[u32]) -> Result<(), Error> {
+		self.settings.set_cipher_suites(&self.socket, suites)
+	}
+
+	/// Connect to the given IPv4 address and port, without doing a DNS
+	/// look-up.
+	pub fn connect_ip(&self, addr: core::net::Ipv4Addr, port: u16) -> Result<(), Error> {
+		self.connect_addr(&core::net::SocketAddrV4::new(addr, port).into())
+	}
+
+	/// Like `connect_ip`, but taking the address and port together.
+	pub fn connect_addr(&self, addr: &NrfSockAddrIn) -> Result<(), Error> {
+		self.socket
+			.connect_labelled("tls_connect", SockAddr::V4(addr.0))
+	}
+
+	/// Connect to the given IPv6 address and port, without doing a DNS
+	/// look-up. The socket must have been created with `SocketFamily::Ipv6`.
+	pub fn connect_ipv6(&self, addr: core::net::Ipv6Addr, port: u16) -> Result<(), Error> {
+		self.connect_addr6(&core::net::SocketAddrV6::new(addr, port, 0, 0).into())
+	}
+
+	/// Like `connect_ipv6`, but taking the address and port together.
+	pub fn connect_addr6(&self, addr: &NrfSockAddrIn6) -> Result<(), Error> {
+		self.socket
+			.connect_labelled("tls_connect", SockAddr::V6(addr.0))
 	}
 }
 
@@ -239,12 +300,119 @@ impl PeerVerification {
 	}
 }
 
+impl SecureSettings {
+	/// Start with the given peer verification and security tags, and session
+	/// caching enabled. Gives `Error::InvalidArgument` if there are more
+	/// security tags than the modem accepts.
+	pub(crate) fn new(
+		peer_verify: sys::nrf_sec_peer_verify_t,
+		security_tags: &[SecurityTag],
+	) -> Result<SecureSettings, Error> {
+		let mut tags = heapless::Vec::new();
+		tags.extend_from_slice(SecurityTag::as_raw_slice(security_tags))
+			.map_err(|_| Error::InvalidArgument)?;
+		Ok(SecureSettings {
+			peer_verify,
+			// Always enable session caching to speed up connecting. 1 =
+			// enabled, 0 = disabled (the default).
+			session_cache: 1,
+			security_tags: tags,
+			// We don't set the cipher list, and assume the defaults are
+			// sensible. Call `set_cipher_suites` if they aren't.
+			cipher_suites: heapless::Vec::new(),
+		})
+	}
+
+	/// Set all these options on `socket`.
+	pub(crate) fn apply(&self, socket: &Socket) -> Result<(), Error> {
+		// Set whether we verify the peer
+		socket.set_option(SocketOption::TlsPeerVerify(self.peer_verify))?;
+		socket.set_option(SocketOption::TlsSessionCache(self.session_cache))?;
+		if !self.security_tags.is_empty() {
+			// Configure the socket to use the pre-stored certificates. See
+			// `provision_certificates`.
+			socket.set_option(SocketOption::TlsTagList(&self.security_tags))?;
+		}
+		if !self.cipher_suites.is_empty() {
+			socket.set_option(SocketOption::TlsCipherList(&self.cipher_suites))?;
+		}
+		Ok(())
+	}
+
+	/// Turn session caching on or off, on `socket` and for any socket which
+	/// replaces it.
+	pub(crate) fn set_session_cache(
+		&mut self,
+		socket: &Socket,
+		enabled: bool,
+	) -> Result<(), Error> {
+		// 1 = enabled, 0 = disabled
+		let value = if enabled { 1 } else { 0 };
+		socket.set_option(SocketOption::TlsSessionCache(value))?;
+		self.session_cache = value;
+		Ok(())
+	}
+
+	/// Restrict the cipher suites, on `socket` and for any socket which
+	/// replaces it.
+	pub(crate) fn set_cipher_suites(
+		&mut self,
+		socket: &Socket,
+		suites: &[u32],
+	) -> Result<(), Error> {
+		let mut list = heapless::Vec::new();
+		list.extend_from_slice(suites)
+			.map_err(|_| Error::InvalidArgument)?;
+		socket.set_option(SocketOption::TlsCipherList(suites))?;
+		self.cipher_suites = list;
+		Ok(())
+	}
+}
+
+impl SecurityTag {
+	/// Create a new security tag. Returns `None` if the value is in the range
+	/// reserved for the modem's own use.
+	pub const fn new(tag: u32) -> Option<SecurityTag> {
+		if tag < FIRST_RESERVED_SECURITY_TAG {
+			Some(SecurityTag(tag))
+		} else {
+			None
+		}
+	}
+
+	/// Get the numeric value of this tag.
+	pub const fn as_u32(self) -> u32 {
+		self.0
+	}
+
+	/// The NRF library wants a list of `sys::nrf_sec_tag_t` values, which is
+	/// exactly what a list of `SecurityTag` is.
+	pub(crate) fn as_raw_slice(tags: &[SecurityTag]) -> &[sys::nrf_sec_tag_t] {
+		// NOTE(unsafe) - SecurityTag is `repr(transparent)` over `nrf_sec_tag_t`.
+		unsafe {
+			core::slice::from_raw_parts(tags.as_ptr() as *const sys::nrf_sec_tag_t, tags.len())
+		}
+	}
+}
+
+impl From<SecurityTag> for u32 {
+	fn from(tag: SecurityTag) -> u32 {
+		tag.0
+	}
+}
+
+impl core::fmt::Display for SecurityTag {
+	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+		write!(f, "{}", self.0)
+	}
+}
+
 /// Store SSL certificates in the modem NVRAM for use with a subsequent TLS
 /// connection.
 ///
 /// Any existing certificates with the given tag are deleted.
 ///
-/// * `tag` - the numeric value used to identify this set of certificates.
+/// * `tag` - the value used to identify this set of certificates.
 /// * `ca_chain` - Supply a string representing an X509 server side
 ///   certificate chain in PEM format, or None.
 /// * `public_cert` - If you want client-side auth, supply an X509 client
@@ -252,28 +420,153 @@ impl PeerVerification {
 /// * `key` - If you want client-side auth, supply the private key for the
 ///   `public_cert` in PEM format here, otherwise supply None.
 pub fn provision_certificates(
-	tag: u32,
+	tag: SecurityTag,
 	ca_chain: Option<&'static str>,
 	public_cert: Option<&'static str>,
 	key: Option<&'static str>,
+) -> Result<(), Error> {
+	// Check everything before we delete any existing credentials
+	for credential in [ca_chain, public_cert, key].iter().flatten() {
+		validate_credential(credential)?;
+	}
+	write_credentials(
+		tag,
+		&[
+			(CredentialType::RootCA, ca_chain),
+			(CredentialType::ClientCert, public_cert),
+			(CredentialType::ClientPrivate, key),
+		],
+	)
+}
+
+/// Store a pre-shared key (PSK) and PSK identity in the modem NVRAM for use
+/// with a subsequent TLS or DTLS connection.
+///
+/// Any existing PSK and identity with the given tag are deleted.
+///
+/// * `tag` - the value used to identify this PSK.
+/// * `identity` - the PSK identity, as text.
+/// * `psk_hex` - the key itself, as a string of hex digits (e.g.
+///   `"0123456789abcdef"`).
+pub fn provision_psk(tag: SecurityTag, identity: &str, psk_hex: &str) -> Result<(), Error> {
+	validate_credential(identity)?;
+	let psk_valid = !psk_hex.is_empty()
+		&& psk_hex.len() % 2 == 0
+		&& psk_hex.bytes().all(|b| b.is_ascii_hexdigit());
+	if !psk_valid {
+		return Err(Error::InvalidCredential);
+	}
+	write_credentials(
+		tag,
+		&[
+			(CredentialType::Psk, Some(psk_hex)),
+			(CredentialType::PskIdentity, Some(identity)),
+		],
+	)
+}
+
+/// List the credentials stored in the modem, as pairs of security tag and
+/// credential type.
+///
+/// The list includes any credentials stored under the tags reserved for the
+/// modem's own use. Credentials of types this crate doesn't know about are
+/// left out, as are any after the first 16.
+///
+/// The modem sends the whole list at once. If it holds so many credentials
+/// that the list doesn't fit in our buffer, you get `Error::Truncated`.
+pub fn list_credentials() -> Result<heapless::Vec<(u32, CredentialType), 16>, Error> {
+	let mut result = heapless::Vec::new();
+	let mut parse_result = Ok(());
+	// Each line looks like `%CMNG: 16842753,0,"<sha256>"`
+	crate::at::AtCommand::new("AT%CMNG")
+		.arg(CredentialOpcode::List)
+		.send_long::<CREDENTIAL_LIST_LENGTH, _>(|line| {
+			if let Some(fields) = line.strip_prefix("%CMNG:") {
+				let mut fields = fields.split(',').map(str::trim);
+				let tag = fields.next().and_then(|f| f.parse::<u32>().ok());
+				let kind = fields.next().and_then(|f| f.parse::<u8>().ok());
+				match (tag, kind) {
+					(Some(tag), Some(kind)) => {
+						if let Some(kind) = CredentialType::from_u8(kind) {
+							// Ignore any which don't fit
+							let _ = result.push((tag, kind));
+						}
+					}
+					_ => parse_result = Err(Error::BadDataFormat),
+				}
+			}
+		})?;
+	parse_result.map(|_| result)
+}
+
+/// Delete a credential stored in the modem.
+///
+/// You get `AtError::CmeError(CmeErrorCode::NotFound)` if there is no such
+/// credential.
+pub fn delete_credential(tag: SecurityTag, kind: CredentialType) -> Result<(), Error> {
+	crate::at::AtCommand::new("AT%CMNG")
+		.arg(CredentialOpcode::Delete)
+		.arg(tag)
+		.arg(kind)
+		.send(|_| {})
+}
+
+impl core::fmt::Display for CredentialOpcode {
+	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+		write!(f, "{}", *self as i32)
+	}
+}
+
+impl core::fmt::Display for CredentialType {
+	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+		write!(f, "{}", *self as i32)
+	}
+}
+
+//******************************************************************************
+// Private Functions and Impl on Private Types
+//******************************************************************************
+
+impl CredentialType {
+	/// Convert the type number used by `AT%CMNG`, if we recognise it.
+	fn from_u8(value: u8) -> Option<CredentialType> {
+		match value {
+			0 => Some(CredentialType::RootCA),
+			1 => Some(CredentialType::ClientCert),
+			2 => Some(CredentialType::ClientPrivate),
+			3 => Some(CredentialType::Psk),
+			4 => Some(CredentialType::PskIdentity),
+			5 => Some(CredentialType::PublicKey),
+			6 => Some(CredentialType::DeviceIdentityPublicKey),
+			_ => None,
+		}
+	}
+}
+
+/// Delete each credential type in `credentials` stored under `tag`, then
+/// write the new value if there is one.
+fn write_credentials(
+	tag: SecurityTag,
+	credentials: &[(CredentialType, Option<&str>)],
 ) -> Result<(), Error> {
 	let mut at_socket = crate::at::AtSocket::new()?;
-	for (key, var) in &[
-		(CredentialType::RootCA, ca_chain),
-		(CredentialType::ClientCert, public_cert),
-		(CredentialType::ClientPrivate, key),
-	] {
+	for (key, var) in credentials {
 		write!(
 			at_socket,
 			"AT%CMNG={},{},{}\r\n",
 			CredentialOpcode::Delete,
 			tag,
 			key
-		)?;
+		)
+		.map_err(|_| {
+			at_socket
+				.take_last_write_error()
+				.unwrap_or(Error::WriteError)
+		})?;
 		match at_socket.poll_response(|_| {}) {
 			Ok(_) => {}
-			Err(Error::AtError(AtError::CmeError(513))) => {
-				// 513 is NOT FOUND. We can ignore this
+			Err(Error::AtError(AtError::CmeError(CmeErrorCode::NotFound))) => {
+				// We can ignore this
 			}
 			Err(e) => {
 				return Err(e);
@@ -287,7 +580,12 @@ pub fn provision_certificates(
 				tag,
 				key,
 				string
-			)?;
+			)
+			.map_err(|_| {
+				at_socket
+					.take_last_write_error()
+					.unwrap_or(Error::WriteError)
+			})?;
 			at_socket.poll_response(|_| {})?;
 		}
 	}
@@ -295,23 +593,150 @@ pub fn provision_certificates(
 	Ok(())
 }
 
-impl core::fmt::Display for CredentialOpcode {
-	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
-		write!(f, "{}", *self as i32)
-	}
-}
-
-impl core::fmt::Display for CredentialType {
-	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
-		write!(f, "{}", *self as i32)
+/// Check a credential can be sent inside the quotes of an `AT%CMNG` command.
+///
+/// The modem doesn't support any escaping in `AT%CMNG`, so we only allow
+/// printable ASCII (excluding `"`) and line endings, which is everything a
+/// PEM file should contain.
+fn validate_credential(credential: &str) -> Result<(), Error> {
+	let valid = credential
+		.bytes()
+		.all(|b| b == b'\r' || b == b'\n' || ((b' '..=b'~').contains(&b) && b != b'"'));
+	if valid {
+		Ok(())
+	} else {
+		Err(Error::InvalidCredential)
 	}
 }
 
 //******************************************************************************
-// Private Functions and Impl on Private Types
+// Tests
 //******************************************************************************
 
-// None
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::backend::mock;
+	use core::fmt::Write;
+
+	/// The `AT%CMNG=1` response for `count` certificates
+	fn cmng_list(count: u32) -> heapless::String<4096> {
+		let mut response = heapless::String::new();
+		for tag in 0..count {
+			write!(
+				response,
+				"%CMNG: {},0,\"{:064X}\"\r\n",
+				1_000_000 + tag,
+				tag
+			)
+			.unwrap();
+		}
+		response.push_str("OK\r\n").unwrap();
+		response
+	}
+
+	#[test]
+	fn sixteen_credentials_are_listed() {
+		mock::run(|modem| {
+			let response = cmng_list(16);
+			assert!(response.len() > crate::at::AT_BUFFER_LENGTH);
+			modem.reply("AT%CMNG=1", &response);
+			let list = list_credentials().unwrap();
+			assert_eq!(list.len(), 16);
+			assert_eq!(list[15], (1_000_015, CredentialType::RootCA));
+		});
+	}
+
+	#[test]
+	fn overlong_credential_list_is_an_error() {
+		mock::run(|modem| {
+			modem.reply("AT%CMNG=1", &cmng_list(40));
+			assert!(matches!(list_credentials(), Err(Error::Truncated(_, _))));
+		});
+	}
+
+	#[test]
+	fn options_survive_a_refused_connection() {
+		mock::run(|modem| {
+			modem.resolve(&[[192, 0, 2, 1], [192, 0, 2, 2]]);
+			modem.refuse(1);
+			let tag = SecurityTag::new(42).unwrap();
+			let mut skt =
+				TlsSocket::new(PeerVerification::Enabled, &[tag], Version::Tls1v2).unwrap();
+			skt.set_cipher_suites(&[TLS_PSK_WITH_AES_128_CBC_SHA256])
+				.unwrap();
+			skt.connect("example.com", 443).unwrap();
+			let connections = modem.connections();
+			assert_eq!(connections.len(), 2);
+			assert_ne!(connections[0], connections[1]);
+			let secure = |name: u32| (sys::NRF_SOL_SECURE as i32, name as i32);
+			let options = modem.options(connections[1]);
+			for name in [
+				sys::NRF_SO_SEC_PEER_VERIFY,
+				sys::NRF_SO_SEC_SESSION_CACHE,
+				sys::NRF_SO_SEC_TAG_LIST,
+				sys::NRF_SO_CIPHERSUITE_LIST,
+				sys::NRF_SO_HOSTNAME,
+			] {
+				assert!(options.contains(&secure(name)));
+			}
+		});
+	}
+
+	/// A real self-signed certificate, with a mix of line endings.
+	const CA_CERT: &str = "\
+		-----BEGIN CERTIFICATE-----\r\n\
+		MIIBiTCCAS+gAwIBAgIUZK8g86kOAfrsPtXSDLn1vhtV4ZAwCgYIKoZIzj0EAwIw\r\n\
+		GjEYMBYGA1UEAwwPbnJmeGxpYiB0ZXN0IENBMB4XDTI2MTAxNjE4NTM0NloXDTM2\r\n\
+		MTAxMzE4NTM0NlowGjEYMBYGA1UEAwwPbnJmeGxpYiB0ZXN0IENBMFkwEwYHKoZI\r\n\
+		zj0CAQYIKoZIzj0DAQcDQgAE/WloIvh/oZQGZcEHuCi95K/MS68ExDrWN6CKNG2e\r\n\
+		/pMPjXYsQL7LnbTQkoKcvcMIIBLvt/gByJ0mMGqN2+Hm0KNTMFEwHQYDVR0OBBYE\n\
+		FBAXMulHhVpJbikASC1WEID7SgADMB8GA1UdIwQYMBaAFBAXMulHhVpJbikASC1W\n\
+		EID7SgADMA8GA1UdEwEB/wQFMAMBAf8wCgYIKoZIzj0EAwIDSAAwRQIhAI1/NUbT\n\
+		g4YB/Y4Kni0lSSAf1FST85Qn0ezIe5wXqRGWAiAPVtg6i2h1NSAO70yyLg73lOTk\n\
+		SgbIO14QKpe8nUi5EA==\n\
+		-----END CERTIFICATE-----\n";
+
+	#[test]
+	fn pem_certificate_is_written() {
+		mock::run(|modem| {
+			let mut write = heapless::String::<1024>::new();
+			write!(write, "AT%CMNG=0,42,0,\"{}\"\r\n", CA_CERT).unwrap();
+			let commands = [
+				"AT%CMNG=3,42,0\r\n",
+				write.as_str(),
+				"AT%CMNG=3,42,1\r\n",
+				"AT%CMNG=3,42,2\r\n",
+			];
+			for command in commands.iter() {
+				modem.reply(command, "OK\r\n");
+			}
+			let tag = SecurityTag::new(42).unwrap();
+			provision_certificates(tag, Some(CA_CERT), None, None).unwrap();
+			assert_eq!(modem.commands(), commands);
+		});
+	}
+
+	#[test]
+	fn quote_is_rejected() {
+		assert_eq!(
+			validate_credential("abc\",1,2\r\n"),
+			Err(Error::InvalidCredential)
+		);
+	}
+
+	#[test]
+	fn non_ascii_and_control_characters_are_rejected() {
+		assert_eq!(
+			validate_credential("caf\u{e9}"),
+			Err(Error::InvalidCredential)
+		);
+		assert_eq!(
+			validate_credential("tab\there"),
+			Err(Error::InvalidCredential)
+		);
+	}
+}
 
 //******************************************************************************
 // End of File